@@ -0,0 +1,95 @@
+//! Bullet hell in the headless renderer, `cargo bench --bench sprite_batch`
+
+use std::time::{Duration, Instant};
+
+use ld_game_engine::{
+    batch::SpriteBatch,
+    render::{DrawCommand, Pixmap},
+    sprite::{DrawParams, Sprite, Spritesheet},
+    surface::Surface,
+    util::Mut,
+};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const BULLETS: usize = 2000;
+const FRAMES: u32 = 50;
+
+// spread over a 3x3 screens area so most of them are off-screen
+fn bullets() -> Vec<(f64, f64, f64)> {
+    let mut seed = 12345u32;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 8) as f64 / (1 << 24) as f64
+    };
+    (0..BULLETS)
+        .map(|_| {
+            let x = (next() * 3.0 - 1.0) * WIDTH as f64;
+            let y = (next() * 3.0 - 1.0) * HEIGHT as f64;
+            (x, y, next() * std::f64::consts::TAU)
+        })
+        .collect()
+}
+
+fn bench(surface: &Mut<Surface>, name: &str, mut frame: impl FnMut()) {
+    frame();
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        frame();
+    }
+    let elapsed: Duration = start.elapsed() / FRAMES;
+
+    // one more frame outside of the timing to see how much actually reaches the renderer
+    surface.borrow().start_recording();
+    frame();
+    let recording = surface.borrow().stop_recording().unwrap_or_default();
+    let draws = recording
+        .commands
+        .iter()
+        .filter(|c| matches!(c, DrawCommand::DrawImage { .. }))
+        .count();
+    println!(
+        "{:<24} {:>8.3} ms/frame {:>6} draw_image calls",
+        name,
+        elapsed.as_secs_f64() * 1e3,
+        draws
+    );
+}
+
+fn main() {
+    let surface = Mut::new(Surface::headless(WIDTH, HEIGHT));
+    let mut pixmap = Pixmap::new(16, 16);
+    pixmap.fill([255, 200, 0, 255]);
+    let sheet = Spritesheet::from_pixmap(surface.clone(), pixmap);
+    let sprites: Vec<Sprite> = (0..4).map(|i| sheet.create_sprite(i % 2 * 8, i / 2 * 8, 8, 8)).collect();
+    let bullets = bullets();
+
+    println!("{} bullets, {}x{}", BULLETS, WIDTH, HEIGHT);
+
+    bench(&surface, "Sprite::draw", || {
+        for (i, &(x, y, _)) in bullets.iter().enumerate() {
+            sprites[i % 4].draw(x, y);
+        }
+    });
+    bench(&surface, "Sprite::draw_with", || {
+        for (i, &(x, y, angle)) in bullets.iter().enumerate() {
+            sprites[i % 4].draw_with(x, y, &DrawParams::new().centered().with_rotation(angle));
+        }
+    });
+
+    let mut batch = SpriteBatch::new();
+    bench(&surface, "SpriteBatch::add", || {
+        for (i, &(x, y, _)) in bullets.iter().enumerate() {
+            batch.add(&sprites[i % 4], x, y);
+        }
+        batch.flush();
+    });
+    bench(&surface, "SpriteBatch::add_with", || {
+        for (i, &(x, y, angle)) in bullets.iter().enumerate() {
+            batch.add_with(&sprites[i % 4], x, y, &DrawParams::new().centered().with_rotation(angle));
+        }
+        batch.flush();
+    });
+    let stats = batch.stats();
+    println!("last flush: {} drawn, {} culled", stats.drawn, stats.culled);
+}
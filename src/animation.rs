@@ -0,0 +1,348 @@
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    sprite::{DrawParams, Sprite, Spritesheet, Tileset},
+    Context, Game,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayMode {
+    Loop,
+    /// Forwards and then backwards, without repeating the end frames
+    PingPong,
+    /// Stops at the last frame
+    Once,
+}
+
+#[derive(Clone)]
+pub struct Frame {
+    pub sprite: Sprite,
+    /// In seconds
+    pub duration: f64,
+}
+
+#[derive(Clone)]
+pub struct Animation {
+    frames: Vec<Frame>,
+    mode: PlayMode,
+    events: Vec<(usize, String)>,
+}
+
+impl Animation {
+    /// `None` if there are no frames
+    pub fn new(frames: Vec<Frame>) -> Option<Self> {
+        if frames.is_empty() {
+            return None;
+        }
+        Some(Self {
+            frames,
+            mode: PlayMode::Loop,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn from_sprites(sprites: impl IntoIterator<Item = Sprite>, frame_duration: f64) -> Option<Self> {
+        Self::new(
+            sprites
+                .into_iter()
+                .map(|sprite| Frame {
+                    sprite,
+                    duration: frame_duration,
+                })
+                .collect(),
+        )
+    }
+
+    /// `count` frames of the `(w, h)` size going right from the `(u, v)` start
+    /// and wrapping to the next row after `columns` of them
+    pub fn from_grid(
+        spritesheet: &Spritesheet,
+        (u, v): (u32, u32),
+        (w, h): (u32, u32),
+        columns: u32,
+        count: u32,
+        frame_duration: f64,
+    ) -> Option<Self> {
+        let columns = columns.max(1);
+        Self::from_sprites(
+            (0..count).map(|i| spritesheet.create_sprite(u + i % columns * w, v + i / columns * h, w, h)),
+            frame_duration,
+        )
+    }
+
+    /// The cells by their indices, like `0..4` or `[0, 1, 2, 1]`, `None` if there
+    /// are none, some are out of bounds or the columns are not known yet
+    pub fn from_tileset(tileset: &Tileset, cells: impl IntoIterator<Item = usize>, frame_duration: f64) -> Option<Self> {
+        let sprites = cells.into_iter().map(|i| tileset.get(i)).collect::<Option<Vec<_>>>()?;
+        Self::from_sprites(sprites, frame_duration)
+    }
+
+    pub fn with_mode(mut self, mode: PlayMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Out of bounds frames are ignored with a warning
+    pub fn with_frame_duration(mut self, frame: usize, duration: f64) -> Self {
+        match self.frames.get_mut(frame) {
+            Some(f) => f.duration = duration,
+            None => log::warn!("Frame {} is out of bounds, the animation has {}", frame, self.frames.len()),
+        }
+        self
+    }
+
+    /// The player reports the event every time the frame is shown,
+    /// out of bounds frames are ignored with a warning
+    pub fn with_event(mut self, frame: usize, event: impl Into<String>) -> Self {
+        let event = event.into();
+        if frame < self.frames.len() {
+            self.events.push((frame, event));
+        } else {
+            log::warn!("Event {} is on frame {} which is out of bounds", event, frame);
+        }
+        self
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn mode(&self) -> PlayMode {
+        self.mode
+    }
+
+    /// Of one pass through the frames
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|f| f.duration).sum()
+    }
+}
+
+/// Plays an [Animation], they are shared so many players can use the same one
+#[derive(Clone)]
+pub struct AnimationPlayer {
+    animation: Rc<Animation>,
+    frame: usize,
+    time: f64,
+    backwards: bool,
+    playing: bool,
+    finished: bool,
+    shown: bool,
+    pub speed: f64,
+}
+
+impl AnimationPlayer {
+    pub fn new(animation: impl Into<Rc<Animation>>) -> Self {
+        Self {
+            animation: animation.into(),
+            frame: 0,
+            time: 0.0,
+            backwards: false,
+            playing: true,
+            finished: false,
+            shown: false,
+            speed: 1.0,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn animation(&self) -> &Rc<Animation> {
+        &self.animation
+    }
+
+    /// Restarts with the other animation, unless it's the same one
+    pub fn set_animation(&mut self, animation: &Rc<Animation>) {
+        if !Rc::ptr_eq(&self.animation, animation) {
+            self.animation = animation.clone();
+            self.restart();
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn restart(&mut self) {
+        self.frame = 0;
+        self.time = 0.0;
+        self.backwards = false;
+        self.finished = false;
+        self.shown = false;
+        self.playing = true;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing && !self.finished
+    }
+
+    /// Only `Once` animations finish
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn sprite(&self) -> &Sprite {
+        &self.animation.frames[self.frame].sprite
+    }
+
+    pub fn on_update<G: Game>(&mut self, context: &Context<G>) -> Vec<&str> {
+        self.update(context.delta_time())
+    }
+
+    /// Advances by the time in seconds, returns the events of the frames that were shown
+    pub fn update(&mut self, delta_time: f64) -> Vec<&str> {
+        let mut entered = Vec::new();
+        if !self.is_playing() {
+            return Vec::new();
+        }
+        if !self.shown {
+            self.shown = true;
+            entered.push(self.frame);
+        }
+        self.time += delta_time * self.speed;
+        // zero length animations would loop forever
+        while self.animation.duration() > 0.0 {
+            let duration = self.animation.frames[self.frame].duration;
+            if self.time < duration {
+                break;
+            }
+            self.time -= duration;
+            if !self.advance() {
+                self.time = 0.0;
+                break;
+            }
+            entered.push(self.frame);
+        }
+        let animation = &*self.animation;
+        entered
+            .into_iter()
+            .flat_map(|frame| animation.events.iter().filter(move |(f, _)| *f == frame))
+            .map(|(_, event)| event.as_str())
+            .collect()
+    }
+
+    // false if it's finished
+    fn advance(&mut self) -> bool {
+        let last = self.animation.frames.len() - 1;
+        match self.animation.mode {
+            PlayMode::Loop => self.frame = if self.frame == last { 0 } else { self.frame + 1 },
+            PlayMode::Once if self.frame == last => {
+                self.finished = true;
+                return false;
+            }
+            PlayMode::Once => self.frame += 1,
+            PlayMode::PingPong if last == 0 => {}
+            PlayMode::PingPong => {
+                if self.frame == last {
+                    self.backwards = true;
+                } else if self.frame == 0 {
+                    self.backwards = false;
+                }
+                self.frame = if self.backwards { self.frame - 1 } else { self.frame + 1 };
+            }
+        }
+        true
+    }
+
+    pub fn draw(&self, x: f64, y: f64) {
+        self.sprite().draw(x, y);
+    }
+
+    pub fn draw_with(&self, x: f64, y: f64, params: &DrawParams) {
+        self.sprite().draw_with(x, y, params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render::Pixmap, surface::Surface, util::Mut};
+
+    fn animation(mode: PlayMode) -> Animation {
+        let surface = Mut::new(Surface::headless(8, 8));
+        let sheet = Spritesheet::from_pixmap(surface, Pixmap::new(3, 1));
+        Animation::from_grid(&sheet, (0, 0), (1, 1), 3, 3, 1.0).unwrap().with_mode(mode)
+    }
+
+    fn frames(player: &mut AnimationPlayer, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                player.update(1.0);
+                player.frame()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_and_bad_indices() {
+        let surface = Mut::new(Surface::headless(8, 8));
+        let sheet = Spritesheet::from_pixmap(surface, Pixmap::new(3, 1));
+        assert!(Animation::from_grid(&sheet, (0, 0), (1, 1), 3, 0, 1.0).is_none());
+        assert!(Animation::from_sprites(Vec::new(), 1.0).is_none());
+
+        let animation = animation(PlayMode::Loop).with_frame_duration(3, 5.0).with_event(7, "nope");
+        assert_eq!(animation.duration(), 3.0);
+        let mut player = AnimationPlayer::new(animation);
+        assert!(frames(&mut player, 6).iter().all(|&f| f < 3));
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let mut player = AnimationPlayer::new(animation(PlayMode::Loop));
+        assert_eq!(player.frame(), 0);
+        assert_eq!(frames(&mut player, 5), [1, 2, 0, 1, 2]);
+        assert!(player.is_playing());
+        // several frames in one step
+        player.update(4.0);
+        assert_eq!(player.frame(), 0);
+    }
+
+    #[test]
+    fn ping_pong_reverses_at_both_ends() {
+        let mut player = AnimationPlayer::new(animation(PlayMode::PingPong));
+        assert_eq!(frames(&mut player, 8), [1, 2, 1, 0, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut player = AnimationPlayer::new(animation(PlayMode::Once).with_event(2, "end"));
+        assert_eq!(player.update(10.0), ["end"]);
+        assert_eq!(player.frame(), 2);
+        assert!(player.is_finished());
+        assert!(!player.is_playing());
+        assert!(player.update(1.0).is_empty());
+        assert_eq!(player.frame(), 2);
+
+        player.restart();
+        assert!(player.is_playing());
+        assert_eq!(frames(&mut player, 1), [1]);
+    }
+
+    #[test]
+    fn events_fire_once_per_frame_shown() {
+        let animation = animation(PlayMode::Loop).with_event(0, "start").with_event(1, "step");
+        let mut player = AnimationPlayer::new(animation);
+        assert_eq!(player.update(0.5), ["start"]);
+        assert!(player.update(0.25).is_empty());
+        assert_eq!(player.update(0.25), ["step"]);
+        assert!(player.update(1.0).is_empty());
+        // frames 0 and 1 shown again in a single step
+        assert_eq!(player.update(2.0), ["start", "step"]);
+
+        player.pause();
+        assert!(player.update(5.0).is_empty());
+        assert_eq!(player.frame(), 1);
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    rc::Rc,
+};
+
+use serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use wasm_bindgen_futures::spawn_local;
+
+use crate::{
+    animation::{Animation, Frame, PlayMode},
+    nine_slice::NineSlice,
+    render::Rect,
+    sprite::{Sprite, Spritesheet},
+    surface::Surface,
+    util::{base_url, fetch_text, Mut},
+    V2, v2,
+};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct JsonRect {
+    #[serde(default)]
+    x: f64,
+    #[serde(default)]
+    y: f64,
+    w: f64,
+    h: f64,
+}
+
+impl From<JsonRect> for Rect {
+    fn from(r: JsonRect) -> Self {
+        Rect::new(r.x, r.y, r.w, r.h)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFrame {
+    #[serde(default)]
+    filename: String,
+    frame: JsonRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: Option<JsonRect>,
+    source_size: Option<JsonRect>,
+    /// In milliseconds, only Aseprite has it
+    duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct JsonTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    repeat: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct JsonPoint {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct JsonSliceKey {
+    bounds: JsonRect,
+    center: Option<JsonRect>,
+    pivot: Option<JsonPoint>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct JsonSlice {
+    name: String,
+    keys: Vec<JsonSliceKey>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonMeta {
+    image: Option<String>,
+    #[serde(default)]
+    frame_tags: Vec<JsonTag>,
+    #[serde(default)]
+    slices: Vec<JsonSlice>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct JsonAtlas {
+    #[serde(deserialize_with = "frames_in_order")]
+    frames: Vec<JsonFrame>,
+    #[serde(default)]
+    meta: JsonMeta,
+}
+
+// the "hash" exports are objects and the tags refer to frames by their
+// position in them, so they have to keep the order of the file
+fn frames_in_order<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<JsonFrame>, D::Error> {
+    struct FramesVisitor;
+
+    impl<'de> Visitor<'de> for FramesVisitor {
+        type Value = Vec<JsonFrame>;
+
+        fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+            f.write_str("an array or an object of frames")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut frames = Vec::new();
+            while let Some(frame) = seq.next_element()? {
+                frames.push(frame);
+            }
+            Ok(frames)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut frames = Vec::new();
+            while let Some((filename, mut frame)) = map.next_entry::<String, JsonFrame>()? {
+                frame.filename = filename;
+                frames.push(frame);
+            }
+            Ok(frames)
+        }
+    }
+
+    deserializer.deserialize_any(FramesVisitor)
+}
+
+/// A named region from an Aseprite export, in the coordinates of the frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slice {
+    pub bounds: Rect,
+    /// The middle part of nine-slices, relative to the bounds
+    pub center: Option<Rect>,
+    pub pivot: Option<V2>,
+}
+
+struct Loaded {
+    spritesheet: Spritesheet,
+    frames: Vec<(String, Sprite)>,
+    animations: HashMap<String, Rc<Animation>>,
+    slices: HashMap<String, Slice>,
+}
+
+/// Sprites and animations packed into one image, from the JSON that
+/// Aseprite or TexturePacker (both hash and array) export with it.
+///
+/// Frame tags become animations, trimmed frames are drawn at their offsets.
+/// Frames without durations are 100ms long, same as the Aseprite default.
+#[derive(Clone)]
+pub struct Atlas {
+    inner: Mut<Option<Rc<Loaded>>>,
+}
+
+impl Debug for Atlas {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &*self.inner.borrow() {
+            Some(loaded) => f
+                .debug_struct("Atlas")
+                .field("frames", &loaded.frames.iter().map(|(n, _)| n).collect::<Vec<_>>())
+                .field("animations", &loaded.animations.keys().collect::<Vec<_>>())
+                .finish(),
+            None => write!(f, "Atlas(not loaded)"),
+        }
+    }
+}
+
+impl Atlas {
+    /// With the image that was already loaded, ignoring the one the JSON mentions
+    pub fn parse(spritesheet: Spritesheet, json: &str) -> Option<Self> {
+        let atlas = Self {
+            inner: Mut::new(None),
+        };
+        atlas.set(spritesheet, parse_json(json)?);
+        Some(atlas)
+    }
+
+    /// Loads the JSON and then the image it mentions, relative to it
+    pub(crate) fn load(surface: Mut<Surface>, url: &str) -> Self {
+        let atlas = Self {
+            inner: Mut::new(None),
+        };
+        let moved_atlas = atlas.clone();
+        let url = url.to_owned();
+        spawn_local(async move {
+            let json = match fetch_text(&url).await {
+                Some(text) => parse_json(&text),
+                None => {
+                    log::error!("Failed to load the atlas at {}", url);
+                    return;
+                }
+            };
+            let json = match json {
+                Some(json) => json,
+                None => return log::error!("Failed to parse the atlas at {}", url),
+            };
+            let image = match &json.meta.image {
+                Some(image) => image,
+                None => return log::error!("The atlas at {} has no meta.image", url),
+            };
+            let spritesheet = Spritesheet::load(surface, &format!("{}{}", base_url(&url), image));
+            moved_atlas.set(spritesheet, json);
+        });
+        atlas
+    }
+
+    fn set(&self, spritesheet: Spritesheet, json: JsonAtlas) {
+        let frames: Vec<(String, Sprite, f64)> = json
+            .frames
+            .iter()
+            .map(|frame| {
+                if frame.rotated {
+                    log::warn!("Frame {} is rotated, those are not supported", frame.filename);
+                }
+                let r = frame.frame;
+                let mut sprite = spritesheet.create_sprite(r.x as u32, r.y as u32, r.w as u32, r.h as u32);
+                if let (Some(trim), Some(source)) = (frame.sprite_source_size, frame.source_size) {
+                    sprite = sprite.with_trim(trim.x, trim.y, source.w as u32, source.h as u32);
+                }
+                let duration = frame.duration.unwrap_or(100.0) / 1e3;
+                (frame.filename.clone(), sprite, duration)
+            })
+            .collect();
+
+        let mut animations = HashMap::new();
+        for tag in &json.meta.frame_tags {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                log::warn!("Frame tag {} is out of bounds", tag.name);
+                continue;
+            }
+            let mut tag_frames: Vec<Frame> = frames[tag.from..=tag.to]
+                .iter()
+                .map(|(_, sprite, duration)| Frame {
+                    sprite: sprite.clone(),
+                    duration: *duration,
+                })
+                .collect();
+            if tag.direction.ends_with("reverse") {
+                tag_frames.reverse();
+            }
+            let mode = if tag.direction.starts_with("pingpong") {
+                PlayMode::PingPong
+            } else if tag.repeat.as_deref() == Some("1") {
+                PlayMode::Once
+            } else {
+                PlayMode::Loop
+            };
+            if let Some(animation) = Animation::new(tag_frames) {
+                animations.insert(tag.name.clone(), Rc::new(animation.with_mode(mode)));
+            }
+        }
+
+        let slices = json
+            .meta
+            .slices
+            .iter()
+            .filter_map(|slice| {
+                let key = slice.keys.first()?;
+                let slice_data = Slice {
+                    bounds: key.bounds.into(),
+                    center: key.center.map(Into::into),
+                    pivot: key.pivot.map(|p| v2![p.x, p.y]),
+                };
+                Some((slice.name.clone(), slice_data))
+            })
+            .collect();
+
+        *self.inner.borrow_mut() = Some(Rc::new(Loaded {
+            spritesheet,
+            frames: frames.into_iter().map(|(name, sprite, _)| (name, sprite)).collect(),
+            animations,
+            slices,
+        }));
+    }
+
+    fn loaded(&self) -> Option<Rc<Loaded>> {
+        self.inner.borrow().clone()
+    }
+
+    /// The JSON and the image are both loaded
+    pub fn is_loaded(&self) -> bool {
+        self.loaded().is_some_and(|l| l.spritesheet.is_loaded())
+    }
+
+    pub fn spritesheet(&self) -> Option<Spritesheet> {
+        Some(self.loaded()?.spritesheet.clone())
+    }
+
+    /// By the frame filename
+    pub fn sprite(&self, name: &str) -> Option<Sprite> {
+        let loaded = self.loaded()?;
+        let (_, sprite) = loaded.frames.iter().find(|(n, _)| n == name)?;
+        Some(sprite.clone())
+    }
+
+    /// By the position in the file
+    pub fn frame(&self, index: usize) -> Option<Sprite> {
+        Some(self.loaded()?.frames.get(index)?.1.clone())
+    }
+
+    pub fn frame_names(&self) -> Vec<String> {
+        self.loaded()
+            .map(|l| l.frames.iter().map(|(n, _)| n.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// By the frame tag name
+    pub fn animation(&self, name: &str) -> Option<Rc<Animation>> {
+        self.loaded()?.animations.get(name).cloned()
+    }
+
+    pub fn slice(&self, name: &str) -> Option<Slice> {
+        self.loaded()?.slices.get(name).copied()
+    }
+
+    /// The slice bounds cut out of the first frame as a sprite
+    pub fn slice_sprite(&self, name: &str) -> Option<Sprite> {
+        let loaded = self.loaded()?;
+        let slice = loaded.slices.get(name)?;
+        let (_, frame) = loaded.frames.first()?;
+        let origin = frame.source_origin();
+        let b = slice.bounds;
+        let (u, v) = ((origin.x + b.x) as u32, (origin.y + b.y) as u32);
+        Some(loaded.spritesheet.create_sprite(u, v, b.w as u32, b.h as u32))
+    }
+
+    /// From a slice that has the nine-slice center set in Aseprite
+    pub fn nine_slice(&self, name: &str) -> Option<NineSlice> {
+        let center = self.slice(name)?.center?;
+        let sprite = self.slice_sprite(name)?;
+        let (w, h) = (sprite.width() as f64, sprite.height() as f64);
+        let right = w - center.x - center.w;
+        let bottom = h - center.y - center.h;
+        Some(NineSlice::new(&sprite, center.x as u32, center.y as u32, right as u32, bottom as u32))
+    }
+}
+
+fn parse_json(json: &str) -> Option<JsonAtlas> {
+    serde_json::from_str(json)
+        .map_err(|e| log::error!("Failed to parse the atlas: {}", e))
+        .ok()
+}
+
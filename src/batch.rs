@@ -0,0 +1,253 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    render::{Image, Layer, Rect, Renderer, Transform},
+    sprite::{tint_rgb, DrawParams, Sprite, Spritesheet},
+    V2, v2,
+};
+
+struct Instance {
+    src: Rect,
+    dst: Rect,
+    transform: Option<Transform>,
+    alpha: f64,
+    // the tinted copy instead of the spritesheet image
+    image: Option<Image>,
+}
+
+struct Group {
+    spritesheet: Spritesheet,
+    instances: Vec<Instance>,
+}
+
+/// What the last flush did, including the ones queued with [SpriteBatch::submit]
+/// once the layers are drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub drawn: usize,
+    pub culled: usize,
+    /// The spritesheets that had something drawn
+    pub spritesheets: usize,
+}
+
+/// Collects a lot of sprite draws and then draws them all at once,
+/// skipping the ones that are outside of the visible part of the renderer
+/// (so the camera is accounted for when flushing with it applied).
+///
+/// The draws are grouped by spritesheet in the order they were first used,
+/// so sprites from different spritesheets don't keep their relative order,
+/// use separate batches for those when it matters.
+#[derive(Default)]
+pub struct SpriteBatch {
+    groups: Vec<Group>,
+    // shared with the copies that submit queues
+    stats: Rc<Cell<BatchStats>>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn group(&mut self, spritesheet: &Spritesheet) -> &mut Vec<Instance> {
+        let index = match self.groups.iter().position(|g| g.spritesheet.same_as(spritesheet)) {
+            Some(index) => index,
+            None => {
+                self.groups.push(Group {
+                    spritesheet: spritesheet.clone(),
+                    instances: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[index].instances
+    }
+
+    /// Same as [Sprite::draw]
+    pub fn add(&mut self, sprite: &Sprite, x: f64, y: f64) {
+        self.group(sprite.spritesheet()).push(Instance {
+            src: sprite.rect(),
+            dst: sprite.dst(x, y),
+            transform: None,
+            alpha: 1.0,
+            image: None,
+        });
+    }
+
+    /// Same as [Sprite::draw_with]
+    pub fn add_with(&mut self, sprite: &Sprite, x: f64, y: f64, params: &DrawParams) {
+        let image = match tint_rgb(params.tint) {
+            None => None,
+            Some(_) => match sprite.image(params.tint) {
+                Some(image) => Some(image),
+                None => return,
+            },
+        };
+        let (transform, dst) = sprite.placement(x, y, params);
+        self.group(sprite.spritesheet()).push(Instance {
+            src: sprite.src(params.tint),
+            dst,
+            transform: Some(transform),
+            alpha: params.alpha * params.tint.a,
+            image,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|g| g.instances.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.iter().all(|g| g.instances.is_empty())
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+    }
+
+    pub fn stats(&self) -> BatchStats {
+        self.stats.get()
+    }
+
+    /// Draws everything onto the surface of the sprites and clears the batch
+    pub fn flush(&mut self) {
+        let renderer = match self.groups.first() {
+            Some(group) => group.spritesheet.surface().renderer(),
+            None => return,
+        };
+        self.flush_into(&*renderer);
+    }
+
+    /// Draws everything with the renderer, like a render target, and clears the batch
+    pub fn flush_into(&mut self, renderer: &dyn Renderer) {
+        let mut stats = BatchStats::default();
+        let view = renderer.visible_rect();
+        let base = renderer.get_transform();
+        let mut alpha = 1.0;
+        let mut transformed = false;
+        renderer.save();
+        renderer.set_global_alpha(alpha);
+        for group in &self.groups {
+            let image = match group.spritesheet.image() {
+                Some(image) => image,
+                None => continue,
+            };
+            let mut drawn = false;
+            for instance in &group.instances {
+                let bounds = match instance.transform {
+                    Some(transform) => transformed_bounds(&transform, instance.dst),
+                    None => instance.dst,
+                };
+                if view.is_some_and(|view| !view.intersects(&bounds)) {
+                    stats.culled += 1;
+                    continue;
+                }
+                if instance.alpha != alpha {
+                    alpha = instance.alpha;
+                    renderer.set_global_alpha(alpha);
+                }
+                match instance.transform {
+                    Some(transform) => {
+                        renderer.set_transform(base.then(&transform));
+                        transformed = true;
+                    }
+                    None if transformed => {
+                        renderer.set_transform(base);
+                        transformed = false;
+                    }
+                    None => {}
+                }
+                renderer.draw_image(instance.image.as_ref().unwrap_or(&image), instance.src, instance.dst);
+                stats.drawn += 1;
+                drawn = true;
+            }
+            if drawn {
+                stats.spritesheets += 1;
+            }
+        }
+        renderer.restore();
+        self.groups.clear();
+        self.stats.set(stats);
+    }
+
+    /// Queues the flush into the layer, see [Surface::submit](crate::surface::Surface::submit)
+    pub fn submit(&mut self, layer: Layer, z: i32) {
+        let surface = match self.groups.first() {
+            Some(group) => group.spritesheet.surface().clone(),
+            None => return,
+        };
+        let mut batch = Self {
+            groups: std::mem::take(&mut self.groups),
+            stats: self.stats.clone(),
+        };
+        surface.submit(layer, z, move |renderer| batch.flush_into(renderer));
+    }
+}
+
+fn transformed_bounds(transform: &Transform, rect: Rect) -> Rect {
+    let corners = [
+        v2![rect.x, rect.y],
+        v2![rect.x + rect.w, rect.y],
+        v2![rect.x, rect.y + rect.h],
+        v2![rect.x + rect.w, rect.y + rect.h],
+    ]
+    .map(|p| transform.apply(p));
+    let min = corners.iter().fold(corners[0], |min: V2, p| min.inf(p));
+    let max = corners.iter().fold(corners[0], |max: V2, p| max.sup(p));
+    Rect::from_corners(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{Pixmap, SoftwareRenderer},
+        surface::Surface,
+        util::Mut,
+    };
+
+    fn sheet() -> (Spritesheet, Mut<Surface>, Rc<SoftwareRenderer>) {
+        let renderer = Rc::new(SoftwareRenderer::new(10, 10));
+        let surface = Mut::new(Surface::with_renderer(renderer.clone()));
+        let mut pixmap = Pixmap::new(2, 2);
+        pixmap.fill([255, 255, 255, 255]);
+        (Spritesheet::from_pixmap(surface.clone(), pixmap), surface, renderer)
+    }
+
+    // two on the screen and two off it, one of each rotated
+    fn fill(batch: &mut SpriteBatch, sprite: &Sprite) {
+        let rotated = DrawParams::new().centered().with_scale(3.0).with_rotation(std::f64::consts::FRAC_PI_4);
+        batch.add(sprite, 1.0, 1.0);
+        batch.add(sprite, 20.0, 20.0);
+        // 6 wide so it ends left of the screen, but the corner reaches in when rotated
+        batch.add_with(sprite, -3.5, 5.0, &rotated);
+        batch.add_with(sprite, -10.0, 5.0, &rotated);
+    }
+
+    #[test]
+    fn culling() {
+        let (sheet, _, renderer) = sheet();
+        let sprite = sheet.create_sprite(0, 0, 2, 2);
+        let mut batch = SpriteBatch::new();
+        fill(&mut batch, &sprite);
+        assert_eq!(batch.len(), 4);
+        batch.flush();
+        assert!(batch.is_empty());
+        assert_eq!(batch.stats(), BatchStats { drawn: 2, culled: 2, spritesheets: 1 });
+        assert_eq!(renderer.snapshot().pixel(2, 2), [255, 255, 255, 255]);
+        assert_eq!(renderer.snapshot().pixel(5, 5), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn submitted_stats() {
+        let (sheet, surface, _) = sheet();
+        let sprite = sheet.create_sprite(0, 0, 2, 2);
+        let mut batch = SpriteBatch::new();
+        fill(&mut batch, &sprite);
+        batch.submit(Layer::World, 0);
+        assert!(batch.is_empty());
+        assert_eq!(batch.stats(), BatchStats::default());
+        surface.borrow().flush_layers();
+        assert_eq!(batch.stats(), BatchStats { drawn: 2, culled: 2, spritesheets: 1 });
+    }
+}
@@ -0,0 +1,191 @@
+use nalgebra::Rotation2;
+use noise::{NoiseFn, Perlin};
+
+use crate::{
+    render::{Renderer, Transform},
+    V2, v2,
+};
+
+/// The 2D camera, positions are in world units and the screen is in logical pixels.
+///
+/// The engine applies the surface camera at the start of every frame and
+/// uses it to compute world positions of pointer events.
+#[derive(Debug, Clone)]
+pub struct Camera2D {
+    pub position: V2,
+    pub zoom: f64,
+    pub rotation: f64,
+    bounds: Option<(V2, V2)>,
+    target: Option<V2>,
+    follow_speed: f64,
+    trauma: f64,
+    shake_offset: f64,
+    shake_angle: f64,
+    shake_decay: f64,
+    shake_time: f64,
+    shake: (V2, f64),
+    noise: Perlin,
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Camera2D {
+    pub fn new() -> Self {
+        Self {
+            position: v2![0.0, 0.0],
+            zoom: 1.0,
+            rotation: 0.0,
+            bounds: None,
+            target: None,
+            follow_speed: 5.0,
+            trauma: 0.0,
+            shake_offset: 16.0,
+            shake_angle: 0.05,
+            shake_decay: 1.5,
+            shake_time: 0.0,
+            shake: (v2![0.0, 0.0], 0.0),
+            noise: Perlin::new(),
+        }
+    }
+
+    pub fn with_position(mut self, position: V2) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_zoom(mut self, zoom: f64) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f64) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// World rectangle (min, max) the view is kept inside of
+    pub fn with_bounds(mut self, min: V2, max: V2) -> Self {
+        self.bounds = Some((min, max));
+        self
+    }
+
+    /// How fast the camera catches up with the follow target, in 1/s,
+    /// infinity makes it snap to the target
+    pub fn with_follow_speed(mut self, follow_speed: f64) -> Self {
+        self.follow_speed = follow_speed;
+        self
+    }
+
+    /// Max shake offset in world units, max shake angle in radians and how
+    /// much trauma is lost per second
+    pub fn with_shake(mut self, offset: f64, angle: f64, decay: f64) -> Self {
+        self.shake_offset = offset;
+        self.shake_angle = angle;
+        self.shake_decay = decay;
+        self
+    }
+
+    pub fn set_bounds(&mut self, bounds: Option<(V2, V2)>) {
+        self.bounds = bounds;
+    }
+
+    pub fn follow(&mut self, target: V2) {
+        self.target = Some(target);
+    }
+
+    pub fn stop_following(&mut self) {
+        self.target = None;
+    }
+
+    /// Adds trauma (clamped to 0..1), the shake is proportional to its square
+    pub fn shake(&mut self, trauma: f64) {
+        self.trauma = (self.trauma + trauma).clamp(0.0, 1.0);
+    }
+
+    pub fn update(&mut self, delta_time: f64, screen_size: V2) {
+        if let Some(target) = self.target {
+            let t = 1.0 - (-self.follow_speed * delta_time).exp();
+            self.position += (target - self.position) * if t.is_nan() { 1.0 } else { t };
+        }
+
+        if let Some((min, max)) = self.bounds {
+            let half = self.view_half_extents(screen_size);
+            let clamp = |pos: f64, min: f64, max: f64, half: f64| {
+                if max - min <= half * 2.0 {
+                    (min + max) / 2.0
+                } else {
+                    pos.clamp(min + half, max - half)
+                }
+            };
+            self.position = v2![
+                clamp(self.position.x, min.x, max.x, half.x),
+                clamp(self.position.y, min.y, max.y, half.y)
+            ];
+        }
+
+        self.trauma = (self.trauma - self.shake_decay * delta_time).max(0.0);
+        self.shake_time += delta_time;
+        self.shake = if self.trauma > 0.0 {
+            let perlin = &self.noise;
+            let amount = self.trauma * self.trauma;
+            let t = self.shake_time * 20.0;
+            (
+                v2![perlin.get([t, 0.5]), perlin.get([t, 10.5])] * amount * self.shake_offset,
+                perlin.get([t, 20.5]) * amount * self.shake_angle,
+            )
+        } else {
+            (v2![0.0, 0.0], 0.0)
+        };
+    }
+
+    fn eye(&self) -> (V2, f64) {
+        (self.position + self.shake.0, self.rotation + self.shake.1)
+    }
+
+    // half of the axis-aligned size of the rotated view
+    fn view_half_extents(&self, screen_size: V2) -> V2 {
+        let half = screen_size / (2.0 * self.zoom);
+        let (sin, cos) = self.rotation.sin_cos();
+        v2![
+            half.x * cos.abs() + half.y * sin.abs(),
+            half.x * sin.abs() + half.y * cos.abs()
+        ]
+    }
+
+    pub fn world_to_screen(&self, world: V2, screen_size: V2) -> V2 {
+        let (eye, rotation) = self.eye();
+        Rotation2::new(-rotation) * (world - eye) * self.zoom + screen_size / 2.0
+    }
+
+    pub fn screen_to_world(&self, screen: V2, screen_size: V2) -> V2 {
+        let (eye, rotation) = self.eye();
+        Rotation2::new(rotation) * ((screen - screen_size / 2.0) / self.zoom) + eye
+    }
+
+    /// Axis-aligned world rectangle (min, max) that is visible on the screen
+    pub fn visible_rect(&self, screen_size: V2) -> (V2, V2) {
+        let center = self.eye().0;
+        let half = self.view_half_extents(screen_size);
+        (center - half, center + half)
+    }
+
+    /// Multiplies the current renderer transform by the world-to-screen one
+    pub fn apply(&self, renderer: &dyn Renderer, screen_size: V2) {
+        let (eye, rotation) = self.eye();
+        let (sin, cos) = rotation.sin_cos();
+        let (a, b, c, d) = (self.zoom * cos, -self.zoom * sin, self.zoom * sin, self.zoom * cos);
+        let center = screen_size / 2.0;
+        renderer.transform(Transform::new(
+            a,
+            b,
+            c,
+            d,
+            center.x - (a * eye.x + c * eye.y),
+            center.y - (b * eye.x + d * eye.y),
+        ));
+    }
+}
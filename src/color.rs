@@ -0,0 +1,482 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use wasm_bindgen_futures::spawn_local;
+
+use crate::util::{fetch_text, Mut};
+
+/// A straight (not premultiplied) RGBA color, the channels are in 0..1.
+///
+/// It serializes as a css color string and deserializes from any
+/// color [Color::parse] understands.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+impl Color {
+    pub const TRANSPARENT: Color = Color::new(0.0, 0.0, 0.0, 0.0);
+    pub const BLACK: Color = Color::new(0.0, 0.0, 0.0, 1.0);
+    pub const WHITE: Color = Color::new(1.0, 1.0, 1.0, 1.0);
+    pub const GRAY: Color = Color::new(0.5, 0.5, 0.5, 1.0);
+    pub const RED: Color = Color::new(1.0, 0.0, 0.0, 1.0);
+    pub const GREEN: Color = Color::new(0.0, 1.0, 0.0, 1.0);
+    pub const BLUE: Color = Color::new(0.0, 0.0, 1.0, 1.0);
+    pub const YELLOW: Color = Color::new(1.0, 1.0, 0.0, 1.0);
+    pub const CYAN: Color = Color::new(0.0, 1.0, 1.0, 1.0);
+    pub const MAGENTA: Color = Color::new(1.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(r: f64, g: f64, b: f64, a: f64) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::rgba(r, g, b, 1.0)
+    }
+
+    pub fn rgba(r: u8, g: u8, b: u8, a: f64) -> Self {
+        Self::new(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0, a)
+    }
+
+    pub fn from_rgba8([r, g, b, a]: [u8; 4]) -> Self {
+        Self::rgba(r, g, b, a as f64 / 255.0)
+    }
+
+    pub fn to_rgba8(&self) -> [u8; 4] {
+        let byte = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [byte(self.r), byte(self.g), byte(self.b), byte(self.a)]
+    }
+
+    pub fn to_array(&self) -> [f64; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    /// `0xRRGGBB`, opaque
+    pub fn from_hex(hex: u32) -> Self {
+        Self::rgb((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+    }
+
+    /// Hue in degrees, saturation and lightness in 0..1
+    pub fn hsl(h: f64, s: f64, l: f64) -> Self {
+        Self::hsla(h, s, l, 1.0)
+    }
+
+    pub fn hsla(h: f64, s: f64, l: f64, a: f64) -> Self {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        Self::from_hue(h, c, l - c / 2.0, a)
+    }
+
+    /// Hue in degrees, saturation and value in 0..1
+    pub fn hsv(h: f64, s: f64, v: f64) -> Self {
+        Self::hsva(h, s, v, 1.0)
+    }
+
+    pub fn hsva(h: f64, s: f64, v: f64, a: f64) -> Self {
+        let c = v * s;
+        Self::from_hue(h, c, v - c, a)
+    }
+
+    // the common part of hsl and hsv, `c` is the chroma and `m` is the minimum
+    fn from_hue(h: f64, c: f64, m: f64, a: f64) -> Self {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        Self::new(r + m, g + m, b + m, a)
+    }
+
+    // hue in degrees, max and min channels
+    fn hue(&self) -> (f64, f64, f64) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let d = max - min;
+        let h = if d <= 0.0 {
+            0.0
+        } else if max == self.r {
+            60.0 * ((self.g - self.b) / d).rem_euclid(6.0)
+        } else if max == self.g {
+            60.0 * ((self.b - self.r) / d + 2.0)
+        } else {
+            60.0 * ((self.r - self.g) / d + 4.0)
+        };
+        (h, max, min)
+    }
+
+    /// (hue in degrees, saturation, lightness)
+    pub fn to_hsl(&self) -> (f64, f64, f64) {
+        let (h, max, min) = self.hue();
+        let l = (max + min) / 2.0;
+        let s = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+        };
+        (h, s, l)
+    }
+
+    /// (hue in degrees, saturation, value)
+    pub fn to_hsv(&self) -> (f64, f64, f64) {
+        let (h, max, min) = self.hue();
+        (h, if max <= 0.0 { 0.0 } else { (max - min) / max }, max)
+    }
+
+    pub fn with_alpha(self, a: f64) -> Self {
+        Self { a, ..self }
+    }
+
+    /// Multiplies the alpha
+    pub fn fade(self, amount: f64) -> Self {
+        self.with_alpha(self.a * amount)
+    }
+
+    /// Adds to the HSL lightness
+    pub fn lighten(self, amount: f64) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Self::hsla(h, s, (l + amount).clamp(0.0, 1.0), self.a)
+    }
+
+    pub fn darken(self, amount: f64) -> Self {
+        self.lighten(-amount)
+    }
+
+    pub fn lerp(self, other: Color, t: f64) -> Self {
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+            mix(self.a, other.a),
+        )
+    }
+
+    pub fn premultiply(self) -> Self {
+        Self::new(self.r * self.a, self.g * self.a, self.b * self.a, self.a)
+    }
+
+    pub fn unpremultiply(self) -> Self {
+        if self.a <= 0.0 {
+            return Self::TRANSPARENT;
+        }
+        Self::new(self.r / self.a, self.g / self.a, self.b / self.a, self.a)
+    }
+
+    /// `#rrggbb`, or `#rrggbbaa` when not opaque
+    pub fn to_hex(&self) -> String {
+        let [r, g, b, a] = self.to_rgba8();
+        if a == 255 {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+
+    /// The css string for the canvas
+    pub fn to_css(&self) -> String {
+        let [r, g, b, _] = self.to_rgba8();
+        if self.a >= 1.0 {
+            format!("rgb({}, {}, {})", r, g, b)
+        } else {
+            format!("rgba({}, {}, {}, {})", r, g, b, self.a.max(0.0))
+        }
+    }
+
+    /// Parses hex (`#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`),
+    /// `rgb()`/`rgba()`, `hsl()`/`hsla()` and a few basic named colors
+    pub fn parse(color: &str) -> Option<Self> {
+        let color = color.trim().to_ascii_lowercase();
+        if let Some(args) = color
+            .strip_prefix("rgba(")
+            .or_else(|| color.strip_prefix("rgb("))
+            .and_then(|s| s.strip_suffix(')'))
+        {
+            return match parse_args(args)?[..] {
+                [r, g, b] => Some(Self::new(r / 255.0, g / 255.0, b / 255.0, 1.0)),
+                [r, g, b, a] => Some(Self::new(r / 255.0, g / 255.0, b / 255.0, a)),
+                _ => None,
+            };
+        }
+        if let Some(args) = color
+            .strip_prefix("hsla(")
+            .or_else(|| color.strip_prefix("hsl("))
+            .and_then(|s| s.strip_suffix(')'))
+        {
+            return match parse_args(&args.replace("deg", "").replace('%', ""))?[..] {
+                [h, s, l] => Some(Self::hsl(h, s / 100.0, l / 100.0)),
+                [h, s, l, a] => Some(Self::hsla(h, s / 100.0, l / 100.0, a)),
+                _ => None,
+            };
+        }
+        let named = match color.as_str() {
+            "transparent" => return Some(Self::TRANSPARENT),
+            "black" => 0x000000,
+            "white" => 0xffffff,
+            "red" => 0xff0000,
+            "lime" => 0x00ff00,
+            "green" => 0x008000,
+            "blue" => 0x0000ff,
+            "yellow" => 0xffff00,
+            "cyan" | "aqua" => 0x00ffff,
+            "magenta" | "fuchsia" => 0xff00ff,
+            "orange" => 0xffa500,
+            "purple" => 0x800080,
+            "gray" | "grey" => 0x808080,
+            _ => return parse_hex(color.strip_prefix('#')?),
+        };
+        Some(Self::from_hex(named))
+    }
+}
+
+fn parse_args(args: &str) -> Option<Vec<f64>> {
+    args.split([',', '/', ' '])
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect()
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    let digits: Vec<f64> = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as f64))
+        .collect::<Option<_>>()?;
+    match digits.len() {
+        3 | 4 => Some(Color::new(
+            digits[0] / 15.0,
+            digits[1] / 15.0,
+            digits[2] / 15.0,
+            digits.get(3).map_or(1.0, |a| a / 15.0),
+        )),
+        6 | 8 => {
+            let byte = |i: usize| (digits[i] * 16.0 + digits[i + 1]) / 255.0;
+            Some(Color::new(
+                byte(0),
+                byte(2),
+                byte(4),
+                if digits.len() == 8 { byte(6) } else { 1.0 },
+            ))
+        }
+        _ => None,
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str(&self.to_css())
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s).ok_or_else(|| format!("Invalid color '{}'", s))
+    }
+}
+
+impl From<[u8; 4]> for Color {
+    fn from(rgba: [u8; 4]) -> Self {
+        Self::from_rgba8(rgba)
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+    }
+}
+
+/// A list of colors, some of which may be named
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Palette {
+    colors: Vec<(Color, Option<String>)>,
+}
+
+impl Palette {
+    pub fn new(colors: impl IntoIterator<Item = Color>) -> Self {
+        Self {
+            colors: colors.into_iter().map(|c| (c, None)).collect(),
+        }
+    }
+
+    /// The Lospec `.hex` format, one hex color per line
+    pub fn parse_hex(text: &str) -> Option<Self> {
+        let colors = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| parse_hex(line.trim_start_matches('#')).map(|c| (c, None)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { colors })
+    }
+
+    /// The GIMP `.gpl` format, `r g b name` lines after a header
+    pub fn parse_gpl(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()?.trim() != "GIMP Palette" {
+            return None;
+        }
+        let mut colors = Vec::new();
+        for line in lines.map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.contains(':') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let mut channel = || parts.next()?.parse::<u8>().ok();
+            let (r, g, b) = (channel()?, channel()?, channel()?);
+            let name = parts.collect::<Vec<_>>().join(" ");
+            colors.push((Color::rgb(r, g, b), if name.is_empty() { None } else { Some(name) }));
+        }
+        Some(Self { colors })
+    }
+
+    /// Either of the supported formats
+    pub fn parse(text: &str) -> Option<Self> {
+        if text.trim_start().starts_with("GIMP Palette") {
+            Self::parse_gpl(text)
+        } else {
+            Self::parse_hex(text)
+        }
+    }
+
+    pub(crate) fn load(url: &str) -> Mut<Option<Palette>> {
+        let palette = Mut::new(None);
+        let moved_palette = palette.clone();
+        let url = url.to_owned();
+        spawn_local(async move {
+            match fetch_text(&url).await {
+                Some(text) => match Palette::parse(&text) {
+                    Some(parsed) => *moved_palette.borrow_mut() = Some(parsed),
+                    None => log::error!("Failed to parse the palette at {}", url),
+                },
+                None => log::error!("Failed to load the palette at {}", url),
+            }
+        });
+        palette
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Color> {
+        self.colors.get(index).map(|(c, _)| *c)
+    }
+
+    /// Names are compared ignoring case
+    pub fn by_name(&self, name: &str) -> Option<Color> {
+        self.colors
+            .iter()
+            .find(|(_, n)| n.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name)))
+            .map(|(c, _)| *c)
+    }
+
+    pub fn colors(&self) -> impl Iterator<Item = Color> + '_ {
+        self.colors.iter().map(|(c, _)| *c)
+    }
+
+    pub fn push(&mut self, color: Color, name: Option<String>) {
+        self.colors.push((color, name));
+    }
+
+    /// The palette color that is the closest to the given one, for quantizing
+    pub fn nearest(&self, color: Color) -> Option<Color> {
+        let distance = |c: &Color| {
+            (c.r - color.r).powi(2) + (c.g - color.g).powi(2) + (c.b - color.b).powi(2)
+        };
+        self.colors()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Color, b: Color) -> bool {
+        a.to_array().iter().zip(b.to_array().iter()).all(|(x, y)| (x - y).abs() < 1e-6)
+    }
+
+    #[test]
+    fn parse_formats() {
+        let orange = Color::rgb(255, 136, 0);
+        for text in ["#ff8800", "#f80", "#FF8800FF", "#f80f", "rgb(255, 136, 0)", " RGB(255 136 0) "] {
+            assert_eq!(Color::parse(text), Some(orange), "{}", text);
+        }
+        assert_eq!(Color::parse("rgba(255, 136, 0, 0.5)"), Some(orange.with_alpha(0.5)));
+        assert_eq!(Color::parse("rgb(255 136 0 / 0.5)"), Some(orange.with_alpha(0.5)));
+        assert_eq!(Color::parse("#ff880080").map(|c| c.to_rgba8()), Some([255, 136, 0, 128]));
+        assert!(close(Color::parse("hsl(120deg, 100%, 50%)").unwrap(), Color::GREEN));
+        assert!(close(Color::parse("hsla(240, 100%, 50%, 0.25)").unwrap(), Color::BLUE.with_alpha(0.25)));
+        assert_eq!(Color::parse("transparent"), Some(Color::TRANSPARENT));
+        assert_eq!(Color::parse("Grey"), Some(Color::from_hex(0x808080)));
+        assert_eq!("#fff".parse::<Color>(), Ok(Color::WHITE));
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        let malformed = [
+            "", "#", "#12", "#12345", "#ggg", "ff8800", "nope", "rgb(1, 2)", "rgb(1, 2, 3", "rgb(a, b, c)", "hsl(1)",
+        ];
+        for text in malformed {
+            assert_eq!(Color::parse(text), None, "{}", text);
+        }
+        assert!("nope".parse::<Color>().is_err());
+        assert!(serde_json::from_str::<Color>("\"#12\"").is_err());
+    }
+
+    #[test]
+    fn round_trips() {
+        let colors = [Color::rgb(255, 136, 0), Color::rgb(12, 200, 99), Color::rgba(40, 50, 60, 0.5), Color::BLACK];
+        for color in colors {
+            assert_eq!(Color::parse(&color.to_hex()).map(|c| c.to_rgba8()), Some(color.to_rgba8()));
+            assert_eq!(Color::parse(&color.to_css()).map(|c| c.to_rgba8()), Some(color.to_rgba8()));
+            let (h, s, l) = color.to_hsl();
+            assert!(close(Color::hsla(h, s, l, color.a), color), "hsl {:?}", color);
+            let (h, s, v) = color.to_hsv();
+            assert!(close(Color::hsva(h, s, v, color.a), color), "hsv {:?}", color);
+        }
+        assert_eq!(Color::rgb(255, 136, 0).to_hex(), "#ff8800");
+        assert_eq!(Color::rgba(255, 136, 0, 0.5).to_hex(), "#ff880080");
+        assert_eq!(Color::hsv(0.0, 0.0, 1.0), Color::WHITE);
+    }
+
+    #[test]
+    fn palettes() {
+        let hex = Palette::parse("ff0000\n\n#00ff00\r\n0000ff\n").unwrap();
+        assert_eq!(hex, Palette::new([Color::RED, Color::GREEN, Color::BLUE]));
+        assert_eq!(Palette::parse("ff0000\nnope"), None);
+
+        let gpl = "GIMP Palette\nName: Test\nColumns: 2\n# comment\n255   0   0 Deep Red\n  0 255   0\n";
+        let gpl = Palette::parse(gpl).unwrap();
+        assert_eq!(gpl.len(), 2);
+        assert_eq!(gpl.by_name("Deep Red"), Some(Color::RED));
+        assert_eq!(gpl.get(1), Some(Color::GREEN));
+        assert_eq!(Palette::parse_gpl("GIMP Palette\n255 0\n"), None);
+        assert_eq!(Palette::parse_gpl("255 0 0\n"), None);
+
+        assert_eq!(gpl.nearest(Color::rgb(200, 30, 30)), Some(Color::RED));
+        assert!(gpl.nearest(Color::new(f64::NAN, 0.0, 0.0, 1.0)).is_some());
+        assert_eq!(Palette::default().nearest(Color::RED), None);
+    }
+}
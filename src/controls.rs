@@ -0,0 +1,408 @@
+use std::borrow::Cow;
+
+use crate::{
+    color::Color,
+    event::{Event, KeyMeta},
+    render::Transform,
+    surface::SurfaceContextExt,
+    ui::Text,
+    Context, Game,
+    V2, v2,
+};
+
+/// Which edge (or corner) of the screen a control is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Position of the anchor on a screen of given size, offset is pointing
+    /// inwards from the edges, so positive offsets are always visible
+    pub fn resolve(self, size: V2, offset: V2) -> V2 {
+        let (fx, fy) = match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        };
+        let sign = |f: f64| if f > 0.5 { -1.0 } else { 1.0 };
+        v2![
+            size.x * fx + sign(fx) * offset.x,
+            size.y * fy + sign(fy) * offset.y
+        ]
+    }
+}
+
+fn key_code(key: &str) -> u32 {
+    match key {
+        "Backspace" => 8,
+        "Tab" => 9,
+        "Enter" => 13,
+        "Shift" => 16,
+        "Control" => 17,
+        "Alt" => 18,
+        "Escape" => 27,
+        " " => 32,
+        "ArrowLeft" => 37,
+        "ArrowUp" => 38,
+        "ArrowRight" => 39,
+        "ArrowDown" => 40,
+        _ if key.len() == 1 => key
+            .chars()
+            .next()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_uppercase() as u32)
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn emit_key<G: Game>(context: &Context<G>, key: &str, down: bool) {
+    let code = key_code(key);
+    let key = key.to_owned();
+    let meta = KeyMeta::synthetic();
+    context.push_event(if down {
+        Event::KeyDown { code, key, meta }
+    } else {
+        Event::KeyUp { code, key, meta }
+    });
+}
+
+#[derive(Debug)]
+pub struct VirtualJoystick {
+    anchor: Anchor,
+    offset: V2,
+    radius: f64,
+    dead_zone: f64,
+    // up, down, left, right
+    keys: [&'static str; 4],
+    touch: Option<V2>,
+    direction: V2,
+    pressed: [bool; 4],
+}
+
+impl VirtualJoystick {
+    /// Offset and radius are in rems, the joystick emits arrow keys by default
+    pub fn new(anchor: Anchor, offset: V2) -> Self {
+        Self {
+            anchor,
+            offset,
+            radius: 4.0,
+            dead_zone: 0.3,
+            keys: ["ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight"],
+            touch: None,
+            direction: v2![0.0, 0.0],
+            pressed: [false; 4],
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Fraction of the radius in which the stick does not press any keys
+    pub fn with_dead_zone(mut self, dead_zone: f64) -> Self {
+        self.dead_zone = dead_zone;
+        self
+    }
+
+    pub fn with_keys(
+        mut self,
+        up: &'static str,
+        down: &'static str,
+        left: &'static str,
+        right: &'static str,
+    ) -> Self {
+        self.keys = [up, down, left, right];
+        self
+    }
+
+    /// Analog value of the stick, its length is at most 1
+    pub fn direction(&self) -> V2 {
+        self.direction
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.touch.is_some()
+    }
+
+    fn update<G: Game>(&mut self, touches: &[V2], started: bool, context: &Context<G>) -> bool {
+        let center = self.anchor.resolve(context.surface().canvas_size(), self.offset * context.rem_to_px(1.0));
+        let radius = context.rem_to_px(self.radius);
+
+        // the touch list has no ids, so follow the touch closest to the last one
+        // that is still in the joystick zone
+        self.touch = match self.touch {
+            Some(last) => touches
+                .iter()
+                .copied()
+                .filter(|t| (t - center).norm() <= radius * 2.0)
+                .min_by(|a, b| (a - last).norm().total_cmp(&(b - last).norm())),
+            None if started => touches.iter().copied().find(|t| (t - center).norm() <= radius),
+            None => None,
+        };
+
+        self.direction = match self.touch {
+            Some(touch) => {
+                let dir = (touch - center) / radius;
+                if dir.norm() > 1.0 {
+                    dir.normalize()
+                } else {
+                    dir
+                }
+            }
+            None => v2![0.0, 0.0],
+        };
+
+        let pressed = [
+            self.direction.y < -self.dead_zone,
+            self.direction.y > self.dead_zone,
+            self.direction.x < -self.dead_zone,
+            self.direction.x > self.dead_zone,
+        ];
+        self.set_pressed(pressed, context);
+
+        self.touch.is_some()
+    }
+
+    fn set_pressed<G: Game>(&mut self, pressed: [bool; 4], context: &Context<G>) {
+        for (i, &p) in pressed.iter().enumerate() {
+            if self.pressed[i] != p {
+                emit_key(context, self.keys[i], p);
+            }
+        }
+        self.pressed = pressed;
+    }
+
+    fn release<G: Game>(&mut self, context: &Context<G>) {
+        self.touch = None;
+        self.direction = v2![0.0, 0.0];
+        self.set_pressed([false; 4], context);
+    }
+}
+
+#[derive(Debug)]
+pub struct VirtualButton {
+    anchor: Anchor,
+    offset: V2,
+    radius: f64,
+    key: &'static str,
+    label: Option<Text>,
+    pressed: bool,
+}
+
+impl VirtualButton {
+    /// Offset and radius are in rems, the button emits the given key
+    /// (in the `KeyboardEvent.key` format) when pressed
+    pub fn new(anchor: Anchor, offset: V2, key: &'static str) -> Self {
+        Self {
+            anchor,
+            offset,
+            radius: 2.0,
+            key,
+            label: None,
+            pressed: false,
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<Cow<'static, str>>) -> Self {
+        self.label = Some(Text::new(label.into()));
+        self
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    fn update<G: Game>(&mut self, touches: &[V2], context: &Context<G>) -> bool {
+        let center = self.anchor.resolve(context.surface().canvas_size(), self.offset * context.rem_to_px(1.0));
+        let radius = context.rem_to_px(self.radius);
+
+        let pressed = touches.iter().any(|t| (t - center).norm() <= radius);
+        self.set_pressed(pressed, context);
+        pressed
+    }
+
+    fn set_pressed<G: Game>(&mut self, pressed: bool, context: &Context<G>) {
+        if self.pressed != pressed {
+            emit_key(context, self.key, pressed);
+            self.pressed = pressed;
+        }
+    }
+}
+
+/// On-screen joysticks and buttons for touch devices.
+///
+/// They turn touches into the same key events a keyboard would produce,
+/// so the game does not need any special handling for them.
+/// Controls are hidden until the screen is touched and are hidden
+/// again as soon as a real keyboard or a gamepad is used.
+#[derive(Debug)]
+pub struct VirtualControls {
+    joysticks: Vec<VirtualJoystick>,
+    buttons: Vec<VirtualButton>,
+    visible: bool,
+    color: Color,
+    active_color: Color,
+}
+
+impl Default for VirtualControls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualControls {
+    pub fn new() -> Self {
+        Self {
+            joysticks: Vec::new(),
+            buttons: Vec::new(),
+            visible: false,
+            color: Color::WHITE.fade(0.25),
+            active_color: Color::WHITE.fade(0.5),
+        }
+    }
+
+    pub fn with_joystick(mut self, joystick: VirtualJoystick) -> Self {
+        self.joysticks.push(joystick);
+        self
+    }
+
+    pub fn with_button(mut self, button: VirtualButton) -> Self {
+        self.buttons.push(button);
+        self
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_active_color(mut self, active_color: Color) -> Self {
+        self.active_color = active_color;
+        self
+    }
+
+    pub fn joystick(&self, index: usize) -> &VirtualJoystick {
+        &self.joysticks[index]
+    }
+
+    pub fn button(&self, index: usize) -> &VirtualButton {
+        &self.buttons[index]
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible<G: Game>(&mut self, visible: bool, context: &mut Context<G>) {
+        if !visible {
+            // so that no keys are left stuck
+            for joystick in &mut self.joysticks {
+                joystick.release(context);
+            }
+            for button in &mut self.buttons {
+                button.set_pressed(false, context);
+            }
+        }
+        self.visible = visible;
+    }
+
+    /// Returns true if the event was a touch on one of the controls
+    pub fn on_event<G: Game>(&mut self, event: &Event, context: &mut Context<G>) -> bool {
+        match event {
+            Event::KeyDown { meta, .. } | Event::KeyUp { meta, .. } if !meta.synthetic => {
+                if self.visible {
+                    self.set_visible(false, context);
+                }
+                false
+            }
+            Event::GamepadConnected => {
+                self.set_visible(false, context);
+                false
+            }
+            Event::TouchStart { touches }
+            | Event::TouchMove { touches }
+            | Event::TouchEnd { touches } => {
+                self.visible = true;
+                // controls live in canvas pixels so that they are not affected by the resolution
+                let viewport = context.surface().viewport();
+                let touches: Vec<_> = touches.iter().map(|t| viewport.to_canvas(t.screen_pos)).collect();
+                let started = matches!(event, Event::TouchStart { .. });
+                let mut consumed = false;
+                for joystick in &mut self.joysticks {
+                    consumed |= joystick.update(&touches, started, context);
+                }
+                for button in &mut self.buttons {
+                    consumed |= button.update(&touches, context);
+                }
+                consumed
+            }
+            _ => false,
+        }
+    }
+
+    pub fn on_update<G: Game>(&mut self, context: &mut Context<G>) {
+        if !self.visible {
+            return;
+        }
+        let surface = context.surface().renderer();
+        let size = context.surface().canvas_size();
+        let rem = context.rem_to_px(1.0);
+
+        surface.save();
+        surface.set_transform(Transform::identity());
+        surface.set_line_width(0.25 * rem);
+
+        for joystick in &self.joysticks {
+            let center = joystick.anchor.resolve(size, joystick.offset * rem);
+            let radius = joystick.radius * rem;
+            let color = if joystick.is_active() {
+                self.active_color
+            } else {
+                self.color
+            };
+            surface.stroke_color(color);
+            surface.circle(center, radius);
+            surface.fill_color(color);
+            surface.fill_circle(center + joystick.direction * radius, radius * 0.4);
+        }
+
+        for button in &mut self.buttons {
+            let center = button.anchor.resolve(size, button.offset * rem);
+            let color = if button.pressed {
+                self.active_color
+            } else {
+                self.color
+            };
+            surface.fill_color(color);
+            surface.fill_circle(center, button.radius * rem);
+            if let Some(label) = button.label.as_mut() {
+                label.set_size(button.radius);
+                label.on_update(context, center, self.active_color);
+            }
+        }
+
+        surface.restore();
+    }
+}
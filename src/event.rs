@@ -1,8 +1,7 @@
 use wasm_bindgen::{*, prelude::*};
-use web_sys::{DomPoint, EventTarget, MouseEvent, TouchEvent, WheelEvent};
+use web_sys::{EventTarget, HtmlCanvasElement, MouseEvent, TouchEvent, WheelEvent};
 
-use crate::{util::Mut, v2, V2};
-use crate::surface::SurfaceContext;
+use crate::{camera::Camera2D, screen::Orientation, surface::Viewport, util::Mut, v2, V2};
 
 pub trait ListenForever {
     fn listen_forever<E: JsCast>(&self, event_type: &str, f: impl FnMut(E) + 'static);
@@ -27,6 +26,7 @@ pub(super) fn setup_keyboard_events(target: &EventTarget, events: Mut<Vec<Event>
             shift: e.shift_key(),
             ctrl: e.ctrl_key(),
             meta: e.meta_key(),
+            synthetic: false,
         }
     }
 
@@ -49,24 +49,51 @@ pub(super) fn setup_keyboard_events(target: &EventTarget, events: Mut<Vec<Event>
     });
 }
 
-pub(super) fn setup_pointer_events(target: &EventTarget, context: &SurfaceContext, events: Mut<Vec<Event>>) {
+pub(super) fn setup_gamepad_events(target: &EventTarget, events: Mut<Vec<Event>>) {
+    let moved_events = events.clone();
+    target.listen_forever("gamepadconnected", move |_: web_sys::Event| {
+        moved_events.borrow_mut().push(Event::GamepadConnected)
+    });
+
+    let moved_events = events; //.clone();
+    target.listen_forever("gamepaddisconnected", move |_: web_sys::Event| {
+        moved_events.borrow_mut().push(Event::GamepadDisconnected)
+    });
+}
+
+pub(super) fn setup_pointer_events(target: &HtmlCanvasElement, camera: Mut<Camera2D>, viewport: Mut<Viewport>, events: Mut<Vec<Event>>) {
     target.listen_forever("contextmenu", |e: web_sys::Event| e.prevent_default());
 
-    fn get_pos(e: &MouseEvent, context: &SurfaceContext) -> V2 {
-        #[wasm_bindgen(inline_js = "export function transform(ctx, x, y) { return new DOMPoint(x, y).matrixTransform(ctx.getTransform().inverse()) }")]
-        extern "C" {
-            fn transform(ctx: &SurfaceContext, x: f64, y: f64) -> DomPoint;
+    // client coords to canvas pixels, the canvas is not always at (0, 0)
+    // and its css size is not always exactly the backing size / dpr
+    fn to_canvas(canvas: &HtmlCanvasElement, x: i32, y: i32) -> V2 {
+        let rect = canvas.get_bounding_client_rect();
+        let offset = v2![x as f64 - rect.left(), y as f64 - rect.top()];
+        // a hidden or collapsed canvas, there is nothing to scale by
+        if rect.width() <= 0.0 || rect.height() <= 0.0 {
+            return offset;
         }
-        let ratio = super::window().device_pixel_ratio();
-        let p = transform(context, e.client_x() as f64 * ratio, e.client_y() as f64 * ratio);
-        v2![p.x(), p.y()]
+        v2![
+            offset.x * canvas.width() as f64 / rect.width(),
+            offset.y * canvas.height() as f64 / rect.height()
+        ]
+    }
+
+    fn get_pos(e: &MouseEvent, canvas: &HtmlCanvasElement, camera: &Mut<Camera2D>, viewport: &Mut<Viewport>) -> (V2, V2) {
+        let viewport = viewport.borrow();
+        let screen_pos = viewport.to_logical(to_canvas(canvas, e.client_x(), e.client_y()));
+        (camera.borrow().screen_to_world(screen_pos, viewport.size), screen_pos)
     }
 
     let moved_event_queue = events.clone();
-    let moved_context = context.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("mouseup", move |e: MouseEvent| {
+        let (pos, screen_pos) = get_pos(&e, &moved_canvas, &moved_camera, &moved_viewport);
         moved_event_queue.borrow_mut().push(Event::MouseUp {
-            pos: get_pos(&e, &moved_context),
+            pos,
+            screen_pos,
             button: match MouseButton::from_code(e.button()) {
                 Some(b) => b,
                 _ => return,
@@ -75,10 +102,14 @@ pub(super) fn setup_pointer_events(target: &EventTarget, context: &SurfaceContex
     });
 
     let moved_event_queue = events.clone();
-    let moved_context = context.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("mousedown", move |e: MouseEvent| {
+        let (pos, screen_pos) = get_pos(&e, &moved_canvas, &moved_camera, &moved_viewport);
         moved_event_queue.borrow_mut().push(Event::MouseDown {
-            pos: get_pos(&e, &moved_context),
+            pos,
+            screen_pos,
             button: match MouseButton::from_code(e.button()) {
                 Some(b) => b,
                 _ => return,
@@ -87,56 +118,78 @@ pub(super) fn setup_pointer_events(target: &EventTarget, context: &SurfaceContex
     });
 
     let moved_event_queue = events.clone();
-    let moved_context = context.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("mousemove", move |e: MouseEvent| {
+        let (pos, screen_pos) = get_pos(&e, &moved_canvas, &moved_camera, &moved_viewport);
         moved_event_queue.borrow_mut().push(Event::MouseMove {
-            pos: get_pos(&e, &moved_context),
+            pos,
+            screen_pos,
             buttons: MouseButton::from_bitmap(e.buttons()),
         });
     });
 
     let moved_event_queue = events.clone();
-    let moved_context = context.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("wheel", move |e: WheelEvent| {
+        let (pos, screen_pos) = get_pos(&e, &moved_canvas, &moved_camera, &moved_viewport);
         moved_event_queue.borrow_mut().push(Event::MouseWheel {
-            pos: get_pos(&e, &moved_context),
+            pos,
+            screen_pos,
             delta: v2![e.delta_x(), e.delta_y()],
             buttons: MouseButton::from_bitmap(e.buttons()),
         });
     });
 
-    fn get_touches(e: TouchEvent) -> Box<[V2]> {
-        let ratio = super::window().device_pixel_ratio();
+    fn get_touches(e: TouchEvent, canvas: &HtmlCanvasElement, camera: &Mut<Camera2D>, viewport: &Mut<Viewport>) -> Box<[Touch]> {
+        let camera = camera.borrow();
+        let viewport = viewport.borrow();
         let touch_list = e.touches();
         let mut touches = Vec::with_capacity(touch_list.length() as usize);
         while let Some(t) = touch_list.get(touches.len() as u32) {
-            touches.push(v2![t.client_x() as f64 * ratio, t.client_y() as f64 * ratio]);
+            let screen_pos = viewport.to_logical(to_canvas(canvas, t.client_x(), t.client_y()));
+            touches.push(Touch {
+                pos: camera.screen_to_world(screen_pos, viewport.size),
+                screen_pos,
+            });
         }
         touches.into_boxed_slice()
     }
 
     let moved_event_queue = events.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("touchstart", move |e: TouchEvent| {
         // prevent mouse emulation if any
         e.prevent_default();
         moved_event_queue.borrow_mut().push(Event::TouchStart {
-            touches: get_touches(e),
+            touches: get_touches(e, &moved_canvas, &moved_camera, &moved_viewport),
         });
     });
 
     let moved_event_queue = events.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("touchmove", move |e: TouchEvent| {
         e.prevent_default();
         moved_event_queue.borrow_mut().push(Event::TouchMove {
-            touches: get_touches(e),
+            touches: get_touches(e, &moved_canvas, &moved_camera, &moved_viewport),
         });
     });
 
     let moved_event_queue = events.clone();
+    let moved_canvas = target.clone();
+    let moved_camera = camera.clone();
+    let moved_viewport = viewport.clone();
     target.listen_forever("touchend", move |e: TouchEvent| {
         e.prevent_default();
         moved_event_queue.borrow_mut().push(Event::TouchEnd {
-            touches: get_touches(e),
+            touches: get_touches(e, &moved_canvas, &moved_camera, &moved_viewport),
         });
     });
 }
@@ -190,35 +243,64 @@ pub struct KeyMeta {
     pub shift: bool,
     pub ctrl: bool,
     pub meta: bool,
+    /// Set for key events that did not come from a real keyboard,
+    /// e.g. the ones emitted by [crate::controls::VirtualControls]
+    pub synthetic: bool,
 }
 
+impl KeyMeta {
+    pub fn synthetic() -> Self {
+        Self {
+            repeat: false,
+            alt: false,
+            shift: false,
+            ctrl: false,
+            meta: false,
+            synthetic: true,
+        }
+    }
+}
+
+/// A single touch point, `pos` is in world space and `screen_pos` is in logical pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Touch {
+    pub pos: V2,
+    pub screen_pos: V2,
+}
+
+/// Pointer events carry both the world position (through the surface camera)
+/// in `pos` and the position in logical pixels in `screen_pos`
 #[derive(Debug, Clone)]
 pub enum Event {
     MouseDown {
         pos: V2,
+        screen_pos: V2,
         button: MouseButton,
     },
     MouseUp {
         pos: V2,
+        screen_pos: V2,
         button: MouseButton,
     },
     MouseMove {
         pos: V2,
+        screen_pos: V2,
         buttons: Vec<MouseButton>,
     },
     MouseWheel {
         pos: V2,
+        screen_pos: V2,
         buttons: Vec<MouseButton>,
         delta: V2,
     },
     TouchStart {
-        touches: Box<[V2]>,
+        touches: Box<[Touch]>,
     },
     TouchMove {
-        touches: Box<[V2]>,
+        touches: Box<[Touch]>,
     },
     TouchEnd {
-        touches: Box<[V2]>,
+        touches: Box<[Touch]>,
     },
     KeyDown {
         code: u32,
@@ -230,6 +312,14 @@ pub enum Event {
         key: String,
         meta: KeyMeta,
     },
+    GamepadConnected,
+    GamepadDisconnected,
+    FullscreenChange {
+        fullscreen: bool,
+    },
+    OrientationChange {
+        orientation: Orientation,
+    },
 }
 
 impl Event {
@@ -244,4 +334,8 @@ impl Event {
     pub fn is_touch(&self) -> bool {
         matches!(self, Event::TouchStart {..} | Event::TouchMove {..} | Event::TouchEnd {..})
     }
+
+    pub fn is_gamepad(&self) -> bool {
+        matches!(self, Event::GamepadConnected | Event::GamepadDisconnected)
+    }
 }
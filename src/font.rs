@@ -0,0 +1,645 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::FontFace;
+
+use crate::{
+    color::Color,
+    document,
+    render::{Image, Rect, Renderer},
+    sprite::{tint_rgb, Spritesheet, TINT_CACHE_LIMIT},
+    surface::Surface,
+    util::{base_url, fetch_text, Mut},
+    xml,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WebFontState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+/// A font file loaded with the FontFace API, see [crate::Resources::load_font]
+#[derive(Debug, Clone)]
+pub struct WebFont {
+    family: String,
+    state: Mut<WebFontState>,
+}
+
+impl PartialEq for WebFont {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&*self.state, &*other.state)
+    }
+}
+
+impl WebFont {
+    pub(crate) fn load(family: &str, url: &str) -> Self {
+        let font = Self {
+            family: family.to_owned(),
+            state: Mut::new(WebFontState::Loading),
+        };
+        let moved_state = font.state.clone();
+        let (family, url) = (family.to_owned(), url.to_owned());
+        spawn_local(async move {
+            let loaded = async {
+                let face = FontFace::new_with_str(&family, &format!("url({})", css_string(&url)))?;
+                JsFuture::from(face.load()?).await?;
+                document().fonts().add(&face)?;
+                Ok::<_, JsValue>(())
+            };
+            *moved_state.borrow_mut() = match loaded.await {
+                Ok(()) => WebFontState::Loaded,
+                Err(e) => {
+                    log::error!("Failed to load the font at {}: {:?}", url, e);
+                    WebFontState::Failed
+                }
+            };
+        });
+        font
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        *self.state.borrow() == WebFontState::Loaded
+    }
+
+    /// Loaded or failed to, in which case the browser falls back to some other font
+    pub fn is_done(&self) -> bool {
+        *self.state.borrow() != WebFontState::Loading
+    }
+
+    pub fn spec(&self, size: f64) -> FontSpec {
+        FontSpec {
+            web: Some(self.clone()),
+            ..FontSpec::new(self.family.clone(), size)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FontSpec {
+    /// A family name or a comma separated list of them, quoted as needed
+    pub family: String,
+    /// In pixels
+    pub size: f64,
+    pub weight: u16,
+    pub italic: bool,
+    /// The text is not drawn until it is loaded
+    #[serde(skip)]
+    pub web: Option<WebFont>,
+}
+
+impl Default for FontSpec {
+    fn default() -> Self {
+        Self::new("sans-serif", 16.0)
+    }
+}
+
+impl FontSpec {
+    pub fn new(family: impl Into<String>, size: f64) -> Self {
+        Self {
+            family: family.into(),
+            size,
+            weight: 400,
+            italic: false,
+            web: None,
+        }
+    }
+
+    pub fn with_size(mut self, size: f64) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u16) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn bold(self) -> Self {
+        self.with_weight(700)
+    }
+
+    pub fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    /// The css font string
+    pub fn to_css(&self) -> String {
+        format!(
+            "{}{} {}px {}",
+            if self.italic { "italic " } else { "" },
+            self.weight,
+            self.size,
+            css_family(&self.family)
+        )
+    }
+}
+
+const GENERIC_FAMILIES: &[&str] = &[
+    "serif",
+    "sans-serif",
+    "monospace",
+    "cursive",
+    "fantasy",
+    "system-ui",
+    "ui-serif",
+    "ui-sans-serif",
+    "ui-monospace",
+    "ui-rounded",
+    "math",
+    "emoji",
+    "fangsong",
+    "inherit",
+    "initial",
+    "unset",
+];
+
+fn css_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// names like "Press Start 2P" are not valid unquoted, generic ones stop working when quoted
+fn css_family(family: &str) -> String {
+    family
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if name.starts_with('"') || name.starts_with('\'') || GENERIC_FAMILIES.contains(&name) {
+                name.to_owned()
+            } else {
+                css_string(name)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Glyph {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Where to draw the glyph relative to the pen position and the top of the line
+    #[serde(default)]
+    pub xoffset: f64,
+    #[serde(default)]
+    pub yoffset: f64,
+    /// How far the pen moves after the glyph
+    pub xadvance: f64,
+    #[serde(default)]
+    pub page: usize,
+}
+
+/// The glyph table of a bitmap font.
+///
+/// Parsed from BMFont `.fnt` files (text or XML) or from JSON like this:
+/// ```json
+/// {
+///     "size": 8, "line_height": 10, "base": 8, "pages": ["font.png"],
+///     "glyphs": { "A": { "x": 0, "y": 0, "width": 5, "height": 7, "xadvance": 6 } },
+///     "kerning": [["A", "V", -1]]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BitmapFontData {
+    pub size: f64,
+    pub line_height: f64,
+    /// From the top of the line to the baseline
+    pub base: f64,
+    /// Image files, relative to the font file
+    #[serde(default)]
+    pub pages: Vec<String>,
+    pub glyphs: HashMap<char, Glyph>,
+    #[serde(default)]
+    pub kerning: Vec<(char, char, f64)>,
+}
+
+impl BitmapFontData {
+    /// Any of the supported formats
+    pub fn parse(text: &str) -> Option<Self> {
+        let trimmed = text.trim_start();
+        if trimmed.starts_with('{') {
+            serde_json::from_str(trimmed)
+                .map_err(|e| log::error!("Failed to parse the font: {}", e))
+                .ok()
+        } else if trimmed.starts_with('<') {
+            Self::parse_xml(trimmed)
+        } else {
+            Self::parse_fnt(trimmed)
+        }
+    }
+
+    /// The BMFont XML format
+    pub fn parse_xml(text: &str) -> Option<Self> {
+        let root = xml::parse(text)?;
+        let tags = root.descendants().into_iter().map(|e| (e.name.as_str(), e.attributes.clone()));
+        Self::from_tags(tags)
+    }
+
+    /// The BMFont text format
+    pub fn parse_fnt(text: &str) -> Option<Self> {
+        let mut tags = Vec::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+            tags.push((&line[..name_end], xml::parse_attributes(&line[name_end..])?));
+        }
+        Self::from_tags(tags.into_iter())
+    }
+
+    // both BMFont formats have the same tags and attributes
+    fn from_tags<'a>(tags: impl Iterator<Item = (&'a str, Vec<(String, String)>)>) -> Option<Self> {
+        let mut data = Self::default();
+        let mut pages = Vec::new();
+        for (name, attributes) in tags {
+            let attr = |key: &str| attributes.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+            let num = |key: &str| attr(key).and_then(|v| v.trim().parse::<f64>().ok()).unwrap_or(0.0);
+            match name {
+                "info" => data.size = num("size").abs(),
+                "common" => {
+                    data.line_height = num("lineHeight");
+                    data.base = num("base");
+                }
+                "page" => pages.push((num("id") as usize, attr("file")?.to_owned())),
+                "char" => {
+                    let c = std::char::from_u32(num("id") as u32)?;
+                    data.glyphs.insert(
+                        c,
+                        Glyph {
+                            x: num("x") as u32,
+                            y: num("y") as u32,
+                            width: num("width") as u32,
+                            height: num("height") as u32,
+                            xoffset: num("xoffset"),
+                            yoffset: num("yoffset"),
+                            xadvance: num("xadvance"),
+                            page: num("page") as usize,
+                        },
+                    );
+                }
+                "kerning" => {
+                    let first = std::char::from_u32(num("first") as u32)?;
+                    let second = std::char::from_u32(num("second") as u32)?;
+                    data.kerning.push((first, second, num("amount")));
+                }
+                _ => {}
+            }
+        }
+        if data.glyphs.is_empty() {
+            return None;
+        }
+        if data.size == 0.0 {
+            data.size = data.line_height;
+        }
+        pages.sort_by_key(|(id, _)| *id);
+        data.pages = pages.into_iter().map(|(_, file)| file).collect();
+        Some(data)
+    }
+}
+
+struct Loaded {
+    data: BitmapFontData,
+    kerning: HashMap<(char, char), f64>,
+    pages: Vec<Spritesheet>,
+    // by page and the color part of the text color, the alpha is applied when drawing
+    tinted: RefCell<HashMap<(usize, [u8; 3]), Image>>,
+}
+
+/// A font drawn from glyphs in spritesheets, for the crisp pixel-art text.
+///
+/// The glyphs are tinted with the text color, so white fonts work best,
+/// and white draws them as they are.
+#[derive(Clone)]
+pub struct BitmapFont {
+    inner: Mut<Option<Rc<Loaded>>>,
+}
+
+impl Debug for BitmapFont {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &*self.inner.borrow() {
+            Some(loaded) => write!(f, "BitmapFont({} glyphs)", loaded.data.glyphs.len()),
+            None => write!(f, "BitmapFont(not loaded)"),
+        }
+    }
+}
+
+impl PartialEq for BitmapFont {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&*self.inner, &*other.inner)
+    }
+}
+
+impl BitmapFont {
+    pub fn new(data: BitmapFontData, pages: Vec<Spritesheet>) -> Self {
+        let font = Self {
+            inner: Mut::new(None),
+        };
+        font.set(data, pages);
+        font
+    }
+
+    /// A single page font, ignoring the pages the glyph table mentions
+    pub fn from_spritesheet(spritesheet: Spritesheet, glyphs: &str) -> Option<Self> {
+        Some(Self::new(BitmapFontData::parse(glyphs)?, vec![spritesheet]))
+    }
+
+    /// Loads the glyph table and then its pages from the urls relative to it
+    pub(crate) fn load(surface: Mut<Surface>, url: &str) -> Self {
+        let font = Self {
+            inner: Mut::new(None),
+        };
+        let moved_font = font.clone();
+        let url = url.to_owned();
+        spawn_local(async move {
+            let data = match fetch_text(&url).await {
+                Some(text) => BitmapFontData::parse(&text),
+                None => {
+                    log::error!("Failed to load the font at {}", url);
+                    return;
+                }
+            };
+            match data {
+                Some(data) => {
+                    let base = base_url(&url);
+                    let pages = data
+                        .pages
+                        .iter()
+                        .map(|file| Spritesheet::load(surface.clone(), &format!("{}{}", base, file)))
+                        .collect();
+                    moved_font.set(data, pages);
+                }
+                None => log::error!("Failed to parse the font at {}", url),
+            }
+        });
+        font
+    }
+
+    fn set(&self, data: BitmapFontData, pages: Vec<Spritesheet>) {
+        let kerning = data.kerning.iter().map(|&(a, b, amount)| ((a, b), amount)).collect();
+        *self.inner.borrow_mut() = Some(Rc::new(Loaded {
+            data,
+            kerning,
+            pages,
+            tinted: RefCell::new(HashMap::new()),
+        }));
+    }
+
+    /// The glyph table is loaded, the pages might still be loading
+    pub fn is_loaded(&self) -> bool {
+        self.inner.borrow().is_some()
+    }
+
+    pub fn data(&self) -> Option<BitmapFontData> {
+        Some(self.inner.borrow().as_ref()?.data.clone())
+    }
+
+    fn loaded(&self) -> Option<Rc<Loaded>> {
+        self.inner.borrow().clone()
+    }
+
+    /// The size it was made for, or zero if it's not loaded
+    pub fn size(&self) -> f64 {
+        self.loaded().map_or(0.0, |l| l.data.size)
+    }
+
+    pub fn line_height(&self) -> f64 {
+        self.loaded().map_or(0.0, |l| l.data.line_height)
+    }
+
+    /// Whole number scale closest to the size, so that the pixels stay crisp
+    pub fn scale_for(&self, size: f64) -> f64 {
+        let native = self.size();
+        if native <= 0.0 {
+            return 1.0;
+        }
+        (size / native).round().max(1.0)
+    }
+
+    /// The width of the text at the scale, with kerning
+    pub fn measure(&self, text: &str, scale: f64) -> f64 {
+        let loaded = match self.loaded() {
+            Some(loaded) => loaded,
+            None => return 0.0,
+        };
+        let mut width = 0.0;
+        let mut prev = None;
+        for c in text.chars() {
+            if let Some(glyph) = loaded.glyph(c) {
+                width += glyph.xadvance + loaded.kern(prev, c);
+            }
+            prev = Some(c);
+        }
+        width * scale
+    }
+
+    /// Draws the text with the top of the line at `y`
+    pub fn draw(&self, renderer: &dyn Renderer, text: &str, x: f64, y: f64, scale: f64, color: Color) {
+        let loaded = match self.loaded() {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        renderer.save();
+        if color.a != 1.0 {
+            renderer.set_global_alpha(color.a);
+        }
+        let mut pen = x;
+        let mut prev = None;
+        for c in text.chars() {
+            if let Some(glyph) = loaded.glyph(c) {
+                pen += loaded.kern(prev, c) * scale;
+                if glyph.width > 0 && glyph.height > 0 {
+                    if let Some(image) = loaded.page(glyph.page, color) {
+                        renderer.draw_image(
+                            &image,
+                            Rect::new(glyph.x as f64, glyph.y as f64, glyph.width as f64, glyph.height as f64),
+                            Rect::new(
+                                pen + glyph.xoffset * scale,
+                                y + glyph.yoffset * scale,
+                                glyph.width as f64 * scale,
+                                glyph.height as f64 * scale,
+                            ),
+                        );
+                    }
+                }
+                pen += glyph.xadvance * scale;
+            }
+            prev = Some(c);
+        }
+        renderer.restore();
+    }
+}
+
+impl Loaded {
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.data.glyphs.get(&c).or_else(|| self.data.glyphs.get(&'?'))
+    }
+
+    fn kern(&self, prev: Option<char>, c: char) -> f64 {
+        prev.and_then(|p| self.kerning.get(&(p, c)).copied()).unwrap_or(0.0)
+    }
+
+    // the page image multiplied by the color without its alpha, cached
+    fn page(&self, index: usize, color: Color) -> Option<Image> {
+        let sheet = self.pages.get(index)?;
+        let image = sheet.image()?;
+        let rgb = match tint_rgb(color) {
+            Some(rgb) => rgb,
+            None => return Some(image),
+        };
+        let key = (index, rgb);
+        if let Some(tinted) = self.tinted.borrow().get(&key) {
+            return Some(tinted.clone());
+        }
+        let full = Rect::new(0.0, 0.0, image.width() as f64, image.height() as f64);
+        let tinted = sheet.surface().tint_image(&image, full, color.with_alpha(1.0));
+        let mut cache = self.tinted.borrow_mut();
+        if cache.len() >= TINT_CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(key, tinted.clone());
+        Some(tinted)
+    }
+}
+
+/// Either a css font or a bitmap one, see [crate::text::TextStyle]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Font {
+    Css(FontSpec),
+    /// The size is in pixels, and is rounded to a whole number scale of the font
+    Bitmap { font: BitmapFont, size: f64 },
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font::Css(FontSpec::default())
+    }
+}
+
+impl Font {
+    pub fn size(&self) -> f64 {
+        match self {
+            Font::Css(spec) => spec.size,
+            Font::Bitmap { size, .. } => *size,
+        }
+    }
+
+    pub fn set_size(&mut self, new_size: f64) {
+        match self {
+            Font::Css(spec) => spec.size = new_size,
+            Font::Bitmap { size, .. } => *size = new_size,
+        }
+    }
+
+    pub fn with_size(mut self, size: f64) -> Self {
+        self.set_size(size);
+        self
+    }
+
+    /// Web fonts are done loading and bitmap ones have their glyphs
+    pub fn is_ready(&self) -> bool {
+        match self {
+            Font::Css(spec) => spec.web.iter().all(WebFont::is_done),
+            Font::Bitmap { font, .. } => font.is_loaded(),
+        }
+    }
+}
+
+impl From<WebFont> for Font {
+    fn from(font: WebFont) -> Self {
+        Font::Css(font.spec(16.0))
+    }
+}
+
+impl From<FontSpec> for Font {
+    fn from(spec: FontSpec) -> Self {
+        Font::Css(spec)
+    }
+}
+
+/// At its own size
+impl From<BitmapFont> for Font {
+    fn from(font: BitmapFont) -> Self {
+        let size = font.size();
+        Font::Bitmap { font, size }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{Pixmap, SoftwareRenderer};
+
+    #[test]
+    fn font_spec_css() {
+        assert_eq!(FontSpec::new("sans-serif", 16.0).to_css(), "400 16px sans-serif");
+        assert_eq!(
+            FontSpec::new("Press Start 2P", 8.0).bold().italic().to_css(),
+            "italic 700 8px \"Press Start 2P\""
+        );
+        assert_eq!(
+            FontSpec::new("Pixel \"Font\", 'Fallback', monospace", 8.0).to_css(),
+            "400 8px \"Pixel \\\"Font\\\"\", 'Fallback', monospace"
+        );
+    }
+
+    fn font() -> (BitmapFont, Rc<SoftwareRenderer>) {
+        let renderer = Rc::new(SoftwareRenderer::new(2, 2));
+        let surface = Mut::new(Surface::with_renderer(renderer.clone()));
+        let mut pixmap = Pixmap::new(2, 2);
+        pixmap.fill([255, 255, 255, 255]);
+        let glyphs = r#"{ "size": 2, "line_height": 2, "base": 2,
+            "glyphs": { "A": { "x": 0, "y": 0, "width": 2, "height": 2, "xadvance": 2 } } }"#;
+        let font = BitmapFont::from_spritesheet(Spritesheet::from_pixmap(surface, pixmap), glyphs).unwrap();
+        (font, renderer)
+    }
+
+    fn cached(font: &BitmapFont) -> usize {
+        font.loaded().unwrap().tinted.borrow().len()
+    }
+
+    #[test]
+    fn tint_alpha_is_drawn_not_cached() {
+        let (font, renderer) = font();
+        for i in 0..100 {
+            font.draw(&*renderer, "A", 0.0, 0.0, 1.0, Color::RED.with_alpha(i as f64 / 100.0));
+        }
+        assert_eq!(cached(&font), 1);
+
+        let (font, renderer) = self::font();
+        font.draw(&*renderer, "A", 0.0, 0.0, 1.0, Color::RED.with_alpha(0.5));
+        assert_eq!(renderer.snapshot().pixel(1, 1), [255, 0, 0, 128]);
+
+        let (font, renderer) = self::font();
+        font.draw(&*renderer, "A", 0.0, 0.0, 1.0, Color::WHITE.with_alpha(0.5));
+        assert_eq!(cached(&font), 0);
+        assert_eq!(renderer.snapshot().pixel(0, 0), [255, 255, 255, 128]);
+    }
+
+    #[test]
+    fn tint_cache_is_bounded() {
+        let (font, renderer) = font();
+        for i in 0..(TINT_CACHE_LIMIT as u32 + 10) {
+            font.draw(&*renderer, "A", 0.0, 0.0, 1.0, Color::rgb(i as u8, (i >> 8) as u8, 0));
+        }
+        assert!(cached(&font) <= TINT_CACHE_LIMIT);
+    }
+
+    #[test]
+    fn quoted_url() {
+        assert_eq!(css_string(r#"fonts/a "b".ttf"#), r#""fonts/a \"b\".ttf""#);
+    }
+}
@@ -8,18 +8,37 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::{*, prelude::*};
 use web_sys::{Document, HtmlElement, Window};
 
+use atlas::Atlas;
+use color::Palette;
 use event::Event;
+use font::{BitmapFont, WebFont};
 use sound::{Sound, SoundContext};
 use sprite::Spritesheet;
-use surface::Surface;
+use surface::{Host, Surface};
+use tilemap::Tilemap;
 use util::Mut;
 
+pub mod animation;
+pub mod atlas;
+pub mod batch;
+pub mod camera;
+pub mod color;
+pub mod controls;
 pub mod event;
+pub mod font;
+pub mod lighting;
+pub mod nine_slice;
+pub mod particles;
+pub mod render;
+pub mod screen;
 pub mod sound;
 pub mod sprite;
 pub mod surface;
+pub mod text;
+pub mod tilemap;
 pub mod ui;
 pub mod util;
+mod xml;
 
 pub type V2 = Vector2<f64>;
 
@@ -45,23 +64,23 @@ pub fn body() -> HtmlElement {
     document().body().expect("No document.body")
 }
 
-fn get_data<D: Default + for<'a> Deserialize<'a>>() -> D {
+fn get_data<D: Default + for<'a> Deserialize<'a>>(key: &str) -> D {
     window()
         .local_storage()
         .unwrap()
         .unwrap()
-        .get("data")
+        .get(key)
         .unwrap()
         .and_then(|s| serde_json::from_str(&s).ok())
         .unwrap_or_default()
 }
 
-fn set_data<D: Serialize>(data: &D) {
+fn set_data<D: Serialize>(key: &str, data: &D) {
     window()
         .local_storage()
         .unwrap()
         .unwrap()
-        .set("data", &serde_json::to_string(data).unwrap())
+        .set(key, &serde_json::to_string(data).unwrap())
         .unwrap()
 }
 
@@ -110,6 +129,8 @@ pub struct Context<'a, G: Game> {
     rem_to_px: f64,
     surface: Mut<Surface>,
     sound_context: Mut<SoundContext>,
+    events: Mut<Vec<Event>>,
+    storage_key: &'a str,
     storage: &'a mut G::Storage,
     pub game: &'a mut G,
 }
@@ -131,12 +152,18 @@ impl<'a, G: Game> Context<'a, G> {
         self.sound_context.borrow_mut()
     }
 
+    /// Queues an event as if it came from the browser, it is dispatched
+    /// to the current state before the next `on_update`.
+    pub fn push_event(&self, event: Event) {
+        self.events.borrow_mut().push(event);
+    }
+
     pub fn storage(&self) -> &G::Storage {
         self.storage
     }
 
     pub fn set_storage(&mut self, new_storage: G::Storage) {
-        set_data(&new_storage);
+        set_data(self.storage_key, &new_storage);
         *self.storage = new_storage;
     }
 }
@@ -173,17 +200,51 @@ fn handle_transition<G: Game>(
     }
 }
 
-fn run<G: Game>() {
+/// Things that have to be known before the game starts
+#[derive(Debug, Clone)]
+pub struct GameConfig {
+    pub host: Host,
+    /// The local storage key for [Game::Storage], give different games on the same page different keys
+    pub storage_key: String,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            host: Host::Window,
+            storage_key: "data".into(),
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_host(mut self, host: Host) -> Self {
+        self.host = host;
+        self
+    }
+
+    pub fn with_storage_key(mut self, storage_key: impl Into<String>) -> Self {
+        self.storage_key = storage_key.into();
+        self
+    }
+}
+
+fn run<G: Game>(config: GameConfig) {
     let event_queue = Mut::new(Vec::new());
 
-    let surface = Mut::new(Surface::new(event_queue.clone()));
+    let surface = Mut::new(Surface::new(&config.host, event_queue.clone()));
     let sound_context = Mut::new(SoundContext::new());
 
     let (mut game, current_state) = G::load(Resources {
         surface: surface.clone(),
         sound_context: sound_context.clone(),
     });
-    let mut storage = get_data();
+    let storage_key = config.storage_key;
+    let mut storage = get_data(&storage_key);
 
     let mut states = vec![current_state];
     handle_transition(
@@ -194,7 +255,9 @@ fn run<G: Game>() {
             rem_to_px: compute_rem_to_pixel_ratio(),
             surface: surface.clone(),
             sound_context: sound_context.clone(),
+            events: event_queue.clone(),
             game: &mut game,
+            storage_key: &storage_key,
             storage: &mut storage,
         },
     );
@@ -212,19 +275,17 @@ fn run<G: Game>() {
     let rc2 = rc1.clone();
 
     *rc1.borrow_mut() = Some(Closure::wrap(Box::new(move |time: f64| {
-        let ctx = surface.borrow().context();
-
-        ctx.set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0).unwrap();
-        let size = surface.borrow().size();
-        let center = size / 2.0;
-        ctx.translate(center.x, center.y).unwrap();
-
         let time = time / 1e3;
+        let delta_time = time - last_time;
+
+        surface.borrow().begin_frame(delta_time);
 
         handle_transition(
             &mut states,
             |state, context| loop {
-                if let Some(event) = event_queue.borrow_mut().pop() {
+                // don't hold the queue borrowed while dispatching, states can push events too
+                let next = event_queue.borrow_mut().pop();
+                if let Some(event) = next {
                     match state.on_event(event, context) {
                         StateTransition::None => (),
                         x => break x,
@@ -234,15 +295,20 @@ fn run<G: Game>() {
                 }
             },
             Context {
-                delta_time: time - last_time,
+                delta_time,
                 rem_to_px: compute_rem_to_pixel_ratio(),
                 surface: surface.clone(),
                 sound_context: sound_context.clone(),
+                events: event_queue.clone(),
                 game: &mut game,
+                storage_key: &storage_key,
                 storage: &mut storage,
             },
         );
 
+        surface.borrow().flush_layers();
+        surface.borrow().end_frame();
+
         last_time = time;
 
         window_moved
@@ -261,6 +327,10 @@ pub struct Resources {
 }
 
 impl Resources {
+    pub fn surface(&self) -> Ref<Surface> {
+        self.surface.borrow()
+    }
+
     pub fn load_spritesheet(&self, url: &str) -> Spritesheet {
         Spritesheet::load(self.surface.clone(), url)
     }
@@ -268,6 +338,32 @@ impl Resources {
     pub fn load_sound(&self, url: &str) -> Sound {
         Sound::load(self.sound_context.clone(), url)
     }
+
+    /// Loads a TTF, OTF or WOFF font under the family name, text using it
+    /// is not drawn until it's loaded
+    pub fn load_font(&self, family: &str, url: &str) -> WebFont {
+        WebFont::load(family, url)
+    }
+
+    /// Loads an Aseprite or TexturePacker JSON and then the image it mentions
+    pub fn load_atlas(&self, url: &str) -> Atlas {
+        Atlas::load(self.surface.clone(), url)
+    }
+
+    /// Loads a BMFont `.fnt` (text or XML) or a JSON glyph table, and then the page images
+    pub fn load_bitmap_font(&self, url: &str) -> BitmapFont {
+        BitmapFont::load(self.surface.clone(), url)
+    }
+
+    /// Loads a Lospec `.hex` or a GIMP `.gpl` palette, it's `None` until it's loaded
+    pub fn load_palette(&self, url: &str) -> Mut<Option<Palette>> {
+        Palette::load(url)
+    }
+
+    /// Loads a Tiled `.tmj` or `.tmx` map, its external tilesets and their images
+    pub fn load_tilemap(&self, url: &str) -> Tilemap {
+        Tilemap::load(self.surface.clone(), url)
+    }
 }
 
 // copying Amethyst so hard accidentaly
@@ -304,7 +400,13 @@ pub trait Game
 
 pub trait GameRun: Game + private::Sealed {
     fn run() {
-        run::<Self>()
+        run::<Self>(GameConfig::default())
+    }
+
+    /// Can be called several times (with different hosts) to have
+    /// independent game instances on one page
+    fn run_with(config: GameConfig) {
+        run::<Self>(config)
     }
 }
 
@@ -0,0 +1,234 @@
+use crate::{
+    color::Color,
+    render::{BlendMode, FillRule, Layer, Path, Rect, RenderTarget, Transform},
+    surface::{Surface, SurfaceContextExt},
+    V2,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub pos: V2,
+    pub radius: f64,
+    pub color: Color,
+    pub intensity: f64,
+    /// Exponent of the falloff, 1 is linear and bigger ones make the light tighter
+    pub falloff: f64,
+    /// Direction and half of the angle of the cone, in radians
+    pub cone: Option<(f64, f64)>,
+}
+
+impl Light {
+    pub fn point(pos: V2, radius: f64) -> Self {
+        Self {
+            pos,
+            radius,
+            color: Color::WHITE,
+            intensity: 1.0,
+            falloff: 1.0,
+            cone: None,
+        }
+    }
+
+    pub fn cone(pos: V2, radius: f64, direction: f64, spread: f64) -> Self {
+        Self::point(pos, radius).with_cone(direction, spread)
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_falloff(mut self, falloff: f64) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    pub fn with_cone(mut self, direction: f64, spread: f64) -> Self {
+        self.cone = Some((direction, spread));
+        self
+    }
+
+    fn bounds(&self) -> Rect {
+        Rect::new(
+            self.pos.x - self.radius,
+            self.pos.y - self.radius,
+            self.radius * 2.0,
+            self.radius * 2.0,
+        )
+    }
+}
+
+/// Ambient darkness with lights and hard shadows, multiplied over the world.
+///
+/// Lights and occluders are in world units. Set them up every frame
+/// (or keep them around) and call [Lighting::submit], which renders the light
+/// map right away and composites it after everything else in [Layer::World].
+/// Occluder polygons are in shadow themselves, which is what you want
+/// for cave walls and such.
+pub struct Lighting {
+    pub ambient: Color,
+    pub lights: Vec<Light>,
+    pub occluders: Vec<Vec<V2>>,
+    rings: u32,
+    targets: Option<(RenderTarget, RenderTarget)>,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lighting {
+    pub fn new() -> Self {
+        Self {
+            ambient: Color::rgb(16, 16, 24),
+            lights: Vec::new(),
+            occluders: Vec::new(),
+            rings: 24,
+            targets: None,
+        }
+    }
+
+    /// The color of the unlit areas, black is complete darkness
+    pub fn with_ambient(mut self, ambient: Color) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    /// The falloff is drawn as this many concentric circles, more is smoother and slower
+    pub fn with_rings(mut self, rings: u32) -> Self {
+        self.rings = rings.max(1);
+        self
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn add_occluder(&mut self, polygon: Vec<V2>) {
+        self.occluders.push(polygon);
+    }
+
+    /// Removes all the lights and occluders
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.occluders.clear();
+    }
+
+    fn targets(&mut self, surface: &Surface) -> &(RenderTarget, RenderTarget) {
+        let size = surface.size();
+        let (width, height) = (size.x.ceil().max(1.0) as u32, size.y.ceil().max(1.0) as u32);
+        match &self.targets {
+            Some((map, _)) if map.width() == width && map.height() == height => {}
+            _ => {
+                self.targets = Some((
+                    surface.create_target(width, height),
+                    surface.create_target(width, height),
+                ))
+            }
+        }
+        self.targets.as_ref().unwrap()
+    }
+
+    /// Renders the light map and queues it to be multiplied over the world layer
+    pub fn submit(&mut self, surface: &Surface) {
+        let size = surface.size();
+        let screen = Rect::new(0.0, 0.0, size.x, size.y);
+        let (visible_min, visible_max) = surface.camera().visible_rect(size);
+        let visible = Rect::from_corners(visible_min, visible_max);
+        let camera = surface.camera().clone();
+        let (ambient, rings) = (self.ambient, self.rings);
+        let lights: Vec<_> = self
+            .lights
+            .iter()
+            .filter(|light| light.bounds().intersects(&visible))
+            .cloned()
+            .collect();
+        let (map, scratch) = self.targets(surface).clone();
+
+        map.clear_with(ambient);
+        map.set_transform(Transform::identity());
+        camera.apply(&*map, size);
+        scratch.set_transform(Transform::identity());
+        camera.apply(&*scratch, size);
+
+        for light in &lights {
+            scratch.clear();
+            draw_light(&scratch, light, rings);
+            scratch.set_blend_mode(BlendMode::DestinationOut);
+            scratch.fill_color(Color::BLACK);
+            for occluder in &self.occluders {
+                fill_shadow(&scratch, light, occluder);
+            }
+            scratch.set_blend_mode(BlendMode::SourceOver);
+
+            map.save();
+            map.set_transform(Transform::identity());
+            map.set_blend_mode(BlendMode::Lighter);
+            map.draw_image(&scratch.image(), screen, screen);
+            map.restore();
+        }
+
+        let image = map.image();
+        let viewport = surface.viewport();
+        surface.submit(Layer::World, i32::MAX, move |renderer| {
+            viewport.apply(renderer);
+            renderer.set_blend_mode(BlendMode::Multiply);
+            renderer.draw_image(&image, screen, screen);
+        });
+    }
+}
+
+fn draw_light(target: &RenderTarget, light: &Light, rings: u32) {
+    target.save();
+    if let Some((direction, spread)) = light.cone {
+        target.clip_path(&Path::pie(light.pos, light.radius, direction - spread, direction + spread));
+    }
+    target.set_blend_mode(BlendMode::Lighter);
+    target.fill_color(light.color);
+    // the discs add up, so that the sum at any distance matches the falloff
+    let intensity = |k: f64| light.intensity * ((k + 0.5) / rings as f64).powf(light.falloff);
+    let mut previous = 0.0;
+    for k in 0..rings {
+        let current = intensity(k as f64);
+        target.set_global_alpha((current - previous).clamp(0.0, 1.0));
+        target.fill_circle(light.pos, light.radius * (1.0 - k as f64 / rings as f64));
+        previous = current;
+    }
+    target.restore();
+}
+
+fn fill_shadow(target: &RenderTarget, light: &Light, polygon: &[V2]) {
+    if polygon.len() < 2 {
+        return;
+    }
+    // far enough for the projected edges to stay outside of the light
+    let far = light.radius * 8.0;
+    let project = |p: V2| {
+        let dir = p - light.pos;
+        let len = dir.norm();
+        if len <= f64::EPSILON {
+            p
+        } else {
+            p + dir / len * far
+        }
+    };
+    // filled one by one, as the front and back edges wind in opposite directions
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (a_far, b_far) = (project(a), project(b));
+        target.begin_path();
+        target.move_to(a.x, a.y);
+        target.line_to(b.x, b.y);
+        target.line_to(b_far.x, b_far.y);
+        target.line_to(a_far.x, a_far.y);
+        target.close_path();
+        target.fill(FillRule::NonZero);
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt::{Debug, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    render::{Image, Rect, Renderer},
+    sprite::{Sprite, Spritesheet},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SliceMode {
+    Stretch,
+    /// Repeated at the original size, the last ones are cut off
+    Tile,
+}
+
+/// A sprite cut into a 3x3 grid by the insets, the corners keep their size
+/// while the edges and the center stretch or tile to fill the rest.
+#[derive(Clone)]
+pub struct NineSlice {
+    spritesheet: Spritesheet,
+    region: Rect,
+    // left, top, right, bottom
+    insets: [f64; 4],
+    edges: SliceMode,
+    center: SliceMode,
+    scale: f64,
+}
+
+impl Debug for NineSlice {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("NineSlice")
+            .field("region", &self.region)
+            .field("insets", &self.insets)
+            .field("edges", &self.edges)
+            .field("center", &self.center)
+            .field("scale", &self.scale)
+            .finish()
+    }
+}
+
+impl NineSlice {
+    pub fn new(sprite: &Sprite, left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        Self {
+            spritesheet: sprite.spritesheet().clone(),
+            region: sprite.rect(),
+            insets: [left as f64, top as f64, right as f64, bottom as f64],
+            edges: SliceMode::Stretch,
+            center: SliceMode::Stretch,
+            scale: 1.0,
+        }
+    }
+
+    pub fn with_edges(mut self, edges: SliceMode) -> Self {
+        self.edges = edges;
+        self
+    }
+
+    pub fn with_center(mut self, center: SliceMode) -> Self {
+        self.center = center;
+        self
+    }
+
+    /// Of the corners and the tiles, for pixel art
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        assert!(scale > 0.0, "Nine-slice scale must be positive, got {}", scale);
+        self.scale = scale;
+        self
+    }
+
+    /// The smallest size it can be drawn at without squishing the corners
+    pub fn min_size(&self) -> (f64, f64) {
+        let [left, top, right, bottom] = self.insets;
+        ((left + right) * self.scale, (top + bottom) * self.scale)
+    }
+
+    pub fn draw(&self, dst: Rect) {
+        let image = match self.spritesheet.image() {
+            Some(image) => image,
+            None => return,
+        };
+        let renderer = self.spritesheet.surface().renderer();
+        let [left, top, right, bottom] = self.insets;
+        let src = self.region;
+
+        // when it's too small the corners shrink to fit
+        let (min_w, min_h) = self.min_size();
+        let sx = self.scale * if min_w > dst.w { dst.w / min_w } else { 1.0 };
+        let sy = self.scale * if min_h > dst.h { dst.h / min_h } else { 1.0 };
+
+        let src_xs = [src.x, src.x + left, src.x + src.w - right, src.x + src.w];
+        let src_ys = [src.y, src.y + top, src.y + src.h - bottom, src.y + src.h];
+        let dst_xs = [dst.x, dst.x + left * sx, dst.x + dst.w - right * sx, dst.x + dst.w];
+        let dst_ys = [dst.y, dst.y + top * sy, dst.y + dst.h - bottom * sy, dst.y + dst.h];
+
+        for row in 0..3 {
+            for col in 0..3 {
+                let s = Rect::new(src_xs[col], src_ys[row], src_xs[col + 1] - src_xs[col], src_ys[row + 1] - src_ys[row]);
+                let d = Rect::new(dst_xs[col], dst_ys[row], dst_xs[col + 1] - dst_xs[col], dst_ys[row + 1] - dst_ys[row]);
+                if s.w <= 0.0 || s.h <= 0.0 || d.w <= 0.0 || d.h <= 0.0 {
+                    continue;
+                }
+                let mode = match (col, row) {
+                    (1, 1) => self.center,
+                    (1, _) | (_, 1) => self.edges,
+                    _ => SliceMode::Stretch,
+                };
+                match mode {
+                    SliceMode::Stretch => renderer.draw_image(&image, s, d),
+                    SliceMode::Tile => tile(&*renderer, &image, s, d, sx, sy),
+                }
+            }
+        }
+    }
+}
+
+fn tile(renderer: &dyn Renderer, image: &Image, src: Rect, dst: Rect, sx: f64, sy: f64) {
+    let (tile_w, tile_h) = (src.w * sx, src.h * sy);
+    // would never advance
+    if tile_w <= 0.0 || tile_h <= 0.0 {
+        return;
+    }
+    let mut y = 0.0;
+    while y < dst.h {
+        let h = tile_h.min(dst.h - y);
+        let mut x = 0.0;
+        while x < dst.w {
+            let w = tile_w.min(dst.w - x);
+            renderer.draw_image(
+                image,
+                Rect::new(src.x, src.y, w / sx, h / sy),
+                Rect::new(dst.x + x, dst.y + y, w, h),
+            );
+            x += tile_w;
+        }
+        y += tile_h;
+    }
+}
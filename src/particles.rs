@@ -0,0 +1,631 @@
+use std::{
+    convert::TryFrom,
+    f64::consts::{PI, TAU},
+    fmt::{Debug, Formatter},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::SpriteBatch,
+    color::Color,
+    render::{FillRule, Layer, Renderer},
+    sprite::{DrawParams, Sprite},
+    surface::Surface,
+    Context, Game, V2, v2,
+};
+
+/// A random value between the two, in the data files it's either
+/// a single number or a `[min, max]` pair
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RangeRepr", into = "[f64; 2]")]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RangeRepr {
+    Value(f64),
+    Pair([f64; 2]),
+}
+
+impl From<RangeRepr> for Range {
+    fn from(repr: RangeRepr) -> Self {
+        match repr {
+            RangeRepr::Value(value) => Range::fixed(value),
+            RangeRepr::Pair([min, max]) => Range::new(min, max),
+        }
+    }
+}
+
+impl From<Range> for [f64; 2] {
+    fn from(range: Range) -> Self {
+        [range.min, range.max]
+    }
+}
+
+impl From<f64> for Range {
+    fn from(value: f64) -> Self {
+        Range::fixed(value)
+    }
+}
+
+impl From<(f64, f64)> for Range {
+    fn from((min, max): (f64, f64)) -> Self {
+        Range::new(min, max)
+    }
+}
+
+impl Range {
+    pub const fn new(min: f64, max: f64) -> Self {
+        Self { min, max }
+    }
+
+    pub const fn fixed(value: f64) -> Self {
+        Self::new(value, value)
+    }
+
+    fn sample(&self, rng: &mut Rng) -> f64 {
+        self.min + (self.max - self.min) * rng.next()
+    }
+}
+
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(self, other: Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(self, other: Self, t: f64) -> Self {
+        Color::lerp(self, other, t)
+    }
+}
+
+/// Values over the lifetime of a particle, linearly interpolated between
+/// the keys which are at `0..1` of the lifetime.
+/// In the data files it's either a single value or a list of `[t, value]` pairs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "CurveRepr<T>", into = "Vec<(f64, T)>")]
+#[serde(bound(serialize = "T: Serialize + Clone", deserialize = "T: Deserialize<'de>"))]
+pub struct Curve<T> {
+    keys: Vec<(f64, T)>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CurveRepr<T> {
+    Constant(T),
+    Keys(Vec<(f64, T)>),
+}
+
+impl<T> TryFrom<CurveRepr<T>> for Curve<T> {
+    type Error = &'static str;
+
+    fn try_from(repr: CurveRepr<T>) -> Result<Self, Self::Error> {
+        match repr {
+            CurveRepr::Constant(value) => Ok(Curve { keys: vec![(0.0, value)] }),
+            CurveRepr::Keys(keys) if keys.is_empty() => Err("a curve needs at least one key"),
+            CurveRepr::Keys(mut keys) => {
+                keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Ok(Curve { keys })
+            }
+        }
+    }
+}
+
+impl<T> From<Curve<T>> for Vec<(f64, T)> {
+    fn from(curve: Curve<T>) -> Self {
+        curve.keys
+    }
+}
+
+impl<T: Lerp> From<T> for Curve<T> {
+    fn from(value: T) -> Self {
+        Self::constant(value)
+    }
+}
+
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Self { keys: vec![(0.0, value)] }
+    }
+
+    /// From the start to the end of the lifetime
+    pub fn linear(from: T, to: T) -> Self {
+        Self {
+            keys: vec![(0.0, from), (1.0, to)],
+        }
+    }
+
+    /// Adds a key at `t` in `0..1`, keeping them sorted
+    pub fn with_key(mut self, t: f64, value: T) -> Self {
+        let index = self.keys.iter().position(|(k, _)| *k > t).unwrap_or(self.keys.len());
+        self.keys.insert(index, (t, value));
+        self
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.keys.len() < 2
+    }
+
+    pub fn sample(&self, t: f64) -> T {
+        let next = self.keys.iter().position(|(k, _)| *k > t);
+        match next {
+            Some(0) => self.keys[0].1,
+            Some(i) => {
+                let ((t0, a), (t1, b)) = (self.keys[i - 1], self.keys[i]);
+                a.lerp(b, (t - t0) / (t1 - t0))
+            }
+            // the constructors and the deserializer never make empty ones
+            None => self.keys.last().expect("Curve has no keys").1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticleShape {
+    /// Size is the diameter
+    Circle,
+    Square,
+}
+
+/// Everything about how the particles look and move, made to be loaded from
+/// data files, everything is optional there:
+///
+/// ```json
+/// {
+///     "burst": 30, "lifetime": [0.3, 0.6], "speed": [40, 120],
+///     "gravity": [0, 200], "damping": 2,
+///     "size": [[0, 6], [1, 0]], "color": [[0, "#fff6a0"], [0.5, "#ff6000"], [1, "#40404000"]]
+/// }
+/// ```
+///
+/// Angles are in radians and the times are in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmitterConfig {
+    /// Particles per second while emitting, 0 for bursts only,
+    /// which stop emitting right after the burst
+    pub rate: f64,
+    /// Emitted at once when the emitter starts
+    pub burst: u32,
+    /// How long it emits after starting, forever if not set
+    pub duration: Option<f64>,
+    /// New ones are not emitted when there are this many
+    pub max_particles: usize,
+    pub lifetime: Range,
+    /// Size of the rectangle around the emitter position where particles appear
+    pub area: V2,
+    /// The middle of the cone the particles fly out in, the default is up
+    pub direction: f64,
+    /// The full angle of the cone
+    pub spread: f64,
+    pub speed: Range,
+    /// Along the direction the particle flies in, negative slows it down
+    pub acceleration: Range,
+    pub gravity: V2,
+    /// Velocity falloff, in 1/s
+    pub damping: f64,
+    pub rotation: Range,
+    pub angular_velocity: Range,
+    /// The diameter of the shape, or the scale of the sprite
+    pub size: Curve<f64>,
+    /// Multiplies the sprites, only 16 distinct steps of the curve are used
+    /// for them as each tint is a cached copy of the sprite
+    pub color: Curve<Color>,
+    /// Multiplied with the color alpha
+    pub alpha: Curve<f64>,
+    pub shape: ParticleShape,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            rate: 0.0,
+            burst: 0,
+            duration: None,
+            max_particles: 500,
+            lifetime: Range::fixed(1.0),
+            area: v2![0.0, 0.0],
+            direction: -PI / 2.0,
+            spread: TAU,
+            speed: Range::fixed(50.0),
+            acceleration: Range::fixed(0.0),
+            gravity: v2![0.0, 0.0],
+            damping: 0.0,
+            rotation: Range::fixed(0.0),
+            angular_velocity: Range::fixed(0.0),
+            size: Curve::constant(4.0),
+            color: Curve::constant(Color::WHITE),
+            alpha: Curve::constant(1.0),
+            shape: ParticleShape::Circle,
+        }
+    }
+}
+
+impl EmitterConfig {
+    pub fn parse(json: &str) -> Option<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| log::error!("Failed to parse the emitter config: {}", e))
+            .ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Particle {
+    pub pos: V2,
+    pub velocity: V2,
+    pub acceleration: f64,
+    pub rotation: f64,
+    pub angular_velocity: f64,
+    pub age: f64,
+    pub lifetime: f64,
+}
+
+impl Particle {
+    /// How far it is through its lifetime, `0..1`
+    pub fn progress(&self) -> f64 {
+        if self.lifetime > 0.0 {
+            (self.age / self.lifetime).min(1.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+// xorshift, good enough for particles and the same on every run with the same seed
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Emits, moves and draws particles in world space, so moving the
+/// emitter doesn't drag the already emitted ones along
+#[derive(Clone)]
+pub struct ParticleEmitter {
+    pub config: EmitterConfig,
+    pub position: V2,
+    sprite: Option<Sprite>,
+    particles: Vec<Particle>,
+    emitting: bool,
+    // the burst waits for the next update, so the builders apply to it
+    burst_pending: bool,
+    time: f64,
+    // fractional particles left from the last update
+    pending: f64,
+    rng: Rng,
+}
+
+impl Debug for ParticleEmitter {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("ParticleEmitter")
+            .field("position", &self.position)
+            .field("particles", &self.particles.len())
+            .field("emitting", &self.emitting)
+            .finish()
+    }
+}
+
+impl ParticleEmitter {
+    /// Starts emitting with the first update
+    pub fn new(config: EmitterConfig) -> Self {
+        let mut emitter = Self {
+            config,
+            position: v2![0.0, 0.0],
+            sprite: None,
+            particles: Vec::new(),
+            emitting: false,
+            burst_pending: false,
+            time: 0.0,
+            pending: 0.0,
+            rng: Rng(0x2545_f491_4f6c_dd1d),
+        };
+        emitter.start();
+        emitter
+    }
+
+    pub fn with_position(mut self, position: V2) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Particles are drawn as the sprite centered on them instead of the shape
+    pub fn with_sprite(mut self, sprite: Sprite) -> Self {
+        self.sprite = Some(sprite);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        self.rng = Rng(seed.max(1));
+        self
+    }
+
+    /// Emits the configured burst on the next update and starts the continuous emission over
+    pub fn start(&mut self) {
+        self.emitting = true;
+        self.burst_pending = true;
+        self.time = 0.0;
+        self.pending = 0.0;
+    }
+
+    /// Stops emitting, the particles that are there live on
+    pub fn stop(&mut self) {
+        self.emitting = false;
+    }
+
+    pub fn is_emitting(&self) -> bool {
+        self.emitting
+    }
+
+    /// Not emitting and all of the particles are gone, so it can be dropped
+    pub fn is_finished(&self) -> bool {
+        !self.emitting && !self.burst_pending && self.particles.is_empty()
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    pub fn burst(&mut self, count: u32) {
+        for _ in 0..count {
+            self.emit();
+        }
+    }
+
+    fn emit(&mut self) {
+        if self.particles.len() >= self.config.max_particles {
+            return;
+        }
+        let config = &self.config;
+        let rng = &mut self.rng;
+        let angle = config.direction + (rng.next() - 0.5) * config.spread;
+        let offset = v2![(rng.next() - 0.5) * config.area.x, (rng.next() - 0.5) * config.area.y];
+        let particle = Particle {
+            pos: self.position + offset,
+            velocity: v2![angle.cos(), angle.sin()] * config.speed.sample(rng),
+            acceleration: config.acceleration.sample(rng),
+            rotation: config.rotation.sample(rng),
+            angular_velocity: config.angular_velocity.sample(rng),
+            age: 0.0,
+            lifetime: config.lifetime.sample(rng),
+        };
+        self.particles.push(particle);
+    }
+
+    pub fn on_update<G: Game>(&mut self, context: &Context<G>) {
+        self.update(context.delta_time());
+    }
+
+    /// Advances by the time in seconds
+    pub fn update(&mut self, delta_time: f64) {
+        if self.burst_pending {
+            self.burst_pending = false;
+            self.burst(self.config.burst);
+        }
+        let config = &self.config;
+        let damping = (-config.damping * delta_time).exp();
+        self.particles.retain_mut(|p| {
+            p.age += delta_time;
+            if p.age >= p.lifetime {
+                return false;
+            }
+            let speed = p.velocity.norm();
+            if speed > 0.0 {
+                p.velocity += p.velocity / speed * p.acceleration * delta_time;
+            }
+            p.velocity += config.gravity * delta_time;
+            p.velocity *= damping;
+            p.pos += p.velocity * delta_time;
+            p.rotation += p.angular_velocity * delta_time;
+            true
+        });
+
+        if !self.emitting {
+            return;
+        }
+        if config.rate <= 0.0 {
+            // nothing more is coming, so it can finish once the burst is gone
+            self.emitting = false;
+            return;
+        }
+        // the part of the step that was still within the duration
+        let mut emit_time = delta_time;
+        if let Some(duration) = config.duration {
+            emit_time = emit_time.min(duration - self.time).max(0.0);
+            if self.time + delta_time >= duration {
+                self.emitting = false;
+            }
+        }
+        self.time += delta_time;
+        self.pending += config.rate * emit_time;
+        while self.pending >= 1.0 {
+            self.pending -= 1.0;
+            self.emit();
+        }
+    }
+
+    /// Draws onto the surface with its current transform
+    pub fn draw(&self, surface: &Surface) {
+        self.draw_into(&*surface.renderer());
+    }
+
+    /// Draws into the layer at the end of the frame
+    pub fn submit(&self, surface: &Surface, layer: Layer, z: i32) {
+        let emitter = self.clone();
+        surface.submit(layer, z, move |renderer| emitter.draw_into(renderer));
+    }
+
+    pub fn draw_into(&self, renderer: &dyn Renderer) {
+        let config = &self.config;
+        if let Some(sprite) = &self.sprite {
+            let mut batch = SpriteBatch::new();
+            for p in &self.particles {
+                let t = p.progress();
+                let tint = if config.color.is_constant() {
+                    config.color.sample(0.0)
+                } else {
+                    config.color.sample((t * 16.0).floor() / 16.0)
+                };
+                let params = DrawParams::new()
+                    .centered()
+                    .with_rotation(p.rotation)
+                    .with_scale(config.size.sample(t))
+                    .with_alpha((config.alpha.sample(t) * tint.a).clamp(0.0, 1.0))
+                    .with_tint(tint.with_alpha(1.0));
+                batch.add_with(sprite, p.pos.x, p.pos.y, &params);
+            }
+            batch.flush_into(renderer);
+            return;
+        }
+        renderer.save();
+        for p in &self.particles {
+            let t = p.progress();
+            let size = config.size.sample(t);
+            let color = config.color.sample(t);
+            let alpha = config.alpha.sample(t) * color.a;
+            if size <= 0.0 || alpha <= 0.0 {
+                continue;
+            }
+            renderer.set_fill_style(&color.with_alpha(alpha.min(1.0)).to_css());
+            match config.shape {
+                ParticleShape::Circle => {
+                    renderer.begin_path();
+                    renderer.arc(p.pos.x, p.pos.y, size / 2.0, 0.0, TAU, false);
+                    renderer.fill(FillRule::NonZero);
+                }
+                ParticleShape::Square => {
+                    let half = size / 2.0;
+                    let (sin, cos) = p.rotation.sin_cos();
+                    let corners = [v2![-half, -half], v2![half, -half], v2![half, half], v2![-half, half]];
+                    renderer.begin_path();
+                    for (i, c) in corners.iter().enumerate() {
+                        let point = p.pos + v2![c.x * cos - c.y * sin, c.x * sin + c.y * cos];
+                        if i == 0 {
+                            renderer.move_to(point.x, point.y);
+                        } else {
+                            renderer.line_to(point.x, point.y);
+                        }
+                    }
+                    renderer.close_path();
+                    renderer.fill(FillRule::NonZero);
+                }
+            }
+        }
+        renderer.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_sample() {
+        let curve = Curve::linear(10.0, 20.0).with_key(0.5, 0.0);
+        assert_eq!(curve.sample(0.0), 10.0);
+        assert_eq!(curve.sample(0.25), 5.0);
+        assert_eq!(curve.sample(0.75), 10.0);
+        assert_eq!(curve.sample(1.0), 20.0);
+
+        let late = Curve { keys: vec![(0.2, 1.0), (0.6, 3.0)] };
+        assert_eq!(late.sample(0.0), 1.0);
+        assert_eq!(late.sample(0.4), 2.0);
+        assert_eq!(late.sample(2.0), 3.0);
+
+        assert_eq!(Curve::constant(7.0).sample(0.5), 7.0);
+    }
+
+    #[test]
+    fn range_serde() {
+        let fixed: Range = serde_json::from_str("3").unwrap();
+        assert_eq!(fixed, Range::fixed(3.0));
+        let pair: Range = serde_json::from_str("[1, 2.5]").unwrap();
+        assert_eq!(pair, Range::new(1.0, 2.5));
+        assert_eq!(serde_json::to_string(&fixed).unwrap(), "[3.0,3.0]");
+        assert!(serde_json::from_str::<Range>("[1, 2, 3]").is_err());
+        assert!(serde_json::from_str::<Range>("\"big\"").is_err());
+    }
+
+    #[test]
+    fn curve_serde() {
+        let constant: Curve<f64> = serde_json::from_str("5").unwrap();
+        assert_eq!(constant, Curve::constant(5.0));
+        let keys: Curve<f64> = serde_json::from_str("[[1, 3], [0, 1]]").unwrap();
+        assert_eq!(keys, Curve::linear(1.0, 3.0));
+        let colors: Curve<Color> = serde_json::from_str(r##"[[0, "#ffffff"], [1, "#000000"]]"##).unwrap();
+        assert_eq!(colors.sample(0.0), Color::WHITE);
+
+        let empty = serde_json::from_str::<Curve<f64>>("[]").unwrap_err();
+        assert!(empty.to_string().contains("at least one key"), "{}", empty);
+        assert!(EmitterConfig::parse(r#"{ "size": [] }"#).is_none());
+    }
+
+    fn config(json: &str) -> EmitterConfig {
+        EmitterConfig::parse(json).expect("Bad test config")
+    }
+
+    #[test]
+    fn burst_only_finishes() {
+        let mut emitter = ParticleEmitter::new(config(r#"{ "burst": 10, "lifetime": 1 }"#));
+        assert!(!emitter.is_finished());
+        emitter.update(0.1);
+        assert_eq!(emitter.particles().len(), 10);
+        assert!(!emitter.is_emitting());
+        emitter.update(0.5);
+        assert_eq!(emitter.particles().len(), 10);
+        emitter.update(0.5);
+        assert!(emitter.particles().is_empty());
+        assert!(emitter.is_finished());
+    }
+
+    #[test]
+    fn rate_over_duration() {
+        let mut emitter = ParticleEmitter::new(config(r#"{ "rate": 8, "duration": 1, "lifetime": 100 }"#));
+        for _ in 0..8 {
+            emitter.update(0.25);
+        }
+        assert_eq!(emitter.particles().len(), 8);
+        assert!(!emitter.is_emitting());
+        assert!(!emitter.is_finished());
+    }
+
+    #[test]
+    fn burst_and_rate_forever() {
+        let json = r#"{ "rate": 4, "burst": 5, "lifetime": 100, "max_particles": 20 }"#;
+        let mut emitter = ParticleEmitter::new(config(json));
+        for _ in 0..4 {
+            emitter.update(0.5);
+        }
+        assert_eq!(emitter.particles().len(), 5 + 8);
+        assert!(emitter.is_emitting());
+        for _ in 0..10 {
+            emitter.update(0.5);
+        }
+        assert_eq!(emitter.particles().len(), 20);
+
+        emitter.stop();
+        emitter.clear();
+        emitter.update(0.5);
+        assert!(emitter.is_finished());
+    }
+}
@@ -0,0 +1,340 @@
+use js_sys::Array;
+use wasm_bindgen::{JsCast, prelude::*};
+use web_sys::{CanvasGradient, CanvasPattern, CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+
+use crate::{
+    render::{
+        BlendMode, FillRule, Gradient, GradientKind, Image, Paint, PaintKind, Pattern, Pixmap, Rect, Renderer, TextAlign,
+        TextBaseline, Transform,
+    },
+    V2, v2,
+};
+
+/// The [Renderer] that draws into a canvas 2D context
+#[derive(Debug, Clone)]
+pub struct Canvas2D {
+    context: CanvasRenderingContext2d,
+}
+
+impl Canvas2D {
+    pub fn new(context: CanvasRenderingContext2d) -> Self {
+        Self { context }
+    }
+
+    /// The raw context, for the things the [Renderer] does not cover
+    pub fn context(&self) -> &CanvasRenderingContext2d {
+        &self.context
+    }
+
+    // patterns of images that are not loaded yet fail and are tried again next time
+    fn paint_style(&self, paint: &Paint) -> Option<JsValue> {
+        match paint.kind() {
+            PaintKind::Color(color) => Some(color.to_css().into()),
+            _ => paint.cached(|kind| match kind {
+                PaintKind::Gradient(gradient) => Some(self.create_gradient(gradient).into()),
+                PaintKind::Pattern(pattern) => self.create_pattern(pattern).map(Into::into),
+                PaintKind::Color(_) => unreachable!(),
+            }),
+        }
+    }
+
+    fn create_gradient(&self, gradient: &Gradient) -> CanvasGradient {
+        #[wasm_bindgen(inline_js = "export function conic(ctx, a, x, y) { return ctx.createConicGradient(a, x, y) }")]
+        extern "C" {
+            fn conic(ctx: &CanvasRenderingContext2d, angle: f64, x: f64, y: f64) -> CanvasGradient;
+        }
+        let result = match gradient.kind {
+            GradientKind::Linear { from, to } => self.context.create_linear_gradient(from.x, from.y, to.x, to.y),
+            GradientKind::Radial {
+                from,
+                from_radius,
+                to,
+                to_radius,
+            } => self
+                .context
+                .create_radial_gradient(from.x, from.y, from_radius, to.x, to.y, to_radius)
+                .expect("Failed to create a radial gradient"),
+            GradientKind::Conic { center, angle } => conic(&self.context, angle, center.x, center.y),
+        };
+        for (offset, color) in &gradient.stops {
+            result.add_color_stop(*offset as f32, &color.to_css()).unwrap();
+        }
+        result
+    }
+
+    fn create_pattern(&self, pattern: &Pattern) -> Option<CanvasPattern> {
+        #[wasm_bindgen(inline_js = "export function set_transform(p, m) { p.setTransform(new DOMMatrix(Array.from(m))) }")]
+        extern "C" {
+            fn set_transform(pattern: &CanvasPattern, matrix: &[f64]);
+        }
+        let repeat = pattern.repeat.as_str();
+        let result = match &pattern.image {
+            Image::Element(element) => self.context.create_pattern_with_html_image_element(element, repeat),
+            Image::Canvas(canvas) => self.context.create_pattern_with_html_canvas_element(canvas, repeat),
+            Image::Pixels(pixmap) => self
+                .context
+                .create_pattern_with_html_canvas_element(&pixmap_to_canvas(pixmap), repeat),
+        };
+        let result = result.ok().flatten()?;
+        let t = pattern.transform;
+        set_transform(&result, &[t.a, t.b, t.c, t.d, t.e, t.f]);
+        Some(result)
+    }
+}
+
+pub(crate) fn create_canvas(width: u32, height: u32) -> HtmlCanvasElement {
+    let canvas = crate::document()
+        .create_element("canvas")
+        .map_err(|_| ())
+        .and_then(|e| e.dyn_into::<HtmlCanvasElement>().map_err(|_| ()))
+        .expect("Failed to create canvas");
+    canvas.set_width(width);
+    canvas.set_height(height);
+    canvas
+}
+
+pub(crate) fn context_2d(canvas: &HtmlCanvasElement) -> CanvasRenderingContext2d {
+    canvas
+        .get_context("2d")
+        .ok()
+        .flatten()
+        .and_then(|obj| obj.dyn_into::<CanvasRenderingContext2d>().ok())
+        .expect("No canvas 2d context?")
+}
+
+/// Puts the pixels into a new canvas
+pub(crate) fn pixmap_to_canvas(pixmap: &Pixmap) -> HtmlCanvasElement {
+    // through js because the web-sys constructor wants a mutable slice
+    #[wasm_bindgen(inline_js = "export function image_data(d, w, h) { return new ImageData(new Uint8ClampedArray(d), w, h) }")]
+    extern "C" {
+        fn image_data(data: &[u8], width: u32, height: u32) -> ImageData;
+    }
+    let canvas = create_canvas(pixmap.width(), pixmap.height());
+    let data = image_data(pixmap.data(), pixmap.width(), pixmap.height());
+    context_2d(&canvas)
+        .put_image_data(&data, 0.0, 0.0)
+        .expect("Failed to put image data");
+    canvas
+}
+
+/// Reads the pixels back, fails for canvases that had images from other origins drawn on them
+pub(crate) fn canvas_to_pixmap(canvas: &HtmlCanvasElement) -> Option<Pixmap> {
+    let (width, height) = (canvas.width(), canvas.height());
+    let data = context_2d(canvas)
+        .get_image_data(0.0, 0.0, width as f64, height as f64)
+        .map_err(|e| log::error!("Failed to read the pixels back: {:?}", e))
+        .ok()?;
+    Some(Pixmap::from_rgba(width, height, data.data().0))
+}
+
+impl Renderer for Canvas2D {
+    fn size(&self) -> V2 {
+        self.context
+            .canvas()
+            .map(|c| v2![c.width() as f64, c.height() as f64])
+            .unwrap_or_else(|| v2![0.0, 0.0])
+    }
+
+    fn save(&self) {
+        self.context.save();
+    }
+
+    fn restore(&self) {
+        self.context.restore();
+    }
+
+    fn set_transform(&self, t: Transform) {
+        self.context.set_transform(t.a, t.b, t.c, t.d, t.e, t.f).unwrap();
+    }
+
+    fn get_transform(&self) -> Transform {
+        #[wasm_bindgen(inline_js = "export function get_transform(ctx) { const m = ctx.getTransform(); return [m.a, m.b, m.c, m.d, m.e, m.f] }")]
+        extern "C" {
+            fn get_transform(ctx: &CanvasRenderingContext2d) -> Box<[f64]>;
+        }
+        match *get_transform(&self.context) {
+            [a, b, c, d, e, f] => Transform::new(a, b, c, d, e, f),
+            _ => unreachable!(),
+        }
+    }
+
+    fn transform(&self, t: Transform) {
+        self.context.transform(t.a, t.b, t.c, t.d, t.e, t.f).unwrap();
+    }
+
+    fn translate(&self, x: f64, y: f64) {
+        self.context.translate(x, y).unwrap();
+    }
+
+    fn rotate(&self, angle: f64) {
+        self.context.rotate(angle).unwrap();
+    }
+
+    fn scale(&self, x: f64, y: f64) {
+        self.context.scale(x, y).unwrap();
+    }
+
+    fn set_fill_style(&self, style: &str) {
+        self.context.set_fill_style(&style.into());
+    }
+
+    fn set_stroke_style(&self, style: &str) {
+        self.context.set_stroke_style(&style.into());
+    }
+
+    fn set_fill_paint(&self, paint: &Paint) {
+        if let Some(style) = self.paint_style(paint) {
+            self.context.set_fill_style(&style);
+        }
+    }
+
+    fn set_stroke_paint(&self, paint: &Paint) {
+        if let Some(style) = self.paint_style(paint) {
+            self.context.set_stroke_style(&style);
+        }
+    }
+
+    fn set_line_width(&self, width: f64) {
+        self.context.set_line_width(width);
+    }
+
+    fn set_line_dash(&self, pattern: &[f64]) {
+        let array = Array::new_with_length(pattern.len() as u32);
+        for (i, x) in pattern.iter().copied().enumerate() {
+            array.set(i as u32, x.into());
+        }
+        self.context.set_line_dash(&array.into()).unwrap();
+    }
+
+    fn set_global_alpha(&self, alpha: f64) {
+        self.context.set_global_alpha(alpha);
+    }
+
+    fn set_blend_mode(&self, mode: BlendMode) {
+        self.context.set_global_composite_operation(mode.as_str()).unwrap();
+    }
+
+    fn set_image_smoothing(&self, enabled: bool) {
+        self.context.set_image_smoothing_enabled(enabled);
+    }
+
+    fn begin_path(&self) {
+        self.context.begin_path();
+    }
+
+    fn move_to(&self, x: f64, y: f64) {
+        self.context.move_to(x, y);
+    }
+
+    fn line_to(&self, x: f64, y: f64) {
+        self.context.line_to(x, y);
+    }
+
+    fn quadratic_curve_to(&self, cx: f64, cy: f64, x: f64, y: f64) {
+        self.context.quadratic_curve_to(cx, cy, x, y);
+    }
+
+    fn bezier_curve_to(&self, c1x: f64, c1y: f64, c2x: f64, c2y: f64, x: f64, y: f64) {
+        self.context.bezier_curve_to(c1x, c1y, c2x, c2y, x, y);
+    }
+
+    fn arc(&self, x: f64, y: f64, radius: f64, start: f64, end: f64, anticlockwise: bool) {
+        self.context
+            .arc_with_anticlockwise(x, y, radius, start, end, anticlockwise)
+            .unwrap();
+    }
+
+    fn ellipse(&self, center: V2, radii: V2, rotation: f64, start: f64, end: f64, anticlockwise: bool) {
+        self.context
+            .ellipse_with_anticlockwise(center.x, center.y, radii.x, radii.y, rotation, start, end, anticlockwise)
+            .unwrap();
+    }
+
+    fn rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.context.rect(x, y, w, h);
+    }
+
+    fn close_path(&self) {
+        self.context.close_path();
+    }
+
+    fn fill(&self, rule: FillRule) {
+        match rule {
+            FillRule::NonZero => self.context.fill(),
+            FillRule::EvenOdd => self.context.fill_with_canvas_winding_rule(web_sys::CanvasWindingRule::Evenodd),
+        }
+    }
+
+    fn stroke(&self) {
+        self.context.stroke();
+    }
+
+    fn clip(&self, rule: FillRule) {
+        match rule {
+            FillRule::NonZero => self.context.clip(),
+            FillRule::EvenOdd => self.context.clip_with_canvas_winding_rule(web_sys::CanvasWindingRule::Evenodd),
+        }
+    }
+
+    fn fill_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.context.fill_rect(x, y, w, h);
+    }
+
+    fn stroke_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.context.stroke_rect(x, y, w, h);
+    }
+
+    fn clear_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.context.clear_rect(x, y, w, h);
+    }
+
+    fn draw_image(&self, image: &Image, src: Rect, dst: Rect) {
+        let result = match image {
+            Image::Element(element) => self
+                .context
+                .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    element, src.x, src.y, src.w, src.h, dst.x, dst.y, dst.w, dst.h,
+                ),
+            Image::Canvas(canvas) => self
+                .context
+                .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    canvas, src.x, src.y, src.w, src.h, dst.x, dst.y, dst.w, dst.h,
+                ),
+            // slow, upload pixels that are drawn often into a canvas beforehand
+            Image::Pixels(pixmap) => self
+                .context
+                .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
+                    &pixmap_to_canvas(pixmap),
+                    src.x,
+                    src.y,
+                    src.w,
+                    src.h,
+                    dst.x,
+                    dst.y,
+                    dst.w,
+                    dst.h,
+                ),
+        };
+        result.expect("Failed to draw image");
+    }
+
+    fn set_font(&self, font: &str) {
+        self.context.set_font(font);
+    }
+
+    fn set_text_align(&self, align: TextAlign) {
+        self.context.set_text_align(align.as_str());
+    }
+
+    fn set_text_baseline(&self, baseline: TextBaseline) {
+        self.context.set_text_baseline(baseline.as_str());
+    }
+
+    fn fill_text(&self, text: &str, x: f64, y: f64) {
+        self.context.fill_text(text, x, y).unwrap();
+    }
+
+    fn measure_text(&self, text: &str) -> f64 {
+        self.context.measure_text(text).unwrap().width()
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt::{Debug, Formatter};
+
+use crate::render::{Renderer, Transform};
+
+/// Render layers, drawn in the order they are declared in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Layer {
+    Background,
+    World,
+    Effects,
+    Ui,
+    Debug,
+}
+
+pub(crate) type DrawFn = Box<dyn FnOnce(&dyn Renderer)>;
+
+pub(crate) struct QueuedDraw {
+    layer: Layer,
+    z: i32,
+    transform: Transform,
+    draw: DrawFn,
+}
+
+/// Draws submitted during the frame, see [crate::surface::Surface::submit]
+#[derive(Default)]
+pub(crate) struct DrawQueue {
+    draws: Vec<QueuedDraw>,
+}
+
+impl Debug for DrawQueue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "DrawQueue({} draws)", self.draws.len())
+    }
+}
+
+impl DrawQueue {
+    pub(crate) fn push(&mut self, layer: Layer, z: i32, transform: Transform, draw: DrawFn) {
+        self.draws.push(QueuedDraw {
+            layer,
+            z,
+            transform,
+            draw,
+        });
+    }
+
+    /// Takes the queued draws, sorted by layer and z
+    pub(crate) fn take(&mut self) -> Vec<QueuedDraw> {
+        let mut draws = std::mem::take(&mut self.draws);
+        // stable, so the submission order is kept within the same layer and z
+        draws.sort_by_key(|draw| (draw.layer, draw.z));
+        draws
+    }
+}
+
+impl QueuedDraw {
+    pub(crate) fn run(self, renderer: &dyn Renderer) {
+        renderer.save();
+        renderer.set_transform(self.transform);
+        (self.draw)(renderer);
+        renderer.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{render::SoftwareRenderer, surface::Surface, util::Mut};
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    fn logged(log: &Log, name: &'static str) -> DrawFn {
+        let log = log.clone();
+        Box::new(move |_| log.borrow_mut().push(name))
+    }
+
+    #[test]
+    fn sorted_by_layer_then_z() {
+        let log = Log::default();
+        let mut queue = DrawQueue::default();
+        let identity = Transform::identity();
+        queue.push(Layer::Ui, 0, identity, logged(&log, "ui"));
+        queue.push(Layer::World, 5, identity, logged(&log, "world 5"));
+        queue.push(Layer::Background, 10, identity, logged(&log, "background 10"));
+        queue.push(Layer::World, -1, identity, logged(&log, "world -1"));
+        queue.push(Layer::Debug, -100, identity, logged(&log, "debug"));
+
+        let renderer = SoftwareRenderer::new(1, 1);
+        for draw in queue.take() {
+            draw.run(&renderer);
+        }
+        assert_eq!(*log.borrow(), ["background 10", "world -1", "world 5", "ui", "debug"]);
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn stable_within_the_same_z() {
+        let log = Log::default();
+        let mut queue = DrawQueue::default();
+        for name in ["a", "b", "c", "d"] {
+            queue.push(Layer::World, 1, Transform::identity(), logged(&log, name));
+        }
+        queue.push(Layer::World, 0, Transform::identity(), logged(&log, "first"));
+
+        let renderer = SoftwareRenderer::new(1, 1);
+        for draw in queue.take() {
+            draw.run(&renderer);
+        }
+        assert_eq!(*log.borrow(), ["first", "a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn runs_with_the_captured_transform() {
+        let mut queue = DrawQueue::default();
+        let seen = Rc::new(RefCell::new(None));
+        let moved_seen = seen.clone();
+        let transform = Transform::translation(3.0, 4.0);
+        queue.push(Layer::World, 0, transform, Box::new(move |r| *moved_seen.borrow_mut() = Some(r.get_transform())));
+
+        let renderer = SoftwareRenderer::new(1, 1);
+        for draw in queue.take() {
+            draw.run(&renderer);
+        }
+        assert_eq!(*seen.borrow(), Some(transform));
+        assert_eq!(renderer.get_transform(), Transform::identity());
+    }
+
+    fn resubmit(surface: Mut<Surface>, count: Rc<RefCell<usize>>) {
+        let moved_surface = surface.clone();
+        surface.borrow().submit(Layer::Effects, 0, move |_| {
+            *count.borrow_mut() += 1;
+            resubmit(moved_surface, count);
+        });
+    }
+
+    #[test]
+    fn resubmitting_draws_stop() {
+        let surface = Mut::new(Surface::headless(1, 1));
+        let count = Rc::new(RefCell::new(0));
+        resubmit(surface.clone(), count.clone());
+        surface.borrow().flush_layers();
+        assert_eq!(*count.borrow(), 2);
+
+        // the dropped ones don't leak into the next frame
+        surface.borrow().flush_layers();
+        assert_eq!(*count.borrow(), 2);
+    }
+}
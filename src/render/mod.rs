@@ -0,0 +1,649 @@
+use std::{
+    f64::consts::TAU,
+    fmt::{Debug, Formatter},
+    rc::Rc,
+};
+
+pub(crate) use layer::DrawQueue;
+use serde::{Deserialize, Serialize};
+use web_sys::{HtmlCanvasElement, HtmlImageElement};
+
+use crate::{V2, v2};
+
+pub use canvas::Canvas2D;
+pub(crate) use canvas::pixmap_to_canvas;
+pub use layer::Layer;
+pub use paint::{Gradient, GradientKind, Paint, PaintKind, Pattern, Repeat};
+pub use path::{Path, PathOp};
+pub use record::{DrawCommand, Golden, ImageKey, PaintDesc, Recording, RecordingRenderer, replay};
+pub use software::SoftwareRenderer;
+pub use target::RenderTarget;
+
+mod canvas;
+mod layer;
+mod paint;
+mod path;
+mod record;
+mod software;
+mod target;
+
+/// An affine transform in the canvas order, so that
+/// `x' = a * x + c * y + e` and `y' = b * x + d * y + f`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+    pub e: f64,
+    pub f: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub const fn new(a: f64, b: f64, c: f64, d: f64, e: f64, f: f64) -> Self {
+        Self { a, b, c, d, e, f }
+    }
+
+    pub const fn identity() -> Self {
+        Self::new(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+    }
+
+    pub const fn translation(x: f64, y: f64) -> Self {
+        Self::new(1.0, 0.0, 0.0, 1.0, x, y)
+    }
+
+    pub const fn scaling(x: f64, y: f64) -> Self {
+        Self::new(x, 0.0, 0.0, y, 0.0, 0.0)
+    }
+
+    pub fn rotation(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new(cos, sin, -sin, cos, 0.0, 0.0)
+    }
+
+    /// `self * other`, i.e. `other` is applied first, same as the canvas `transform` call
+    pub fn then(&self, other: &Transform) -> Self {
+        Self {
+            a: self.a * other.a + self.c * other.b,
+            b: self.b * other.a + self.d * other.b,
+            c: self.a * other.c + self.c * other.d,
+            d: self.b * other.c + self.d * other.d,
+            e: self.a * other.e + self.c * other.f + self.e,
+            f: self.b * other.e + self.d * other.f + self.f,
+        }
+    }
+
+    pub fn determinant(&self) -> f64 {
+        self.a * self.d - self.b * self.c
+    }
+
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() <= f64::EPSILON {
+            return None;
+        }
+        Some(Self {
+            a: self.d / det,
+            b: -self.b / det,
+            c: -self.c / det,
+            d: self.a / det,
+            e: (self.c * self.f - self.d * self.e) / det,
+            f: (self.b * self.e - self.a * self.f) / det,
+        })
+    }
+
+    pub fn apply(&self, p: V2) -> V2 {
+        v2![
+            self.a * p.x + self.c * p.y + self.e,
+            self.b * p.x + self.d * p.y + self.f
+        ]
+    }
+
+    pub fn apply_vector(&self, v: V2) -> V2 {
+        v2![self.a * v.x + self.c * v.y, self.b * v.x + self.d * v.y]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl Rect {
+    pub const fn new(x: f64, y: f64, w: f64, h: f64) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub fn from_corners(min: V2, max: V2) -> Self {
+        Self::new(min.x, min.y, max.x - min.x, max.y - min.y)
+    }
+
+    pub fn pos(&self) -> V2 {
+        v2![self.x, self.y]
+    }
+
+    pub fn size(&self) -> V2 {
+        v2![self.w, self.h]
+    }
+
+    pub fn contains(&self, p: V2) -> bool {
+        p.x >= self.x && p.x < self.x + self.w && p.y >= self.y && p.y < self.y + self.h
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+impl FillRule {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FillRule::NonZero => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        }
+    }
+}
+
+/// The canvas `globalCompositeOperation`s that are supported by all renderers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendMode {
+    SourceOver,
+    SourceIn,
+    SourceAtop,
+    DestinationIn,
+    DestinationOut,
+    Copy,
+    Lighter,
+    Multiply,
+    Screen,
+    Darken,
+    Lighten,
+}
+
+impl BlendMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BlendMode::SourceOver => "source-over",
+            BlendMode::SourceIn => "source-in",
+            BlendMode::SourceAtop => "source-atop",
+            BlendMode::DestinationIn => "destination-in",
+            BlendMode::DestinationOut => "destination-out",
+            BlendMode::Copy => "copy",
+            BlendMode::Lighter => "lighter",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Darken => "darken",
+            BlendMode::Lighten => "lighten",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TextAlign::Left => "left",
+            TextAlign::Center => "center",
+            TextAlign::Right => "right",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextBaseline {
+    Top,
+    Middle,
+    Alphabetic,
+    Bottom,
+}
+
+impl TextBaseline {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TextBaseline::Top => "top",
+            TextBaseline::Middle => "middle",
+            TextBaseline::Alphabetic => "alphabetic",
+            TextBaseline::Bottom => "bottom",
+        }
+    }
+}
+
+/// RGBA8 pixels (not premultiplied, same as canvas `ImageData`)
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pixmap {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Debug for Pixmap {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Pixmap({}x{})", self.width, self.height)
+    }
+}
+
+// in bytes, `None` if it doesn't fit in memory at all
+fn data_len(width: u32, height: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize)?.checked_mul(4)
+}
+
+impl Pixmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; data_len(width, height).expect("Pixmap is too large")],
+        }
+    }
+
+    pub fn from_rgba(width: u32, height: u32, data: Vec<u8>) -> Self {
+        assert_eq!(Some(data.len()), data_len(width, height), "Wrong pixmap data length");
+        Self { width, height, data }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Pixel by pixel, row by row
+    pub fn from_fn(width: u32, height: u32, mut f: impl FnMut(u32, u32) -> [u8; 4]) -> Self {
+        let mut pixmap = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                pixmap.set_pixel(x, y, f(x, y));
+            }
+        }
+        pixmap
+    }
+
+    /// A copy of the part, it has to be inside
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Pixmap {
+        assert!(
+            x as u64 + width as u64 <= self.width as u64 && y as u64 + height as u64 <= self.height as u64,
+            "Crop is out of bounds"
+        );
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in y..y + height {
+            let start = self.index(x, row);
+            data.extend_from_slice(&self.data[start..start + width as usize * 4]);
+        }
+        Self::from_rgba(width, height, data)
+    }
+
+    /// Row by row, whether the alpha is above the threshold, for pixel perfect collisions
+    pub fn alpha_mask(&self, threshold: u8) -> Vec<bool> {
+        self.data.chunks_exact(4).map(|p| p[3] > threshold).collect()
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.index(x, y);
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = self.index(x, y);
+        self.data[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    pub fn fill(&mut self, rgba: [u8; 4]) {
+        for pixel in self.data.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+
+    /// Encodes into the netpbm PAM format, which is trivial and still
+    /// opens in most image viewers
+    pub fn encode_pam(&self) -> Vec<u8> {
+        let header = format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.width, self.height
+        );
+        let mut data = header.into_bytes();
+        data.extend_from_slice(&self.data);
+        data
+    }
+
+    /// Decodes the RGBA8 PAM files [Pixmap::encode_pam] makes
+    pub fn decode_pam(data: &[u8]) -> Option<Self> {
+        const END: &[u8] = b"ENDHDR\n";
+        let header_len = data.windows(END.len()).position(|w| w == END)? + END.len();
+        let header = std::str::from_utf8(&data[..header_len]).ok()?;
+        let (mut width, mut height) = (None, None);
+        for line in header.lines() {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("WIDTH"), Some(w)) => width = w.parse().ok(),
+                (Some("HEIGHT"), Some(h)) => height = h.parse().ok(),
+                (Some("DEPTH"), Some(d)) if d != "4" => return None,
+                (Some("MAXVAL"), Some(m)) if m != "255" => return None,
+                _ => {}
+            }
+        }
+        let (width, height): (u32, u32) = (width?, height?);
+        let pixels = &data[header_len..];
+        if Some(pixels.len()) != data_len(width, height) {
+            return None;
+        }
+        Some(Self::from_rgba(width, height, pixels.to_vec()))
+    }
+
+    /// Number of pixels where any channel differs by more than `tolerance`,
+    /// pixmaps of different sizes differ in every pixel
+    pub fn diff(&self, other: &Pixmap, tolerance: u8) -> usize {
+        if self.width != other.width || self.height != other.height {
+            let pixels = |p: &Pixmap| p.width as usize * p.height as usize;
+            return pixels(self).max(pixels(other));
+        }
+        self.data
+            .chunks_exact(4)
+            .zip(other.data.chunks_exact(4))
+            .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| (*a as i16 - *b as i16).unsigned_abs() > tolerance as u16))
+            .count()
+    }
+}
+
+/// Something that can be drawn with [Renderer::draw_image]
+#[derive(Clone)]
+pub enum Image {
+    Element(HtmlImageElement),
+    Canvas(HtmlCanvasElement),
+    Pixels(Rc<Pixmap>),
+}
+
+impl Debug for Image {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Image::Element(e) => write!(f, "Image::Element({})", e.src()),
+            Image::Canvas(c) => write!(f, "Image::Canvas({}x{})", c.width(), c.height()),
+            Image::Pixels(p) => write!(f, "Image::Pixels({:?})", p),
+        }
+    }
+}
+
+impl From<Pixmap> for Image {
+    fn from(pixmap: Pixmap) -> Self {
+        Image::Pixels(Rc::new(pixmap))
+    }
+}
+
+impl Image {
+    pub fn width(&self) -> u32 {
+        match self {
+            Image::Element(e) => e.natural_width(),
+            Image::Canvas(c) => c.width(),
+            Image::Pixels(p) => p.width(),
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Image::Element(e) => e.natural_height(),
+            Image::Canvas(c) => c.height(),
+            Image::Pixels(p) => p.height(),
+        }
+    }
+
+    /// A copy of the pixels, `None` for images from other origins as the browser doesn't allow reading those
+    pub fn to_pixmap(&self) -> Option<Pixmap> {
+        match self {
+            Image::Element(e) => {
+                let canvas = canvas::create_canvas(self.width(), self.height());
+                canvas::context_2d(&canvas).draw_image_with_html_image_element(e, 0.0, 0.0).ok()?;
+                canvas::canvas_to_pixmap(&canvas)
+            }
+            Image::Canvas(c) => canvas::canvas_to_pixmap(c),
+            Image::Pixels(p) => Some((**p).clone()),
+        }
+    }
+}
+
+/// The drawing API the engine and the games use, modeled after the canvas 2D context.
+///
+/// [Canvas2D] draws into an actual canvas and [SoftwareRenderer] draws into
+/// a [Pixmap] on the CPU, which works natively and is useful for tests.
+pub trait Renderer {
+    /// Size of the render target in pixels
+    fn size(&self) -> V2;
+
+    fn save(&self);
+
+    fn restore(&self);
+
+    fn set_transform(&self, transform: Transform);
+
+    fn get_transform(&self) -> Transform;
+
+    /// Multiplies the current transform by the given one
+    fn transform(&self, transform: Transform) {
+        self.set_transform(self.get_transform().then(&transform));
+    }
+
+    fn translate(&self, x: f64, y: f64) {
+        self.transform(Transform::translation(x, y));
+    }
+
+    fn rotate(&self, angle: f64) {
+        self.transform(Transform::rotation(angle));
+    }
+
+    fn scale(&self, x: f64, y: f64) {
+        self.transform(Transform::scaling(x, y));
+    }
+
+    fn set_fill_style(&self, style: &str);
+
+    fn set_stroke_style(&self, style: &str);
+
+    /// Sets the fill to a color, a gradient or a pattern
+    fn set_fill_paint(&self, paint: &Paint);
+
+    fn set_stroke_paint(&self, paint: &Paint);
+
+    fn set_line_width(&self, width: f64);
+
+    fn set_line_dash(&self, pattern: &[f64]);
+
+    fn set_global_alpha(&self, alpha: f64);
+
+    fn set_blend_mode(&self, mode: BlendMode);
+
+    fn set_image_smoothing(&self, enabled: bool);
+
+    fn begin_path(&self);
+
+    fn move_to(&self, x: f64, y: f64);
+
+    fn line_to(&self, x: f64, y: f64);
+
+    fn quadratic_curve_to(&self, cx: f64, cy: f64, x: f64, y: f64);
+
+    fn bezier_curve_to(&self, c1x: f64, c1y: f64, c2x: f64, c2y: f64, x: f64, y: f64);
+
+    fn arc(&self, x: f64, y: f64, radius: f64, start: f64, end: f64, anticlockwise: bool);
+
+    fn ellipse(&self, center: V2, radii: V2, rotation: f64, start: f64, end: f64, anticlockwise: bool);
+
+    fn rect(&self, x: f64, y: f64, w: f64, h: f64);
+
+    fn close_path(&self);
+
+    fn fill(&self, rule: FillRule);
+
+    fn stroke(&self);
+
+    /// Intersects the current clip with the current path
+    fn clip(&self, rule: FillRule);
+
+    fn fill_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.begin_path();
+        self.rect(x, y, w, h);
+        self.fill(FillRule::NonZero);
+    }
+
+    fn stroke_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.begin_path();
+        self.rect(x, y, w, h);
+        self.stroke();
+    }
+
+    /// Makes the pixels in the rectangle fully transparent
+    fn clear_rect(&self, x: f64, y: f64, w: f64, h: f64);
+
+    /// Draws the `src` part of the image into the `dst` rectangle
+    fn draw_image(&self, image: &Image, src: Rect, dst: Rect);
+
+    fn set_font(&self, font: &str);
+
+    fn set_text_align(&self, align: TextAlign);
+
+    fn set_text_baseline(&self, baseline: TextBaseline);
+
+    /// [SoftwareRenderer] can't rasterize fonts and draws a box for every glyph,
+    /// so image comparisons catch text being there but not how it looks
+    fn fill_text(&self, text: &str, x: f64, y: f64);
+
+    /// Width of the text with the current font
+    fn measure_text(&self, text: &str) -> f64;
+
+    /// The part of the user space that ends up on the target with the current
+    /// transform, for culling what's not visible
+    fn visible_rect(&self) -> Option<Rect> {
+        let inverse = self.get_transform().inverse()?;
+        let size = self.size();
+        let corners = [v2![0.0, 0.0], v2![size.x, 0.0], v2![0.0, size.y], size].map(|p| inverse.apply(p));
+        let min = corners.iter().fold(corners[0], |min, p| min.inf(p));
+        let max = corners.iter().fold(corners[0], |max, p| max.sup(p));
+        Some(Rect::from_corners(min, max))
+    }
+}
+
+/// Font size in pixels from a css font string, `rem`s are assumed to be 16px
+pub(crate) fn font_size_px(font: &str) -> f64 {
+    font.split_whitespace()
+        .find_map(|part| {
+            let (number, unit) = part.split_at(part.find(|c: char| c.is_ascii_alphabetic() || c == '%')?);
+            let number: f64 = number.parse().ok()?;
+            match unit {
+                "px" => Some(number),
+                "rem" | "em" => Some(number * 16.0),
+                "pt" => Some(number * 4.0 / 3.0),
+                _ => None,
+            }
+        })
+        .unwrap_or(10.0)
+}
+
+pub(crate) fn normalize_arc(start: f64, end: f64, anticlockwise: bool) -> (f64, f64) {
+    // same rules as the canvas arc, the sweep is at most a full circle
+    let sweep = if anticlockwise { start - end } else { end - start };
+    let sweep = if sweep >= TAU { TAU } else { sweep.rem_euclid(TAU) };
+    (start, if anticlockwise { -sweep } else { sweep })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Transform, b: Transform) -> bool {
+        let (a, b) = ([a.a, a.b, a.c, a.d, a.e, a.f], [b.a, b.b, b.c, b.d, b.e, b.f]);
+        a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn transform_then_applies_the_other_first() {
+        let t = Transform::translation(10.0, 0.0).then(&Transform::scaling(2.0, 3.0));
+        assert_eq!(t.apply(v2![1.0, 1.0]), v2![12.0, 3.0]);
+
+        let r = Transform::rotation(std::f64::consts::FRAC_PI_2);
+        let p = r.apply(v2![1.0, 0.0]);
+        assert!((p - v2![0.0, 1.0]).norm() < 1e-9);
+    }
+
+    #[test]
+    fn transform_inverse() {
+        let t = Transform::translation(5.0, -2.0)
+            .then(&Transform::rotation(0.7))
+            .then(&Transform::scaling(2.0, 0.5));
+        let inverse = t.inverse().unwrap();
+        assert!(close(t.then(&inverse), Transform::identity()));
+        assert!(close(inverse.then(&t), Transform::identity()));
+        let p = v2![3.0, 4.0];
+        assert!((inverse.apply(t.apply(p)) - p).norm() < 1e-9);
+
+        assert_eq!(Transform::scaling(0.0, 1.0).inverse(), None);
+    }
+
+    #[test]
+    fn pam_roundtrip() {
+        let mut pixmap = Pixmap::new(3, 2);
+        pixmap.set_pixel(0, 0, [1, 2, 3, 4]);
+        pixmap.set_pixel(2, 1, [255, 128, 0, 255]);
+        let decoded = Pixmap::decode_pam(&pixmap.encode_pam()).unwrap();
+        assert_eq!(decoded, pixmap);
+
+        assert_eq!(Pixmap::decode_pam(b"P7\nWIDTH 2\nHEIGHT 2\nDEPTH 4\nMAXVAL 255\nENDHDR\n"), None);
+        let gray = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 1\nMAXVAL 255\nENDHDR\n\0";
+        assert_eq!(Pixmap::decode_pam(gray), None);
+
+        // sizes that overflow u32, or usize altogether
+        for size in ["65536", "4294967295"] {
+            let huge = format!("P7\nWIDTH {0}\nHEIGHT {0}\nDEPTH 4\nMAXVAL 255\nENDHDR\n\0\0\0\0", size);
+            assert_eq!(Pixmap::decode_pam(huge.as_bytes()), None);
+        }
+    }
+
+    #[test]
+    fn pixmap_diff() {
+        let a = Pixmap::new(2, 2);
+        let mut b = a.clone();
+        assert_eq!(a.diff(&b, 0), 0);
+        b.set_pixel(1, 1, [0, 0, 0, 3]);
+        assert_eq!(a.diff(&b, 0), 1);
+        assert_eq!(a.diff(&b, 3), 0);
+        assert_eq!(a.diff(&Pixmap::new(3, 2), 0), 6);
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    cell::RefCell,
+    f64::consts::TAU,
+    fmt::{Debug, Formatter},
+    rc::Rc,
+};
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use crate::{
+    color::Color,
+    render::{Image, Rect, Transform},
+    sprite::Sprite,
+    V2,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GradientKind {
+    Linear {
+        from: V2,
+        to: V2,
+    },
+    /// Between two circles, same as the canvas one
+    Radial {
+        from: V2,
+        from_radius: f64,
+        to: V2,
+        to_radius: f64,
+    },
+    /// Around the center, starting at the angle (in radians, clockwise from the x axis)
+    Conic {
+        center: V2,
+        angle: f64,
+    },
+}
+
+/// Gradient coordinates are in the user space of the fill or stroke,
+/// same as with the canvas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub stops: Vec<(f64, Color)>,
+}
+
+impl Gradient {
+    pub fn new(kind: GradientKind) -> Self {
+        Self { kind, stops: Vec::new() }
+    }
+
+    pub fn linear(from: V2, to: V2) -> Self {
+        Self::new(GradientKind::Linear { from, to })
+    }
+
+    /// From the center to the radius
+    pub fn radial(center: V2, radius: f64) -> Self {
+        Self::radial_between(center, 0.0, center, radius)
+    }
+
+    pub fn radial_between(from: V2, from_radius: f64, to: V2, to_radius: f64) -> Self {
+        Self::new(GradientKind::Radial {
+            from,
+            from_radius,
+            to,
+            to_radius,
+        })
+    }
+
+    pub fn conic(center: V2, angle: f64) -> Self {
+        Self::new(GradientKind::Conic { center, angle })
+    }
+
+    /// The offset is in 0..1, NaN ones are ignored with a warning
+    pub fn with_stop(mut self, offset: f64, color: Color) -> Self {
+        if offset.is_nan() {
+            log::warn!("Gradient stop offset is NaN");
+            return self;
+        }
+        self.stops.push((offset.clamp(0.0, 1.0), color));
+        // stable, so stops with equal offsets keep their order for hard edges
+        self.stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self
+    }
+
+    /// The color at `t`, interpolated with premultiplied alpha like the browsers do
+    pub fn color_at(&self, t: f64) -> Color {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Color::TRANSPARENT,
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        let i = self.stops.iter().rposition(|(offset, _)| *offset <= t).unwrap_or(0);
+        let ((a_offset, a), (b_offset, b)) = (self.stops[i], self.stops[(i + 1).min(self.stops.len() - 1)]);
+        if b_offset <= a_offset {
+            return b;
+        }
+        let t = (t - a_offset) / (b_offset - a_offset);
+        a.premultiply().lerp(b.premultiply(), t).unpremultiply()
+    }
+
+    /// Where the point is along the gradient, `None` where radial ones are not painted
+    pub fn offset_at(&self, p: V2) -> Option<f64> {
+        match self.kind {
+            GradientKind::Linear { from, to } => {
+                let d = to - from;
+                let len = d.norm_squared();
+                if len <= f64::EPSILON {
+                    return None;
+                }
+                Some((p - from).dot(&d) / len)
+            }
+            GradientKind::Radial {
+                from,
+                from_radius,
+                to,
+                to_radius,
+            } => {
+                // the largest w where the circle lerped between the two by w goes through p
+                let (cd, dr, pd) = (to - from, to_radius - from_radius, p - from);
+                let a = cd.norm_squared() - dr * dr;
+                let b = pd.dot(&cd) + from_radius * dr;
+                let c = pd.norm_squared() - from_radius * from_radius;
+                let valid = |w: f64| from_radius + w * dr >= 0.0;
+                if a.abs() <= f64::EPSILON {
+                    let w = c / (2.0 * b);
+                    return if b != 0.0 && valid(w) { Some(w) } else { None };
+                }
+                let discriminant = b * b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let (w1, w2) = ((b + discriminant.sqrt()) / a, (b - discriminant.sqrt()) / a);
+                [w1.max(w2), w1.min(w2)].iter().copied().find(|w| valid(*w))
+            }
+            GradientKind::Conic { center, angle } => {
+                let d = p - center;
+                Some((d.y.atan2(d.x) - angle).rem_euclid(TAU) / TAU)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Repeat {
+    Both,
+    X,
+    Y,
+    None,
+}
+
+impl Repeat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Repeat::Both => "repeat",
+            Repeat::X => "repeat-x",
+            Repeat::Y => "repeat-y",
+            Repeat::None => "no-repeat",
+        }
+    }
+}
+
+/// An image repeated over the fill, with its own transform on top of the user space
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub image: Image,
+    pub repeat: Repeat,
+    pub transform: Transform,
+}
+
+impl Pattern {
+    pub fn new(image: Image) -> Self {
+        Self {
+            image,
+            repeat: Repeat::Both,
+            transform: Transform::identity(),
+        }
+    }
+
+    /// Copies the sprite out of its spritesheet, `None` if it's not loaded yet
+    pub fn from_sprite(sprite: &Sprite) -> Option<Self> {
+        let (image, region) = sprite.region()?;
+        let target = sprite.surface().create_target(region.w as u32, region.h as u32);
+        target.draw_image(&image, region, Rect::new(0.0, 0.0, region.w, region.h));
+        Some(Self::new(target.image()))
+    }
+
+    pub fn with_repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PaintKind {
+    Color(Color),
+    Gradient(Gradient),
+    Pattern(Pattern),
+}
+
+struct PaintInner {
+    kind: PaintKind,
+    cache: RefCell<Option<JsValue>>,
+}
+
+/// Anything a fill or a stroke can be set to.
+///
+/// Make them once and keep them around, they are cheap to clone and the
+/// canvas gradient or pattern is only created the first time it's used.
+/// Because of that, patterns of render targets do not see later changes.
+#[derive(Clone)]
+pub struct Paint {
+    inner: Rc<PaintInner>,
+}
+
+impl Debug for Paint {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "Paint({:?})", self.inner.kind)
+    }
+}
+
+impl Paint {
+    pub fn new(kind: PaintKind) -> Self {
+        Self {
+            inner: Rc::new(PaintInner {
+                kind,
+                cache: RefCell::new(None),
+            }),
+        }
+    }
+
+    pub fn kind(&self) -> &PaintKind {
+        &self.inner.kind
+    }
+
+    /// The browser object, created on the first call that succeeds
+    pub(crate) fn cached(&self, create: impl FnOnce(&PaintKind) -> Option<JsValue>) -> Option<JsValue> {
+        let mut cache = self.inner.cache.borrow_mut();
+        if cache.is_none() {
+            *cache = create(&self.inner.kind);
+        }
+        cache.clone()
+    }
+}
+
+impl From<Color> for Paint {
+    fn from(color: Color) -> Self {
+        Self::new(PaintKind::Color(color))
+    }
+}
+
+impl From<Gradient> for Paint {
+    fn from(gradient: Gradient) -> Self {
+        Self::new(PaintKind::Gradient(gradient))
+    }
+}
+
+impl From<Pattern> for Paint {
+    fn from(pattern: Pattern) -> Self {
+        Self::new(PaintKind::Pattern(pattern))
+    }
+}
@@ -0,0 +1,187 @@
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    render::Renderer,
+    V2, v2,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PathOp {
+    MoveTo(V2),
+    LineTo(V2),
+    QuadraticTo(V2, V2),
+    BezierTo(V2, V2, V2),
+    Arc {
+        center: V2,
+        radius: f64,
+        start: f64,
+        end: f64,
+        anticlockwise: bool,
+    },
+    Ellipse {
+        center: V2,
+        radii: V2,
+        rotation: f64,
+        start: f64,
+        end: f64,
+        anticlockwise: bool,
+    },
+    Close,
+}
+
+/// A reusable path, so that shapes can be built once and then
+/// filled or stroked with [crate::surface::SurfaceContextExt]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Path {
+    ops: Vec<PathOp>,
+}
+
+impl Path {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ops(&self) -> &[PathOp] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn op(mut self, op: PathOp) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    pub fn move_to(self, pos: V2) -> Self {
+        self.op(PathOp::MoveTo(pos))
+    }
+
+    pub fn line_to(self, pos: V2) -> Self {
+        self.op(PathOp::LineTo(pos))
+    }
+
+    pub fn quadratic_to(self, control: V2, pos: V2) -> Self {
+        self.op(PathOp::QuadraticTo(control, pos))
+    }
+
+    pub fn bezier_to(self, c1: V2, c2: V2, pos: V2) -> Self {
+        self.op(PathOp::BezierTo(c1, c2, pos))
+    }
+
+    /// Same as the canvas arc, it is connected to the previous point with a line
+    pub fn arc(self, center: V2, radius: f64, start: f64, end: f64) -> Self {
+        self.op(PathOp::Arc {
+            center,
+            radius,
+            start,
+            end,
+            anticlockwise: false,
+        })
+    }
+
+    pub fn arc_anticlockwise(self, center: V2, radius: f64, start: f64, end: f64) -> Self {
+        self.op(PathOp::Arc {
+            center,
+            radius,
+            start,
+            end,
+            anticlockwise: true,
+        })
+    }
+
+    pub fn ellipse(self, center: V2, radii: V2, rotation: f64) -> Self {
+        self.op(PathOp::Ellipse {
+            center,
+            radii,
+            rotation,
+            start: 0.0,
+            end: TAU,
+            anticlockwise: false,
+        })
+    }
+
+    pub fn close(self) -> Self {
+        self.op(PathOp::Close)
+    }
+
+    /// Lines through the points, closed if `close` is set
+    pub fn polyline(points: &[V2], close: bool) -> Self {
+        let mut path = Self::new();
+        if let Some((first, rest)) = points.split_first() {
+            path = path.move_to(*first);
+            for p in rest {
+                path = path.line_to(*p);
+            }
+            if close {
+                path = path.close();
+            }
+        }
+        path
+    }
+
+    pub fn polygon(points: &[V2]) -> Self {
+        Self::polyline(points, true)
+    }
+
+    pub fn rect(pos: V2, size: V2) -> Self {
+        Self::polygon(&[pos, pos + v2![size.x, 0.0], pos + size, pos + v2![0.0, size.y]])
+    }
+
+    /// The radius is clamped to half of the smaller side
+    pub fn rounded_rect(pos: V2, size: V2, radius: f64) -> Self {
+        let r = radius.min(size.x.abs() / 2.0).min(size.y.abs() / 2.0).max(0.0);
+        let (x0, y0, x1, y1) = (pos.x, pos.y, pos.x + size.x, pos.y + size.y);
+        Self::new()
+            .move_to(v2![x0 + r, y0])
+            .arc(v2![x1 - r, y0 + r], r, -FRAC_PI_2, 0.0)
+            .arc(v2![x1 - r, y1 - r], r, 0.0, FRAC_PI_2)
+            .arc(v2![x0 + r, y1 - r], r, FRAC_PI_2, PI)
+            .arc(v2![x0 + r, y0 + r], r, PI, PI + FRAC_PI_2)
+            .close()
+    }
+
+    /// A circle sector from the center
+    pub fn pie(center: V2, radius: f64, start: f64, end: f64) -> Self {
+        Self::new().move_to(center).arc(center, radius, start, end).close()
+    }
+
+    pub fn circle(center: V2, radius: f64) -> Self {
+        Self::new()
+            .move_to(center + v2![radius, 0.0])
+            .arc(center, radius, 0.0, TAU)
+            .close()
+    }
+
+    /// Replaces the current path of the renderer with this one
+    pub fn trace(&self, renderer: &(impl Renderer + ?Sized)) {
+        renderer.begin_path();
+        for op in &self.ops {
+            match *op {
+                PathOp::MoveTo(p) => renderer.move_to(p.x, p.y),
+                PathOp::LineTo(p) => renderer.line_to(p.x, p.y),
+                PathOp::QuadraticTo(c, p) => renderer.quadratic_curve_to(c.x, c.y, p.x, p.y),
+                PathOp::BezierTo(c1, c2, p) => renderer.bezier_curve_to(c1.x, c1.y, c2.x, c2.y, p.x, p.y),
+                PathOp::Arc {
+                    center,
+                    radius,
+                    start,
+                    end,
+                    anticlockwise,
+                } => renderer.arc(center.x, center.y, radius, start, end, anticlockwise),
+                PathOp::Ellipse {
+                    center,
+                    radii,
+                    rotation,
+                    start,
+                    end,
+                    anticlockwise,
+                } => renderer.ellipse(center, radii, rotation, start, end, anticlockwise),
+                PathOp::Close => renderer.close_path(),
+            }
+        }
+    }
+}
@@ -0,0 +1,625 @@
+use std::{cell::RefCell, collections::HashMap, fs, path::PathBuf, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    color::Color,
+    render::{
+        BlendMode, FillRule, Gradient, Image, Paint, PaintKind, Pattern, Pixmap, Rect, Renderer, Repeat, TextAlign,
+        TextBaseline, Transform,
+    },
+    V2,
+};
+
+/// Identifies an image in recorded commands without storing its pixels
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ImageKey {
+    Url(String),
+    /// Canvases have no identity, so they are numbered in the order of first use
+    Canvas { index: usize, width: u32, height: u32 },
+    Pixels { hash: u64, width: u32, height: u32 },
+}
+
+/// A [Paint] in the recorded commands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PaintDesc {
+    Color(Color),
+    Gradient(Gradient),
+    Pattern {
+        image: ImageKey,
+        repeat: Repeat,
+        transform: Transform,
+    },
+}
+
+impl PaintDesc {
+    fn to_paint(&self, images: &dyn Fn(&ImageKey) -> Option<Image>) -> Option<Paint> {
+        Some(match self {
+            PaintDesc::Color(color) => (*color).into(),
+            PaintDesc::Gradient(gradient) => gradient.clone().into(),
+            PaintDesc::Pattern {
+                image,
+                repeat,
+                transform,
+            } => Pattern::new(images(image)?)
+                .with_repeat(*repeat)
+                .with_transform(*transform)
+                .into(),
+        })
+    }
+}
+
+/// One [Renderer] call, with all of its arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DrawCommand {
+    Save,
+    Restore,
+    SetTransform(Transform),
+    Transform(Transform),
+    Translate(f64, f64),
+    Rotate(f64),
+    Scale(f64, f64),
+    FillStyle(String),
+    StrokeStyle(String),
+    FillPaint(PaintDesc),
+    StrokePaint(PaintDesc),
+    LineWidth(f64),
+    LineDash(Vec<f64>),
+    GlobalAlpha(f64),
+    BlendMode(BlendMode),
+    ImageSmoothing(bool),
+    BeginPath,
+    MoveTo(f64, f64),
+    LineTo(f64, f64),
+    QuadraticCurveTo(f64, f64, f64, f64),
+    BezierCurveTo(f64, f64, f64, f64, f64, f64),
+    Arc {
+        x: f64,
+        y: f64,
+        radius: f64,
+        start: f64,
+        end: f64,
+        anticlockwise: bool,
+    },
+    Ellipse {
+        center: V2,
+        radii: V2,
+        rotation: f64,
+        start: f64,
+        end: f64,
+        anticlockwise: bool,
+    },
+    Rect(Rect),
+    ClosePath,
+    Fill(FillRule),
+    Stroke,
+    Clip(FillRule),
+    FillRect(Rect),
+    StrokeRect(Rect),
+    ClearRect(Rect),
+    DrawImage { image: ImageKey, src: Rect, dst: Rect },
+    Font(String),
+    TextAlign(TextAlign),
+    TextBaseline(TextBaseline),
+    FillText { text: String, x: f64, y: f64 },
+}
+
+impl DrawCommand {
+    /// Calls the renderer, images are looked up with the given function
+    /// and the ones that are not found are skipped
+    pub fn apply(&self, renderer: &dyn Renderer, images: &dyn Fn(&ImageKey) -> Option<Image>) {
+        match self {
+            DrawCommand::Save => renderer.save(),
+            DrawCommand::Restore => renderer.restore(),
+            DrawCommand::SetTransform(t) => renderer.set_transform(*t),
+            DrawCommand::Transform(t) => renderer.transform(*t),
+            DrawCommand::Translate(x, y) => renderer.translate(*x, *y),
+            DrawCommand::Rotate(angle) => renderer.rotate(*angle),
+            DrawCommand::Scale(x, y) => renderer.scale(*x, *y),
+            DrawCommand::FillStyle(style) => renderer.set_fill_style(style),
+            DrawCommand::StrokeStyle(style) => renderer.set_stroke_style(style),
+            DrawCommand::FillPaint(paint) => match paint.to_paint(images) {
+                Some(paint) => renderer.set_fill_paint(&paint),
+                None => log::warn!("Replaying a fill with an unknown pattern image {:?}", paint),
+            },
+            DrawCommand::StrokePaint(paint) => match paint.to_paint(images) {
+                Some(paint) => renderer.set_stroke_paint(&paint),
+                None => log::warn!("Replaying a stroke with an unknown pattern image {:?}", paint),
+            },
+            DrawCommand::LineWidth(width) => renderer.set_line_width(*width),
+            DrawCommand::LineDash(pattern) => renderer.set_line_dash(pattern),
+            DrawCommand::GlobalAlpha(alpha) => renderer.set_global_alpha(*alpha),
+            DrawCommand::BlendMode(mode) => renderer.set_blend_mode(*mode),
+            DrawCommand::ImageSmoothing(enabled) => renderer.set_image_smoothing(*enabled),
+            DrawCommand::BeginPath => renderer.begin_path(),
+            DrawCommand::MoveTo(x, y) => renderer.move_to(*x, *y),
+            DrawCommand::LineTo(x, y) => renderer.line_to(*x, *y),
+            DrawCommand::QuadraticCurveTo(cx, cy, x, y) => renderer.quadratic_curve_to(*cx, *cy, *x, *y),
+            DrawCommand::BezierCurveTo(c1x, c1y, c2x, c2y, x, y) => {
+                renderer.bezier_curve_to(*c1x, *c1y, *c2x, *c2y, *x, *y)
+            }
+            DrawCommand::Arc {
+                x,
+                y,
+                radius,
+                start,
+                end,
+                anticlockwise,
+            } => renderer.arc(*x, *y, *radius, *start, *end, *anticlockwise),
+            DrawCommand::Ellipse {
+                center,
+                radii,
+                rotation,
+                start,
+                end,
+                anticlockwise,
+            } => renderer.ellipse(*center, *radii, *rotation, *start, *end, *anticlockwise),
+            DrawCommand::Rect(r) => renderer.rect(r.x, r.y, r.w, r.h),
+            DrawCommand::ClosePath => renderer.close_path(),
+            DrawCommand::Fill(rule) => renderer.fill(*rule),
+            DrawCommand::Stroke => renderer.stroke(),
+            DrawCommand::Clip(rule) => renderer.clip(*rule),
+            DrawCommand::FillRect(r) => renderer.fill_rect(r.x, r.y, r.w, r.h),
+            DrawCommand::StrokeRect(r) => renderer.stroke_rect(r.x, r.y, r.w, r.h),
+            DrawCommand::ClearRect(r) => renderer.clear_rect(r.x, r.y, r.w, r.h),
+            DrawCommand::DrawImage { image, src, dst } => match images(image) {
+                Some(image) => renderer.draw_image(&image, *src, *dst),
+                None => log::warn!("Replaying a draw of an unknown image {:?}", image),
+            },
+            DrawCommand::Font(font) => renderer.set_font(font),
+            DrawCommand::TextAlign(align) => renderer.set_text_align(*align),
+            DrawCommand::TextBaseline(baseline) => renderer.set_text_baseline(*baseline),
+            DrawCommand::FillText { text, x, y } => renderer.fill_text(text, *x, *y),
+        }
+    }
+}
+
+/// Recorded commands together with the images they draw
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub commands: Vec<DrawCommand>,
+    pub images: HashMap<ImageKey, Image>,
+}
+
+impl Recording {
+    pub fn replay(&self, renderer: &dyn Renderer) {
+        replay(&self.commands, renderer, |key| self.images.get(key).cloned());
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.commands).expect("Failed to serialize draw commands")
+    }
+}
+
+/// Replays the commands onto any renderer, e.g. ones loaded from a json file
+pub fn replay(commands: &[DrawCommand], renderer: &dyn Renderer, images: impl Fn(&ImageKey) -> Option<Image>) {
+    for command in commands {
+        command.apply(renderer, &images);
+    }
+}
+
+// fnv-1a, because the std hasher is not guaranteed to be stable
+fn hash_pixels(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// A [Renderer] that records every call and passes it through to another one
+pub struct RecordingRenderer {
+    inner: Rc<dyn Renderer>,
+    recording: RefCell<Recording>,
+    canvases: RefCell<Vec<web_sys::HtmlCanvasElement>>,
+}
+
+impl RecordingRenderer {
+    pub fn new(inner: Rc<dyn Renderer>) -> Self {
+        Self {
+            inner,
+            recording: RefCell::default(),
+            canvases: RefCell::default(),
+        }
+    }
+
+    pub fn inner(&self) -> Rc<dyn Renderer> {
+        self.inner.clone()
+    }
+
+    /// Takes everything recorded so far, leaving the recording empty
+    pub fn take(&self) -> Recording {
+        self.canvases.borrow_mut().clear();
+        self.recording.take()
+    }
+
+    fn record(&self, command: DrawCommand) {
+        self.recording.borrow_mut().commands.push(command);
+    }
+
+    // also keeps the image around for replaying
+    fn record_image(&self, image: &Image) -> ImageKey {
+        let key = self.image_key(image);
+        self.recording
+            .borrow_mut()
+            .images
+            .entry(key.clone())
+            .or_insert_with(|| image.clone());
+        key
+    }
+
+    fn paint_desc(&self, paint: &Paint) -> PaintDesc {
+        match paint.kind() {
+            PaintKind::Color(color) => PaintDesc::Color(*color),
+            PaintKind::Gradient(gradient) => PaintDesc::Gradient(gradient.clone()),
+            PaintKind::Pattern(pattern) => PaintDesc::Pattern {
+                image: self.record_image(&pattern.image),
+                repeat: pattern.repeat,
+                transform: pattern.transform,
+            },
+        }
+    }
+
+    fn image_key(&self, image: &Image) -> ImageKey {
+        match image {
+            Image::Element(element) => ImageKey::Url(element.src()),
+            Image::Canvas(canvas) => {
+                let mut canvases = self.canvases.borrow_mut();
+                let index = match canvases.iter().position(|c| c == canvas) {
+                    Some(index) => index,
+                    None => {
+                        canvases.push(canvas.clone());
+                        canvases.len() - 1
+                    }
+                };
+                ImageKey::Canvas {
+                    index,
+                    width: canvas.width(),
+                    height: canvas.height(),
+                }
+            }
+            Image::Pixels(pixmap) => ImageKey::Pixels {
+                hash: hash_pixels(pixmap.data()),
+                width: pixmap.width(),
+                height: pixmap.height(),
+            },
+        }
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn size(&self) -> V2 {
+        self.inner.size()
+    }
+
+    fn save(&self) {
+        self.record(DrawCommand::Save);
+        self.inner.save();
+    }
+
+    fn restore(&self) {
+        self.record(DrawCommand::Restore);
+        self.inner.restore();
+    }
+
+    fn set_transform(&self, t: Transform) {
+        self.record(DrawCommand::SetTransform(t));
+        self.inner.set_transform(t);
+    }
+
+    fn get_transform(&self) -> Transform {
+        self.inner.get_transform()
+    }
+
+    fn transform(&self, t: Transform) {
+        self.record(DrawCommand::Transform(t));
+        self.inner.transform(t);
+    }
+
+    fn translate(&self, x: f64, y: f64) {
+        self.record(DrawCommand::Translate(x, y));
+        self.inner.translate(x, y);
+    }
+
+    fn rotate(&self, angle: f64) {
+        self.record(DrawCommand::Rotate(angle));
+        self.inner.rotate(angle);
+    }
+
+    fn scale(&self, x: f64, y: f64) {
+        self.record(DrawCommand::Scale(x, y));
+        self.inner.scale(x, y);
+    }
+
+    fn set_fill_style(&self, style: &str) {
+        self.record(DrawCommand::FillStyle(style.to_owned()));
+        self.inner.set_fill_style(style);
+    }
+
+    fn set_stroke_style(&self, style: &str) {
+        self.record(DrawCommand::StrokeStyle(style.to_owned()));
+        self.inner.set_stroke_style(style);
+    }
+
+    fn set_fill_paint(&self, paint: &Paint) {
+        self.record(DrawCommand::FillPaint(self.paint_desc(paint)));
+        self.inner.set_fill_paint(paint);
+    }
+
+    fn set_stroke_paint(&self, paint: &Paint) {
+        self.record(DrawCommand::StrokePaint(self.paint_desc(paint)));
+        self.inner.set_stroke_paint(paint);
+    }
+
+    fn set_line_width(&self, width: f64) {
+        self.record(DrawCommand::LineWidth(width));
+        self.inner.set_line_width(width);
+    }
+
+    fn set_line_dash(&self, pattern: &[f64]) {
+        self.record(DrawCommand::LineDash(pattern.to_vec()));
+        self.inner.set_line_dash(pattern);
+    }
+
+    fn set_global_alpha(&self, alpha: f64) {
+        self.record(DrawCommand::GlobalAlpha(alpha));
+        self.inner.set_global_alpha(alpha);
+    }
+
+    fn set_blend_mode(&self, mode: BlendMode) {
+        self.record(DrawCommand::BlendMode(mode));
+        self.inner.set_blend_mode(mode);
+    }
+
+    fn set_image_smoothing(&self, enabled: bool) {
+        self.record(DrawCommand::ImageSmoothing(enabled));
+        self.inner.set_image_smoothing(enabled);
+    }
+
+    fn begin_path(&self) {
+        self.record(DrawCommand::BeginPath);
+        self.inner.begin_path();
+    }
+
+    fn move_to(&self, x: f64, y: f64) {
+        self.record(DrawCommand::MoveTo(x, y));
+        self.inner.move_to(x, y);
+    }
+
+    fn line_to(&self, x: f64, y: f64) {
+        self.record(DrawCommand::LineTo(x, y));
+        self.inner.line_to(x, y);
+    }
+
+    fn quadratic_curve_to(&self, cx: f64, cy: f64, x: f64, y: f64) {
+        self.record(DrawCommand::QuadraticCurveTo(cx, cy, x, y));
+        self.inner.quadratic_curve_to(cx, cy, x, y);
+    }
+
+    fn bezier_curve_to(&self, c1x: f64, c1y: f64, c2x: f64, c2y: f64, x: f64, y: f64) {
+        self.record(DrawCommand::BezierCurveTo(c1x, c1y, c2x, c2y, x, y));
+        self.inner.bezier_curve_to(c1x, c1y, c2x, c2y, x, y);
+    }
+
+    fn arc(&self, x: f64, y: f64, radius: f64, start: f64, end: f64, anticlockwise: bool) {
+        self.record(DrawCommand::Arc {
+            x,
+            y,
+            radius,
+            start,
+            end,
+            anticlockwise,
+        });
+        self.inner.arc(x, y, radius, start, end, anticlockwise);
+    }
+
+    fn ellipse(&self, center: V2, radii: V2, rotation: f64, start: f64, end: f64, anticlockwise: bool) {
+        self.record(DrawCommand::Ellipse {
+            center,
+            radii,
+            rotation,
+            start,
+            end,
+            anticlockwise,
+        });
+        self.inner.ellipse(center, radii, rotation, start, end, anticlockwise);
+    }
+
+    fn rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.record(DrawCommand::Rect(Rect::new(x, y, w, h)));
+        self.inner.rect(x, y, w, h);
+    }
+
+    fn close_path(&self) {
+        self.record(DrawCommand::ClosePath);
+        self.inner.close_path();
+    }
+
+    fn fill(&self, rule: FillRule) {
+        self.record(DrawCommand::Fill(rule));
+        self.inner.fill(rule);
+    }
+
+    fn stroke(&self) {
+        self.record(DrawCommand::Stroke);
+        self.inner.stroke();
+    }
+
+    fn clip(&self, rule: FillRule) {
+        self.record(DrawCommand::Clip(rule));
+        self.inner.clip(rule);
+    }
+
+    fn fill_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.record(DrawCommand::FillRect(Rect::new(x, y, w, h)));
+        self.inner.fill_rect(x, y, w, h);
+    }
+
+    fn stroke_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.record(DrawCommand::StrokeRect(Rect::new(x, y, w, h)));
+        self.inner.stroke_rect(x, y, w, h);
+    }
+
+    fn clear_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.record(DrawCommand::ClearRect(Rect::new(x, y, w, h)));
+        self.inner.clear_rect(x, y, w, h);
+    }
+
+    fn draw_image(&self, image: &Image, src: Rect, dst: Rect) {
+        let key = self.record_image(image);
+        self.record(DrawCommand::DrawImage { image: key, src, dst });
+        self.inner.draw_image(image, src, dst);
+    }
+
+    fn set_font(&self, font: &str) {
+        self.record(DrawCommand::Font(font.to_owned()));
+        self.inner.set_font(font);
+    }
+
+    fn set_text_align(&self, align: TextAlign) {
+        self.record(DrawCommand::TextAlign(align));
+        self.inner.set_text_align(align);
+    }
+
+    fn set_text_baseline(&self, baseline: TextBaseline) {
+        self.record(DrawCommand::TextBaseline(baseline));
+        self.inner.set_text_baseline(baseline);
+    }
+
+    fn fill_text(&self, text: &str, x: f64, y: f64) {
+        self.record(DrawCommand::FillText {
+            text: text.to_owned(),
+            x,
+            y,
+        });
+        self.inner.fill_text(text, x, y);
+    }
+
+    fn measure_text(&self, text: &str) -> f64 {
+        self.inner.measure_text(text)
+    }
+}
+
+/// Golden file snapshots for tests.
+///
+/// The commands are stored in `<path>.json` and the pixels in `<path>.pam`.
+/// Missing files are an error, run with the `UPDATE_GOLDEN` environment
+/// variable set to create or overwrite them. On mismatch the actual result
+/// is written next to the golden one with an `.actual` suffix.
+#[derive(Debug, Clone)]
+pub struct Golden {
+    path: PathBuf,
+    epsilon: f64,
+    tolerance: u8,
+    max_diff_pixels: usize,
+}
+
+impl Golden {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            epsilon: 1e-6,
+            tolerance: 0,
+            max_diff_pixels: 0,
+        }
+    }
+
+    /// How much the numbers in the commands are allowed to differ
+    pub fn with_epsilon(mut self, epsilon: f64) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// How much a channel can differ for the pixel to still be the same,
+    /// and how many different pixels are allowed
+    pub fn with_tolerance(mut self, tolerance: u8, max_diff_pixels: usize) -> Self {
+        self.tolerance = tolerance;
+        self.max_diff_pixels = max_diff_pixels;
+        self
+    }
+
+    fn file(&self, extension: &str) -> PathBuf {
+        self.path.with_extension(extension)
+    }
+
+    fn update() -> bool {
+        std::env::var_os("UPDATE_GOLDEN").is_some()
+    }
+
+    pub fn check_commands(&self, commands: &[DrawCommand]) -> Result<(), String> {
+        let path = self.file("json");
+        let actual = serde_json::to_value(commands).expect("Failed to serialize draw commands");
+        let pretty = serde_json::to_string_pretty(&actual).unwrap();
+        if Self::update() {
+            return fs::write(&path, pretty).map_err(|e| format!("Failed to write {}: {}", path.display(), e));
+        }
+        if !path.exists() {
+            return Err(format!("{} is missing, run with UPDATE_GOLDEN=1 to create it", path.display()));
+        }
+        let expected: Value = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .ok_or_else(|| format!("Failed to read {}", path.display()))?;
+        if json_eq(&expected, &actual, self.epsilon) {
+            return Ok(());
+        }
+        let actual_path = self.file("actual.json");
+        let _ = fs::write(&actual_path, pretty);
+        Err(format!(
+            "Draw commands differ from {}, see {}",
+            path.display(),
+            actual_path.display()
+        ))
+    }
+
+    pub fn check_pixmap(&self, pixmap: &Pixmap) -> Result<(), String> {
+        let path = self.file("pam");
+        if Self::update() {
+            return fs::write(&path, pixmap.encode_pam())
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e));
+        }
+        if !path.exists() {
+            return Err(format!("{} is missing, run with UPDATE_GOLDEN=1 to create it", path.display()));
+        }
+        let expected = fs::read(&path)
+            .ok()
+            .and_then(|data| Pixmap::decode_pam(&data))
+            .ok_or_else(|| format!("Failed to read {}", path.display()))?;
+        let diff = expected.diff(pixmap, self.tolerance);
+        if diff <= self.max_diff_pixels {
+            return Ok(());
+        }
+        let actual_path = self.file("actual.pam");
+        let _ = fs::write(&actual_path, pixmap.encode_pam());
+        Err(format!(
+            "{} pixels differ from {}, see {}",
+            diff,
+            path.display(),
+            actual_path.display()
+        ))
+    }
+
+    /// Checks both the commands and the pixels they produce, panicking on mismatch
+    pub fn assert(&self, recording: &Recording, pixmap: &Pixmap) {
+        if let Err(e) = self.check_commands(&recording.commands) {
+            panic!("{}", e);
+        }
+        if let Err(e) = self.check_pixmap(pixmap) {
+            panic!("{}", e);
+        }
+    }
+}
+
+fn json_eq(a: &Value, b: &Value, epsilon: f64) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => (a - b).abs() <= epsilon,
+            _ => a == b,
+        },
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b, epsilon))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(k, a)| b.get(k).is_some_and(|b| json_eq(a, b, epsilon)))
+        }
+        _ => a == b,
+    }
+}
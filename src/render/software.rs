@@ -0,0 +1,974 @@
+use std::{
+    cell::{Ref, RefCell},
+    f64::consts::TAU,
+    rc::Rc,
+};
+
+use crate::{
+    color::Color,
+    render::{
+        font_size_px, normalize_arc, BlendMode, FillRule, Image, Paint, PaintKind, Pixmap, Rect, Renderer, Repeat,
+        TextAlign, TextBaseline, Transform,
+    },
+    V2, v2,
+};
+
+type Rgba = [f64; 4];
+
+#[derive(Clone)]
+struct State {
+    transform: Transform,
+    fill: Paint,
+    stroke: Paint,
+    line_width: f64,
+    line_dash: Vec<f64>,
+    alpha: f64,
+    blend: BlendMode,
+    smoothing: bool,
+    font: String,
+    align: TextAlign,
+    baseline: TextBaseline,
+    clip: Option<Rc<Mask>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            transform: Transform::identity(),
+            fill: Color::BLACK.into(),
+            stroke: Color::BLACK.into(),
+            line_width: 1.0,
+            line_dash: Vec::new(),
+            alpha: 1.0,
+            blend: BlendMode::SourceOver,
+            smoothing: true,
+            font: "10px sans-serif".into(),
+            align: TextAlign::Left,
+            baseline: TextBaseline::Alphabetic,
+            clip: None,
+        }
+    }
+}
+
+struct SubPath {
+    points: Vec<V2>,
+    closed: bool,
+}
+
+struct Inner {
+    pixmap: Pixmap,
+    state: State,
+    stack: Vec<State>,
+    // in device space, same as the canvas transforms points when they are added
+    path: Vec<SubPath>,
+}
+
+/// Coverage (0..1) of a rectangle of pixels
+struct Mask {
+    x: i64,
+    y: i64,
+    w: i64,
+    h: i64,
+    data: Vec<f32>,
+}
+
+impl Mask {
+    fn get(&self, x: i64, y: i64) -> f32 {
+        if x < self.x || y < self.y || x >= self.x + self.w || y >= self.y + self.h {
+            0.0
+        } else {
+            self.data[((y - self.y) * self.w + x - self.x) as usize]
+        }
+    }
+}
+
+const SUBSAMPLES: i64 = 4;
+
+fn rasterize(polygons: &[Vec<V2>], rule: FillRule, width: u32, height: u32) -> Mask {
+    let mut min = v2![f64::INFINITY, f64::INFINITY];
+    let mut max = v2![f64::NEG_INFINITY, f64::NEG_INFINITY];
+    for p in polygons.iter().flatten() {
+        min = min.inf(p);
+        max = max.sup(p);
+    }
+    let x0 = (min.x.floor() as i64).max(0);
+    let y0 = (min.y.floor() as i64).max(0);
+    let x1 = (max.x.ceil() as i64).min(width as i64);
+    let y1 = (max.y.ceil() as i64).min(height as i64);
+    let (w, h) = ((x1 - x0).max(0), (y1 - y0).max(0));
+    let mut mask = Mask {
+        x: x0,
+        y: y0,
+        w,
+        h,
+        data: vec![0.0; (w * h) as usize],
+    };
+    if w == 0 || h == 0 {
+        return mask;
+    }
+
+    // a NaN or infinite point (e.g. from a degenerate transform) can't be filled sensibly
+    let edges: Vec<(V2, V2)> = polygons
+        .iter()
+        .filter(|p| p.len() >= 3 && p.iter().all(|v| v.x.is_finite() && v.y.is_finite()))
+        .flat_map(|p| p.iter().zip(p.iter().cycle().skip(1)).map(|(a, b)| (*a, *b)))
+        .filter(|(a, b)| a.y != b.y)
+        .collect();
+
+    let step = 1.0 / SUBSAMPLES as f64;
+    let weight = 1.0 / (SUBSAMPLES * SUBSAMPLES) as f32;
+    let mut crossings: Vec<(f64, i32)> = Vec::new();
+
+    for row in y0..y1 {
+        for sub in 0..SUBSAMPLES {
+            let y = row as f64 + (sub as f64 + 0.5) * step;
+            crossings.clear();
+            for (a, b) in &edges {
+                let (top, bottom, dir) = if a.y < b.y { (a, b, 1) } else { (b, a, -1) };
+                if y >= top.y && y < bottom.y {
+                    crossings.push((top.x + (y - top.y) / (bottom.y - top.y) * (bottom.x - top.x), dir));
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = match rule {
+                    FillRule::NonZero => winding != 0,
+                    FillRule::EvenOdd => winding % 2 != 0,
+                };
+                if !inside {
+                    continue;
+                }
+                let (xa, xb) = (pair[0].0, pair[1].0);
+                let from = (xa.floor() as i64).max(x0);
+                let to = (xb.ceil() as i64).min(x1);
+                for px in from..to {
+                    let count = (0..SUBSAMPLES)
+                        .map(|j| px as f64 + (j as f64 + 0.5) * step)
+                        .filter(|&x| x >= xa && x < xb)
+                        .count();
+                    mask.data[((row - y0) * w + px - x0) as usize] += count as f32 * weight;
+                }
+            }
+        }
+    }
+    mask
+}
+
+fn signed_area(polygon: &[V2]) -> f64 {
+    polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum::<f64>()
+}
+
+fn oriented(mut polygon: Vec<V2>) -> Vec<V2> {
+    if signed_area(&polygon) < 0.0 {
+        polygon.reverse();
+    }
+    polygon
+}
+
+fn disk(center: V2, radius: f64) -> Vec<V2> {
+    let n = ((radius * TAU / 2.0).ceil() as usize).clamp(8, 64);
+    (0..n)
+        .map(|i| {
+            let (sin, cos) = (i as f64 / n as f64 * TAU).sin_cos();
+            center + v2![cos, sin] * radius
+        })
+        .collect()
+}
+
+// splits the polyline into the "on" parts of the dash pattern
+fn dash(points: &[V2], pattern: &[f64]) -> Vec<Vec<V2>> {
+    let mut pattern = pattern.to_vec();
+    if pattern.len() % 2 == 1 {
+        pattern.extend_from_within(..);
+    }
+    if pattern.is_empty() || pattern.iter().sum::<f64>() <= 0.0 {
+        return vec![points.to_vec()];
+    }
+
+    let mut result = Vec::new();
+    let mut current = vec![points[0]];
+    let (mut index, mut left) = (0, pattern[0]);
+    for segment in points.windows(2) {
+        let (mut a, b) = (segment[0], segment[1]);
+        let mut length = (b - a).norm();
+        while length > 0.0 {
+            let step = left.min(length);
+            let next = a + (b - a) * (step / length);
+            if index % 2 == 0 {
+                current.push(next);
+            }
+            a = next;
+            length -= step;
+            left -= step;
+            if left <= 0.0 {
+                if index % 2 == 0 {
+                    result.push(std::mem::take(&mut current));
+                } else {
+                    current = vec![a];
+                }
+                index = (index + 1) % pattern.len();
+                left = pattern[index];
+            }
+        }
+    }
+    if index % 2 == 0 && current.len() > 1 {
+        result.push(current);
+    }
+    result
+}
+
+// both colors are straight (not premultiplied), `src[3]` already includes coverage
+fn blend(mode: BlendMode, src: Rgba, dst: Rgba) -> Rgba {
+    let (sa, da) = (src[3], dst[3]);
+    let separable = |f: fn(f64, f64) -> f64| {
+        let mut mixed = [0.0, 0.0, 0.0, sa];
+        for i in 0..3 {
+            mixed[i] = (1.0 - da) * src[i] + da * f(dst[i], src[i]);
+        }
+        mixed
+    };
+    let over = |src: Rgba| {
+        let a = src[3] + da * (1.0 - src[3]);
+        if a <= 0.0 {
+            return [0.0; 4];
+        }
+        let mut out = [0.0, 0.0, 0.0, a];
+        for i in 0..3 {
+            out[i] = (src[i] * src[3] + dst[i] * da * (1.0 - src[3])) / a;
+        }
+        out
+    };
+    match mode {
+        BlendMode::SourceOver => over(src),
+        BlendMode::Multiply => over(separable(|b, s| b * s)),
+        BlendMode::Screen => over(separable(|b, s| b + s - b * s)),
+        BlendMode::Darken => over(separable(f64::min)),
+        BlendMode::Lighten => over(separable(f64::max)),
+        BlendMode::Lighter => {
+            let a = (sa + da).min(1.0);
+            if a <= 0.0 {
+                return [0.0; 4];
+            }
+            let mut out = [0.0, 0.0, 0.0, a];
+            for i in 0..3 {
+                out[i] = ((src[i] * sa + dst[i] * da) / a).min(1.0);
+            }
+            out
+        }
+        BlendMode::SourceIn => [src[0], src[1], src[2], sa * da],
+        BlendMode::SourceAtop => {
+            let mut out = [0.0, 0.0, 0.0, da];
+            for i in 0..3 {
+                out[i] = src[i] * sa + dst[i] * (1.0 - sa);
+            }
+            out
+        }
+        BlendMode::DestinationIn => [dst[0], dst[1], dst[2], da * sa],
+        BlendMode::DestinationOut => [dst[0], dst[1], dst[2], da * (1.0 - sa)],
+        BlendMode::Copy => src,
+    }
+}
+
+fn to_bytes(color: Rgba) -> [u8; 4] {
+    let byte = |x: f64| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [byte(color[0]), byte(color[1]), byte(color[2]), byte(color[3])]
+}
+
+fn from_bytes(bytes: [u8; 4]) -> Rgba {
+    [
+        bytes[0] as f64 / 255.0,
+        bytes[1] as f64 / 255.0,
+        bytes[2] as f64 / 255.0,
+        bytes[3] as f64 / 255.0,
+    ]
+}
+
+fn sample(pixmap: &Pixmap, src: Rect, pos: V2, smooth: bool) -> Rgba {
+    let (min_x, min_y) = (src.x.max(0.0), src.y.max(0.0));
+    // partially covered texels count, as with the canvas
+    let max_x = (src.x + src.w).ceil().min(pixmap.width() as f64) - 1.0;
+    let max_y = (src.y + src.h).ceil().min(pixmap.height() as f64) - 1.0;
+    if max_x < min_x || max_y < min_y {
+        return [0.0; 4];
+    }
+    let texel = |x: f64, y: f64| from_bytes(pixmap.pixel(x.clamp(min_x, max_x) as u32, y.clamp(min_y, max_y) as u32));
+    if !smooth {
+        return texel(pos.x.floor(), pos.y.floor());
+    }
+    // bilinear, with premultiplied alpha so that transparent pixels don't bleed their color
+    let (x, y) = (pos.x - 0.5, pos.y - 0.5);
+    let (fx, fy) = (x - x.floor(), y - y.floor());
+    let mut out = [0.0; 4];
+    for (dx, dy, weight) in [
+        (0.0, 0.0, (1.0 - fx) * (1.0 - fy)),
+        (1.0, 0.0, fx * (1.0 - fy)),
+        (0.0, 1.0, (1.0 - fx) * fy),
+        (1.0, 1.0, fx * fy),
+    ] {
+        let t = texel(x.floor() + dx, y.floor() + dy);
+        for i in 0..3 {
+            out[i] += t[i] * t[3] * weight;
+        }
+        out[3] += t[3] * weight;
+    }
+    let alpha = out[3];
+    if alpha > 0.0 {
+        for channel in out.iter_mut().take(3) {
+            *channel /= alpha;
+        }
+    }
+    out
+}
+
+/// The [Renderer] that draws into a [Pixmap] on the CPU.
+///
+/// It does not need a browser, so it can be used natively (e.g. in tests).
+/// It approximates the canvas closely enough for comparing images, with
+/// a few differences: stroke joins are always round, caps are always butt,
+/// `copy`/`source-in`/`destination-in` only affect the drawn shape and
+/// text is not drawn at all (but it is measured, approximately).
+pub struct SoftwareRenderer {
+    inner: RefCell<Inner>,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::from_pixmap(Pixmap::new(width, height))
+    }
+
+    pub fn from_pixmap(pixmap: Pixmap) -> Self {
+        Self {
+            inner: RefCell::new(Inner {
+                pixmap,
+                state: State::default(),
+                stack: Vec::new(),
+                path: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn pixmap(&self) -> Ref<Pixmap> {
+        Ref::map(self.inner.borrow(), |inner| &inner.pixmap)
+    }
+
+    pub fn snapshot(&self) -> Pixmap {
+        self.pixmap().clone()
+    }
+
+    /// Same as resizing a canvas, this clears everything and resets the state
+    pub fn resize(&self, width: u32, height: u32) {
+        let mut inner = self.inner.borrow_mut();
+        inner.pixmap = Pixmap::new(width, height);
+        inner.state = State::default();
+        inner.stack.clear();
+        inner.path.clear();
+    }
+
+    fn push_point(&self, p: V2, new_subpath: bool) {
+        let mut inner = self.inner.borrow_mut();
+        let p = inner.state.transform.apply(p);
+        match inner.path.last_mut() {
+            Some(last) if !new_subpath && !last.closed => last.points.push(p),
+            _ => inner.path.push(SubPath {
+                points: vec![p],
+                closed: false,
+            }),
+        }
+    }
+
+    fn last_point(&self) -> Option<V2> {
+        let inner = self.inner.borrow();
+        let inverse = inner.state.transform.inverse()?;
+        inner
+            .path
+            .last()
+            .and_then(|s| s.points.last())
+            .map(|p| inverse.apply(*p))
+    }
+
+    fn device_scale(&self) -> f64 {
+        self.inner.borrow().state.transform.determinant().abs().sqrt()
+    }
+
+    fn composite(&self, mask: &Mask, paint: impl Fn(V2) -> Rgba) {
+        let mut inner = self.inner.borrow_mut();
+        let Inner { pixmap, state, .. } = &mut *inner;
+        for y in mask.y..mask.y + mask.h {
+            for x in mask.x..mask.x + mask.w {
+                let mut coverage = mask.get(x, y) as f64;
+                if let Some(clip) = &state.clip {
+                    coverage *= clip.get(x, y) as f64;
+                }
+                if coverage <= 0.0 {
+                    continue;
+                }
+                let mut src = paint(v2![x as f64 + 0.5, y as f64 + 0.5]);
+                src[3] *= coverage.min(1.0) * state.alpha;
+                let dst = from_bytes(pixmap.pixel(x as u32, y as u32));
+                pixmap.set_pixel(x as u32, y as u32, to_bytes(blend(state.blend, src, dst)));
+            }
+        }
+    }
+
+    fn composite_paint(&self, mask: &Mask, paint: &Paint) {
+        let (inverse, smooth) = {
+            let state = &self.inner.borrow().state;
+            (state.transform.inverse(), state.smoothing)
+        };
+        match (paint.kind(), inverse) {
+            (PaintKind::Color(color), _) => {
+                let color = color.to_array();
+                self.composite(mask, |_| color)
+            }
+            (PaintKind::Gradient(gradient), Some(inverse)) => self.composite(mask, |device| {
+                gradient
+                    .offset_at(inverse.apply(device))
+                    .map_or([0.0; 4], |t| gradient.color_at(t).to_array())
+            }),
+            (PaintKind::Pattern(pattern), Some(inverse)) => {
+                let pixmap = match &pattern.image {
+                    Image::Pixels(pixmap) => pixmap.clone(),
+                    _ => return,
+                };
+                let inverse = match pattern.transform.inverse() {
+                    Some(pattern_inverse) => pattern_inverse.then(&inverse),
+                    None => return,
+                };
+                let size = v2![pixmap.width() as f64, pixmap.height() as f64];
+                let full = Rect::new(0.0, 0.0, size.x, size.y);
+                self.composite(mask, |device| {
+                    let p = inverse.apply(device);
+                    let (repeat_x, repeat_y) = match pattern.repeat {
+                        Repeat::Both => (true, true),
+                        Repeat::X => (true, false),
+                        Repeat::Y => (false, true),
+                        Repeat::None => (false, false),
+                    };
+                    if (!repeat_x && (p.x < 0.0 || p.x >= size.x)) || (!repeat_y && (p.y < 0.0 || p.y >= size.y)) {
+                        return [0.0; 4];
+                    }
+                    let x = if repeat_x { p.x.rem_euclid(size.x) } else { p.x };
+                    let y = if repeat_y { p.y.rem_euclid(size.y) } else { p.y };
+                    sample(&pixmap, full, v2![x, y], smooth)
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn path_polygons(&self) -> Vec<Vec<V2>> {
+        self.inner
+            .borrow()
+            .path
+            .iter()
+            .filter(|s| s.points.len() >= 3)
+            .map(|s| s.points.clone())
+            .collect()
+    }
+
+    fn stroke_polygons(&self) -> Vec<Vec<V2>> {
+        let scale = self.device_scale();
+        let inner = self.inner.borrow();
+        let half = inner.state.line_width * scale / 2.0;
+        let pattern: Vec<f64> = inner.state.line_dash.iter().map(|d| d * scale).collect();
+
+        let mut polygons = Vec::new();
+        for subpath in &inner.path {
+            let mut points = subpath.points.clone();
+            if subpath.closed && points.len() > 1 {
+                points.push(points[0]);
+            }
+            if points.len() < 2 {
+                continue;
+            }
+            for line in dash(&points, &pattern) {
+                for segment in line.windows(2) {
+                    let (a, b) = (segment[0], segment[1]);
+                    let along = b - a;
+                    if along.norm() <= f64::EPSILON {
+                        continue;
+                    }
+                    let normal = v2![-along.y, along.x].normalize() * half;
+                    polygons.push(oriented(vec![a + normal, b + normal, b - normal, a - normal]));
+                }
+                let closed_loop = subpath.closed && line.len() == points.len();
+                let joins = if closed_loop { &line[..] } else { &line[1..line.len() - 1] };
+                polygons.extend(joins.iter().map(|p| disk(*p, half)));
+            }
+        }
+        polygons
+    }
+
+    fn rect_polygon(&self, x: f64, y: f64, w: f64, h: f64) -> Vec<V2> {
+        let t = self.inner.borrow().state.transform;
+        vec![
+            t.apply(v2![x, y]),
+            t.apply(v2![x + w, y]),
+            t.apply(v2![x + w, y + h]),
+            t.apply(v2![x, y + h]),
+        ]
+    }
+
+    fn mask(&self, polygons: &[Vec<V2>], rule: FillRule) -> Mask {
+        let (width, height) = {
+            let inner = self.inner.borrow();
+            (inner.pixmap.width(), inner.pixmap.height())
+        };
+        rasterize(polygons, rule, width, height)
+    }
+
+    fn flatten_ellipse(&self, center: V2, radii: V2, rotation: f64, start: f64, end: f64, anticlockwise: bool) {
+        let (start, sweep) = normalize_arc(start, end, anticlockwise);
+        let device_radius = radii.x.max(radii.y) * self.device_scale();
+        let n = ((sweep.abs() * device_radius / 2.0).ceil() as usize).clamp(4, 256);
+        let rotate = Transform::rotation(rotation);
+        for i in 0..=n {
+            let angle = start + sweep * i as f64 / n as f64;
+            let (sin, cos) = angle.sin_cos();
+            self.push_point(center + rotate.apply_vector(v2![cos * radii.x, sin * radii.y]), false);
+        }
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn size(&self) -> V2 {
+        let inner = self.inner.borrow();
+        v2![inner.pixmap.width() as f64, inner.pixmap.height() as f64]
+    }
+
+    fn save(&self) {
+        let mut inner = self.inner.borrow_mut();
+        let state = inner.state.clone();
+        inner.stack.push(state);
+    }
+
+    fn restore(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(state) = inner.stack.pop() {
+            inner.state = state;
+        }
+    }
+
+    fn set_transform(&self, transform: Transform) {
+        self.inner.borrow_mut().state.transform = transform;
+    }
+
+    fn get_transform(&self) -> Transform {
+        self.inner.borrow().state.transform
+    }
+
+    fn set_fill_style(&self, style: &str) {
+        if let Some(color) = Color::parse(style) {
+            self.inner.borrow_mut().state.fill = color.into();
+        }
+    }
+
+    fn set_stroke_style(&self, style: &str) {
+        if let Some(color) = Color::parse(style) {
+            self.inner.borrow_mut().state.stroke = color.into();
+        }
+    }
+
+    fn set_fill_paint(&self, paint: &Paint) {
+        self.inner.borrow_mut().state.fill = paint.clone();
+    }
+
+    fn set_stroke_paint(&self, paint: &Paint) {
+        self.inner.borrow_mut().state.stroke = paint.clone();
+    }
+
+    fn set_line_width(&self, width: f64) {
+        if width > 0.0 && width.is_finite() {
+            self.inner.borrow_mut().state.line_width = width;
+        }
+    }
+
+    fn set_line_dash(&self, pattern: &[f64]) {
+        if pattern.iter().all(|x| *x >= 0.0 && x.is_finite()) {
+            self.inner.borrow_mut().state.line_dash = pattern.to_vec();
+        }
+    }
+
+    fn set_global_alpha(&self, alpha: f64) {
+        if (0.0..=1.0).contains(&alpha) {
+            self.inner.borrow_mut().state.alpha = alpha;
+        }
+    }
+
+    fn set_blend_mode(&self, mode: BlendMode) {
+        self.inner.borrow_mut().state.blend = mode;
+    }
+
+    fn set_image_smoothing(&self, enabled: bool) {
+        self.inner.borrow_mut().state.smoothing = enabled;
+    }
+
+    fn begin_path(&self) {
+        self.inner.borrow_mut().path.clear();
+    }
+
+    fn move_to(&self, x: f64, y: f64) {
+        self.push_point(v2![x, y], true);
+    }
+
+    fn line_to(&self, x: f64, y: f64) {
+        self.push_point(v2![x, y], false);
+    }
+
+    fn quadratic_curve_to(&self, cx: f64, cy: f64, x: f64, y: f64) {
+        let from = self.last_point().unwrap_or(v2![cx, cy]);
+        let (c, to) = (v2![cx, cy], v2![x, y]);
+        let length = ((c - from).norm() + (to - c).norm()) * self.device_scale();
+        let n = ((length / 4.0).ceil() as usize).clamp(2, 64);
+        for i in 1..=n {
+            let t = i as f64 / n as f64;
+            let p = from * (1.0 - t).powi(2) + c * (2.0 * (1.0 - t) * t) + to * t.powi(2);
+            self.push_point(p, false);
+        }
+    }
+
+    fn bezier_curve_to(&self, c1x: f64, c1y: f64, c2x: f64, c2y: f64, x: f64, y: f64) {
+        let from = self.last_point().unwrap_or(v2![c1x, c1y]);
+        let (c1, c2, to) = (v2![c1x, c1y], v2![c2x, c2y], v2![x, y]);
+        let length = ((c1 - from).norm() + (c2 - c1).norm() + (to - c2).norm()) * self.device_scale();
+        let n = ((length / 4.0).ceil() as usize).clamp(2, 64);
+        for i in 1..=n {
+            let t = i as f64 / n as f64;
+            let u = 1.0 - t;
+            let p = from * u.powi(3) + c1 * (3.0 * u * u * t) + c2 * (3.0 * u * t * t) + to * t.powi(3);
+            self.push_point(p, false);
+        }
+    }
+
+    fn arc(&self, x: f64, y: f64, radius: f64, start: f64, end: f64, anticlockwise: bool) {
+        self.flatten_ellipse(v2![x, y], v2![radius, radius], 0.0, start, end, anticlockwise);
+    }
+
+    fn ellipse(&self, center: V2, radii: V2, rotation: f64, start: f64, end: f64, anticlockwise: bool) {
+        self.flatten_ellipse(center, radii, rotation, start, end, anticlockwise);
+    }
+
+    fn rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        self.move_to(x, y);
+        self.line_to(x + w, y);
+        self.line_to(x + w, y + h);
+        self.line_to(x, y + h);
+        self.close_path();
+        self.move_to(x, y);
+    }
+
+    fn close_path(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(last) = inner.path.last_mut() {
+            last.closed = true;
+            let first = last.points[0];
+            inner.path.push(SubPath {
+                points: vec![first],
+                closed: false,
+            });
+        }
+    }
+
+    fn fill(&self, rule: FillRule) {
+        let mask = self.mask(&self.path_polygons(), rule);
+        let paint = self.inner.borrow().state.fill.clone();
+        self.composite_paint(&mask, &paint);
+    }
+
+    fn stroke(&self) {
+        let mask = self.mask(&self.stroke_polygons(), FillRule::NonZero);
+        let paint = self.inner.borrow().state.stroke.clone();
+        self.composite_paint(&mask, &paint);
+    }
+
+    fn clip(&self, rule: FillRule) {
+        let mut mask = self.mask(&self.path_polygons(), rule);
+        let mut inner = self.inner.borrow_mut();
+        if let Some(old) = &inner.state.clip {
+            for y in mask.y..mask.y + mask.h {
+                for x in mask.x..mask.x + mask.w {
+                    mask.data[((y - mask.y) * mask.w + x - mask.x) as usize] *= old.get(x, y);
+                }
+            }
+        }
+        inner.state.clip = Some(Rc::new(mask));
+    }
+
+    fn clear_rect(&self, x: f64, y: f64, w: f64, h: f64) {
+        let mask = self.mask(&[self.rect_polygon(x, y, w, h)], FillRule::NonZero);
+        let mut inner = self.inner.borrow_mut();
+        let Inner { pixmap, state, .. } = &mut *inner;
+        for y in mask.y..mask.y + mask.h {
+            for x in mask.x..mask.x + mask.w {
+                let mut coverage = mask.get(x, y) as f64;
+                if let Some(clip) = &state.clip {
+                    coverage *= clip.get(x, y) as f64;
+                }
+                if coverage > 0.0 {
+                    let mut pixel = from_bytes(pixmap.pixel(x as u32, y as u32));
+                    pixel[3] *= 1.0 - coverage.min(1.0);
+                    pixmap.set_pixel(x as u32, y as u32, to_bytes(pixel));
+                }
+            }
+        }
+    }
+
+    fn draw_image(&self, image: &Image, src: Rect, dst: Rect) {
+        // only raw pixels can be read without a browser
+        let pixmap = match image {
+            Image::Pixels(pixmap) => pixmap.clone(),
+            _ => return,
+        };
+        let (inverse, smooth) = {
+            let state = &self.inner.borrow().state;
+            match state.transform.inverse() {
+                Some(inverse) => (inverse, state.smoothing),
+                None => return,
+            }
+        };
+        if dst.w == 0.0 || dst.h == 0.0 {
+            return;
+        }
+        let mask = self.mask(&[self.rect_polygon(dst.x, dst.y, dst.w, dst.h)], FillRule::NonZero);
+        self.composite(&mask, |device| {
+            let user = inverse.apply(device);
+            let u = src.x + (user.x - dst.x) / dst.w * src.w;
+            let v = src.y + (user.y - dst.y) / dst.h * src.h;
+            sample(&pixmap, src, v2![u, v], smooth)
+        });
+    }
+
+    fn set_font(&self, font: &str) {
+        self.inner.borrow_mut().state.font = font.to_owned();
+    }
+
+    fn set_text_align(&self, align: TextAlign) {
+        self.inner.borrow_mut().state.align = align;
+    }
+
+    fn set_text_baseline(&self, baseline: TextBaseline) {
+        self.inner.borrow_mut().state.baseline = baseline;
+    }
+
+    // no font rasterizer here, so every glyph is a box, enough to see where text is
+    // and how much of it there is, with the same advance as measure_text
+    fn fill_text(&self, text: &str, x: f64, y: f64) {
+        let (size, align, baseline, paint) = {
+            let state = &self.inner.borrow().state;
+            (font_size_px(&state.font), state.align, state.baseline, state.fill.clone())
+        };
+        let advance = size * 0.6;
+        let x = match align {
+            TextAlign::Left => x,
+            TextAlign::Center => x - self.measure_text(text) / 2.0,
+            TextAlign::Right => x - self.measure_text(text),
+        };
+        // the top of the em box, with the alphabetic baseline at 0.8 of it
+        let top = match baseline {
+            TextBaseline::Top => y,
+            TextBaseline::Middle => y - size / 2.0,
+            TextBaseline::Alphabetic => y - size * 0.8,
+            TextBaseline::Bottom => y - size,
+        };
+        let polygons: Vec<_> = text
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| !c.is_whitespace())
+            .map(|(i, _)| {
+                let left = x + (i as f64 + 0.1) * advance;
+                self.rect_polygon(left, top + size * 0.1, advance * 0.8, size * 0.7)
+            })
+            .collect();
+        let mask = self.mask(&polygons, FillRule::NonZero);
+        self.composite_paint(&mask, &paint);
+    }
+
+    fn measure_text(&self, text: &str) -> f64 {
+        text.chars().count() as f64 * font_size_px(&self.inner.borrow().state.font) * 0.6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    // a square with a square hole wound the same way
+    fn nested_squares(renderer: &SoftwareRenderer) {
+        renderer.begin_path();
+        renderer.rect(0.0, 0.0, 8.0, 8.0);
+        renderer.rect(2.0, 2.0, 4.0, 4.0);
+    }
+
+    #[test]
+    fn fill_rules() {
+        let renderer = SoftwareRenderer::new(8, 8);
+        renderer.set_fill_style("red");
+        nested_squares(&renderer);
+        renderer.fill(FillRule::NonZero);
+        assert_eq!(renderer.snapshot().pixel(4, 4), RED);
+
+        let renderer = SoftwareRenderer::new(8, 8);
+        renderer.set_fill_style("red");
+        nested_squares(&renderer);
+        renderer.fill(FillRule::EvenOdd);
+        let pixmap = renderer.snapshot();
+        assert_eq!(pixmap.pixel(4, 4), CLEAR);
+        assert_eq!(pixmap.pixel(1, 1), RED);
+    }
+
+    #[test]
+    fn fill_is_antialiased() {
+        let renderer = SoftwareRenderer::new(4, 4);
+        renderer.set_fill_style("red");
+        renderer.fill_rect(0.0, 0.0, 1.5, 4.0);
+        let pixmap = renderer.snapshot();
+        assert_eq!(pixmap.pixel(0, 0), RED);
+        let edge = pixmap.pixel(1, 0)[3];
+        assert!(edge > 100 && edge < 155, "edge alpha {}", edge);
+        assert_eq!(pixmap.pixel(2, 0), CLEAR);
+    }
+
+    #[test]
+    fn clipping() {
+        let renderer = SoftwareRenderer::new(8, 8);
+        renderer.save();
+        renderer.begin_path();
+        renderer.rect(2.0, 2.0, 4.0, 4.0);
+        renderer.clip(FillRule::NonZero);
+        renderer.set_fill_style("red");
+        renderer.fill_rect(0.0, 0.0, 8.0, 8.0);
+        renderer.restore();
+        let pixmap = renderer.snapshot();
+        assert_eq!(pixmap.pixel(3, 3), RED);
+        assert_eq!(pixmap.pixel(1, 1), CLEAR);
+        assert_eq!(pixmap.pixel(6, 6), CLEAR);
+
+        // restore drops the clip, along with the fill style
+        renderer.set_fill_style("red");
+        renderer.fill_rect(0.0, 0.0, 8.0, 8.0);
+        assert_eq!(renderer.snapshot().pixel(1, 1), RED);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let draw = |mode: BlendMode| {
+            let renderer = SoftwareRenderer::new(1, 1);
+            renderer.set_fill_style("#804020");
+            renderer.fill_rect(0.0, 0.0, 1.0, 1.0);
+            renderer.set_blend_mode(mode);
+            renderer.set_fill_style("#ff8000");
+            renderer.fill_rect(0.0, 0.0, 1.0, 1.0);
+            renderer.snapshot().pixel(0, 0)
+        };
+        assert_eq!(draw(BlendMode::SourceOver), [255, 128, 0, 255]);
+        assert_eq!(draw(BlendMode::Multiply), [128, 32, 0, 255]);
+        assert_eq!(draw(BlendMode::Lighten), [255, 128, 32, 255]);
+        assert_eq!(draw(BlendMode::Darken), [128, 64, 0, 255]);
+        assert_eq!(draw(BlendMode::Lighter), [255, 192, 32, 255]);
+        assert_eq!(draw(BlendMode::DestinationOut)[3], 0);
+        assert_eq!(draw(BlendMode::DestinationIn), [128, 64, 32, 255]);
+    }
+
+    #[test]
+    fn global_alpha() {
+        let renderer = SoftwareRenderer::new(1, 1);
+        renderer.set_global_alpha(0.5);
+        renderer.set_fill_style("red");
+        renderer.fill_rect(0.0, 0.0, 1.0, 1.0);
+        assert_eq!(renderer.snapshot().pixel(0, 0), [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn draw_image_sampling() {
+        let mut pixmap = Pixmap::new(2, 2);
+        pixmap.set_pixel(0, 0, [255, 0, 0, 255]);
+        pixmap.set_pixel(1, 0, [0, 255, 0, 255]);
+        pixmap.set_pixel(0, 1, [0, 0, 255, 255]);
+        pixmap.set_pixel(1, 1, [255, 255, 255, 255]);
+        let image: Image = pixmap.clone().into();
+
+        let renderer = SoftwareRenderer::new(4, 4);
+        renderer.set_image_smoothing(false);
+        renderer.draw_image(&image, Rect::new(0.0, 0.0, 2.0, 2.0), Rect::new(0.0, 0.0, 4.0, 4.0));
+        let scaled = renderer.snapshot();
+        for (x, y) in [(0, 0), (1, 1), (3, 0), (0, 3), (3, 3)] {
+            assert_eq!(scaled.pixel(x, y), pixmap.pixel(x / 2, y / 2), "at {}, {}", x, y);
+        }
+
+        // only the source rect is sampled, even at its edges
+        let renderer = SoftwareRenderer::new(2, 2);
+        renderer.draw_image(&image, Rect::new(1.0, 0.0, 1.0, 1.0), Rect::new(0.0, 0.0, 2.0, 2.0));
+        let pixmap = renderer.snapshot();
+        assert_eq!(pixmap.pixel(0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixmap.pixel(1, 1), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn transformed_draws() {
+        let renderer = SoftwareRenderer::new(8, 8);
+        renderer.translate(4.0, 0.0);
+        renderer.scale(2.0, 2.0);
+        renderer.set_fill_style("red");
+        renderer.fill_rect(0.0, 0.0, 1.0, 1.0);
+        let pixmap = renderer.snapshot();
+        assert_eq!(pixmap.pixel(5, 1), RED);
+        assert_eq!(pixmap.pixel(3, 1), CLEAR);
+        assert_eq!(pixmap.pixel(6, 1), CLEAR);
+    }
+
+    #[test]
+    fn clear_rect() {
+        let renderer = SoftwareRenderer::new(4, 1);
+        renderer.set_fill_style("red");
+        renderer.fill_rect(0.0, 0.0, 4.0, 1.0);
+        renderer.clear_rect(1.0, 0.0, 2.0, 1.0);
+        let pixmap = renderer.snapshot();
+        assert_eq!(pixmap.pixel(0, 0), RED);
+        assert_eq!(pixmap.pixel(1, 0)[3], 0);
+        assert_eq!(pixmap.pixel(3, 0), RED);
+    }
+
+    #[test]
+    fn non_finite_paths_are_skipped() {
+        let renderer = SoftwareRenderer::new(4, 4);
+        renderer.set_fill_style("red");
+        renderer.begin_path();
+        renderer.move_to(0.0, 0.0);
+        renderer.line_to(f64::NAN, 4.0);
+        renderer.line_to(0.0, 4.0);
+        renderer.fill(FillRule::NonZero);
+        renderer.scale(f64::NAN, 1.0);
+        renderer.fill_rect(0.0, 0.0, 4.0, 4.0);
+        assert!(renderer.snapshot().data().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn text_is_drawn_as_boxes() {
+        let draw = |align: TextAlign, x: f64| {
+            let renderer = SoftwareRenderer::new(20, 10);
+            renderer.set_font("10px sans-serif");
+            renderer.set_text_align(align);
+            renderer.set_text_baseline(TextBaseline::Top);
+            renderer.set_fill_style("red");
+            renderer.fill_text("a b", x, 0.0);
+            renderer.snapshot()
+        };
+        let left = draw(TextAlign::Left, 0.0);
+        assert_eq!(left.pixel(3, 4), RED);
+        assert_eq!(left.pixel(9, 4), CLEAR);
+        assert_eq!(left.pixel(15, 4), RED);
+        assert_eq!(left.pixel(3, 9), CLEAR);
+        assert_eq!(draw(TextAlign::Right, 18.0), left);
+        assert_eq!(draw(TextAlign::Center, 9.0), left);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{ops::Deref, rc::Rc};
+
+use web_sys::HtmlCanvasElement;
+
+use crate::{
+    color::Color,
+    render::{
+        canvas::{context_2d, create_canvas},
+        Canvas2D, Image, Rect, Renderer, SoftwareRenderer, Transform,
+    },
+    surface::Surface,
+    V2, v2,
+};
+
+#[derive(Clone)]
+enum Backing {
+    Canvas(HtmlCanvasElement, Rc<Canvas2D>),
+    Software(Rc<SoftwareRenderer>),
+}
+
+/// An offscreen image that can be drawn into with the same [Renderer] API
+/// as the surface (it derefs to one) and then drawn onto the surface.
+///
+/// It's a hidden canvas, or a [SoftwareRenderer] for headless surfaces.
+/// Create them with [Surface::create_target].
+#[derive(Clone)]
+pub struct RenderTarget {
+    surface: Surface,
+    backing: Backing,
+}
+
+impl Deref for RenderTarget {
+    type Target = dyn Renderer;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.backing {
+            Backing::Canvas(_, renderer) => &**renderer,
+            Backing::Software(renderer) => &**renderer,
+        }
+    }
+}
+
+impl RenderTarget {
+    pub(crate) fn new(surface: Surface, width: u32, height: u32, image_smoothing: bool) -> Self {
+        let backing = if surface.is_headless() {
+            Backing::Software(Rc::new(SoftwareRenderer::new(width, height)))
+        } else {
+            let canvas = create_canvas(width, height);
+            let renderer = Rc::new(Canvas2D::new(context_2d(&canvas)));
+            Backing::Canvas(canvas, renderer)
+        };
+        let target = Self { surface, backing };
+        target.set_image_smoothing(image_smoothing);
+        target
+    }
+
+    pub fn renderer(&self) -> Rc<dyn Renderer> {
+        match &self.backing {
+            Backing::Canvas(_, renderer) => renderer.clone(),
+            Backing::Software(renderer) => renderer.clone(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.size().x as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.size().y as u32
+    }
+
+    /// Clears the contents and resets the drawing state, same as with canvases
+    pub fn resize(&self, width: u32, height: u32) {
+        match &self.backing {
+            Backing::Canvas(canvas, _) => {
+                canvas.set_width(width);
+                canvas.set_height(height);
+            }
+            Backing::Software(renderer) => renderer.resize(width, height),
+        }
+    }
+
+    /// Makes everything transparent, keeping the drawing state
+    pub fn clear(&self) {
+        let size = self.size();
+        self.save();
+        self.set_transform(Transform::identity());
+        self.clear_rect(0.0, 0.0, size.x, size.y);
+        self.restore();
+    }
+
+    /// Fills everything with the color
+    pub fn clear_with(&self, color: Color) {
+        let size = self.size();
+        self.save();
+        self.set_transform(Transform::identity());
+        self.clear_rect(0.0, 0.0, size.x, size.y);
+        self.set_fill_style(&color.to_css());
+        self.fill_rect(0.0, 0.0, size.x, size.y);
+        self.restore();
+    }
+
+    /// The contents as an image, for canvases this is live, while the
+    /// software ones are copied
+    pub fn image(&self) -> Image {
+        match &self.backing {
+            Backing::Canvas(canvas, _) => Image::Canvas(canvas.clone()),
+            Backing::Software(renderer) => renderer.snapshot().into(),
+        }
+    }
+
+    /// Draws the whole target onto the surface at its current transform
+    pub fn draw(&self, x: f64, y: f64) {
+        let size = self.size();
+        self.draw_rect(Rect::new(x, y, size.x, size.y));
+    }
+
+    pub fn draw_scaled(&self, pos: V2, scale: f64) {
+        let size = self.size() * scale;
+        self.draw_rect(Rect::new(pos.x, pos.y, size.x, size.y));
+    }
+
+    pub fn draw_rect(&self, dst: Rect) {
+        let size = self.size();
+        self.surface
+            .renderer()
+            .draw_image(&self.image(), Rect::from_corners(v2![0.0, 0.0], size), dst);
+    }
+}
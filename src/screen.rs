@@ -0,0 +1,185 @@
+use js_sys::Promise;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::{Element, EventTarget};
+
+use crate::{
+    event::{Event, ListenForever},
+    util::Mut,
+};
+
+#[wasm_bindgen(inline_js = r#"
+export function request_fullscreen(e) {
+    const f = e.requestFullscreen || e.webkitRequestFullscreen;
+    return f ? Promise.resolve(f.call(e)) : Promise.reject(new Error("Fullscreen is not supported"));
+}
+export function exit_fullscreen() {
+    const f = document.exitFullscreen || document.webkitExitFullscreen;
+    if (f) f.call(document);
+}
+export function fullscreen_element() {
+    return document.fullscreenElement || document.webkitFullscreenElement || null;
+}
+export function screen_orientation() {
+    return screen.orientation || null;
+}
+export function lock_orientation(o) {
+    return screen.orientation && screen.orientation.lock
+        ? screen.orientation.lock(o)
+        : Promise.reject(new Error("Orientation lock is not supported"));
+}
+export function unlock_orientation() {
+    if (screen.orientation && screen.orientation.unlock) screen.orientation.unlock();
+}
+export function orientation_type() {
+    return screen.orientation ? screen.orientation.type : (innerWidth > innerHeight ? "landscape" : "portrait");
+}
+"#)]
+extern "C" {
+    fn request_fullscreen(element: &Element) -> Promise;
+    fn exit_fullscreen();
+    fn fullscreen_element() -> Option<Element>;
+    fn screen_orientation() -> Option<EventTarget>;
+    fn lock_orientation(orientation: &str) -> Promise;
+    fn unlock_orientation();
+    fn orientation_type() -> String;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl Orientation {
+    pub(crate) fn current() -> Self {
+        if orientation_type().starts_with("landscape") {
+            Orientation::Landscape
+        } else {
+            Orientation::Portrait
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrientationLock {
+    Any,
+    Natural,
+    Landscape,
+    Portrait,
+    PortraitPrimary,
+    PortraitSecondary,
+    LandscapePrimary,
+    LandscapeSecondary,
+}
+
+impl OrientationLock {
+    fn as_str(self) -> &'static str {
+        match self {
+            OrientationLock::Any => "any",
+            OrientationLock::Natural => "natural",
+            OrientationLock::Landscape => "landscape",
+            OrientationLock::Portrait => "portrait",
+            OrientationLock::PortraitPrimary => "portrait-primary",
+            OrientationLock::PortraitSecondary => "portrait-secondary",
+            OrientationLock::LandscapePrimary => "landscape-primary",
+            OrientationLock::LandscapeSecondary => "landscape-secondary",
+        }
+    }
+}
+
+/// Browsers only allow fullscreen (and orientation locks, which mostly need
+/// fullscreen) shortly after a user gesture, and our events are handled
+/// on the next animation frame, so failed requests are kept here
+/// and retried on the next gesture
+#[derive(Debug, Default)]
+pub(crate) struct ScreenRequests {
+    fullscreen: bool,
+    orientation: Option<OrientationLock>,
+}
+
+pub(crate) fn is_fullscreen(target: &Element) -> bool {
+    fullscreen_element().as_ref() == Some(target)
+}
+
+pub(crate) fn request_fullscreen_on(target: &Element, requests: &Mut<ScreenRequests>) {
+    requests.borrow_mut().fullscreen = true;
+    let promise = request_fullscreen(target);
+    let moved_requests = requests.clone();
+    spawn_local(async move {
+        if JsFuture::from(promise).await.is_ok() {
+            let pending_lock = {
+                let mut requests = moved_requests.borrow_mut();
+                requests.fullscreen = false;
+                requests.orientation
+            };
+            if let Some(lock) = pending_lock {
+                request_orientation_lock(lock, &moved_requests);
+            }
+        }
+    });
+}
+
+pub(crate) fn exit_fullscreen_on(target: &Element, requests: &Mut<ScreenRequests>) {
+    requests.borrow_mut().fullscreen = false;
+    if is_fullscreen(target) {
+        exit_fullscreen();
+    }
+}
+
+pub(crate) fn request_orientation_lock(lock: OrientationLock, requests: &Mut<ScreenRequests>) {
+    requests.borrow_mut().orientation = Some(lock);
+    let promise = lock_orientation(lock.as_str());
+    let moved_requests = requests.clone();
+    spawn_local(async move {
+        if JsFuture::from(promise).await.is_ok() {
+            let mut requests = moved_requests.borrow_mut();
+            if requests.orientation == Some(lock) {
+                requests.orientation = None;
+            }
+        }
+    });
+}
+
+pub(crate) fn unlock_orientation_lock(requests: &Mut<ScreenRequests>) {
+    requests.borrow_mut().orientation = None;
+    unlock_orientation();
+}
+
+pub(crate) fn setup_screen_events(target: Element, requests: Mut<ScreenRequests>, events: Mut<Vec<Event>>) {
+    let document = crate::document();
+
+    for gesture in &["mousedown", "touchend", "keydown"] {
+        let moved_target = target.clone();
+        let moved_requests = requests.clone();
+        document.listen_forever(gesture, move |_: web_sys::Event| {
+            let (fullscreen, orientation) = {
+                let requests = moved_requests.borrow();
+                (requests.fullscreen, requests.orientation)
+            };
+            if fullscreen {
+                request_fullscreen_on(&moved_target, &moved_requests);
+            } else if let Some(lock) = orientation {
+                request_orientation_lock(lock, &moved_requests);
+            }
+        });
+    }
+
+    for change in &["fullscreenchange", "webkitfullscreenchange"] {
+        let moved_target = target.clone();
+        let moved_events = events.clone();
+        document.listen_forever(change, move |_: web_sys::Event| {
+            moved_events.borrow_mut().push(Event::FullscreenChange {
+                fullscreen: is_fullscreen(&moved_target),
+            })
+        });
+    }
+
+    if let Some(orientation) = screen_orientation() {
+        orientation.listen_forever("change", move |_: web_sys::Event| {
+            events.borrow_mut().push(Event::OrientationChange {
+                orientation: Orientation::current(),
+            })
+        });
+    }
+}
@@ -1,13 +1,32 @@
+use std::{cell::Ref, collections::HashMap, rc::Rc};
+
 use wasm_bindgen::{prelude::*, *};
 use web_sys::HtmlImageElement;
 
+use crate::color::Color;
+use crate::render::{Image, Layer, Pixmap, Rect, Transform};
 use crate::surface::Surface;
 use crate::util::Mut;
+use crate::{V2, v2};
+
+// tinted copies of sprites by their region and the tint color, the tint alpha
+// is applied when drawing so that fading doesn't make a new copy every frame
+type TintCache = HashMap<([u32; 4], [u8; 3]), Image>;
+
+/// Past this many tinted copies they are all dropped and made again as needed
+pub(crate) const TINT_CACHE_LIMIT: usize = 256;
+
+// the color part of the tint, `None` for white which needs no copy
+pub(crate) fn tint_rgb(tint: Color) -> Option<[u8; 3]> {
+    let [r, g, b, _] = tint.with_alpha(1.0).to_rgba8();
+    Some([r, g, b]).filter(|rgb| *rgb != [255; 3])
+}
 
 #[derive(Clone)]
 pub struct Spritesheet {
     surface: Mut<Surface>,
-    image: Mut<Option<HtmlImageElement>>,
+    image: Mut<Option<Image>>,
+    tinted: Mut<TintCache>,
 }
 
 impl Spritesheet {
@@ -18,7 +37,7 @@ impl Spritesheet {
             .expect("Failed to set img.src attribute");
 
         let image = if element.complete() {
-            Mut::new(Some(element))
+            Mut::new(Some(Image::Element(element)))
         } else {
             let image = Mut::new(None);
             let moved_image = image.clone();
@@ -27,14 +46,77 @@ impl Spritesheet {
                 .add_event_listener_with_callback(
                     "load",
                     Closure::once_into_js(move |_e: web_sys::Event| {
-                        *moved_image.borrow_mut() = Some(element)
+                        *moved_image.borrow_mut() = Some(Image::Element(element))
                     })
                     .unchecked_ref(),
                 )
                 .unwrap();
             image
         };
-        Spritesheet { surface, image }
+        Spritesheet {
+            surface,
+            image,
+            tinted: Mut::default(),
+        }
+    }
+
+    pub fn from_image(surface: Mut<Surface>, image: Image) -> Spritesheet {
+        Spritesheet {
+            surface,
+            image: Mut::new(Some(image)),
+            tinted: Mut::default(),
+        }
+    }
+
+    /// From pixels generated on the CPU, see [Pixmap::from_fn]
+    pub fn from_pixmap(surface: Mut<Surface>, pixmap: Pixmap) -> Spritesheet {
+        let image = surface.borrow().upload(pixmap);
+        Self::from_image(surface, image)
+    }
+
+    /// Replaces the image with the pixels, the sprites that were already
+    /// created keep their regions
+    pub fn set_pixmap(&self, pixmap: Pixmap) {
+        let image = self.surface.borrow().upload(pixmap);
+        *self.image.borrow_mut() = Some(image);
+        self.tinted.borrow_mut().clear();
+    }
+
+    /// A copy of the pixels, for collision masks and such. It's a slow
+    /// readback for the loaded images, so keep the result around.
+    ///
+    /// `None` until the image is loaded, or if it's from another origin
+    pub fn pixmap(&self) -> Option<Pixmap> {
+        self.image()?.to_pixmap()
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.image.borrow().is_some()
+    }
+
+    pub(crate) fn image(&self) -> Option<Image> {
+        self.image.borrow().clone()
+    }
+
+    pub(crate) fn surface(&self) -> Ref<Surface> {
+        self.surface.borrow()
+    }
+
+    pub(crate) fn same_as(&self, other: &Spritesheet) -> bool {
+        Rc::ptr_eq(&self.image, &other.image)
+    }
+
+    /// Slices the sheet into equally sized cells, `margin` is around the
+    /// whole grid and `spacing` is between the cells, same as in Tiled
+    pub fn grid(&self, cell_w: u32, cell_h: u32, margin: u32, spacing: u32) -> Tileset {
+        Tileset {
+            spritesheet: self.clone(),
+            cell_w,
+            cell_h,
+            margin,
+            spacing,
+            columns: None,
+        }
     }
 
     pub fn create_sprite(&self, u: u32, v: u32, w: u32, h: u32) -> Sprite {
@@ -45,10 +127,161 @@ impl Spritesheet {
             w,
             h,
             scale: 1.0,
+            offset: v2![0.0, 0.0],
+            source_size: (w, h),
         }
     }
 }
 
+/// A [Spritesheet] sliced into a grid, cells are numbered row by row.
+///
+/// Indexing needs to know how many columns there are, which is taken from
+/// the image size, so until it's loaded only [Tileset::at] works unless the
+/// columns are set explicitly.
+#[derive(Clone)]
+pub struct Tileset {
+    spritesheet: Spritesheet,
+    cell_w: u32,
+    cell_h: u32,
+    margin: u32,
+    spacing: u32,
+    columns: Option<u32>,
+}
+
+impl Tileset {
+    pub fn with_columns(mut self, columns: u32) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    pub fn spritesheet(&self) -> &Spritesheet {
+        &self.spritesheet
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) {
+        (self.cell_w, self.cell_h)
+    }
+
+    fn cells_along(&self, size: u32, cell: u32) -> u32 {
+        (size.saturating_sub(2 * self.margin) + self.spacing) / (cell + self.spacing).max(1)
+    }
+
+    pub fn columns(&self) -> Option<u32> {
+        if self.columns.is_some() {
+            return self.columns;
+        }
+        let image = self.spritesheet.image()?;
+        Some(self.cells_along(image.width(), self.cell_w))
+    }
+
+    /// `None` until the image is loaded, explicit columns don't help here
+    pub fn rows(&self) -> Option<u32> {
+        let image = self.spritesheet.image()?;
+        Some(self.cells_along(image.height(), self.cell_h))
+    }
+
+    pub fn count(&self) -> Option<usize> {
+        Some(self.columns()? as usize * self.rows()? as usize)
+    }
+
+    pub fn at(&self, col: u32, row: u32) -> Sprite {
+        self.spritesheet.create_sprite(
+            self.margin + col * (self.cell_w + self.spacing),
+            self.margin + row * (self.cell_h + self.spacing),
+            self.cell_w,
+            self.cell_h,
+        )
+    }
+
+    /// `None` if it's out of bounds, or if the columns are not known yet
+    pub fn get(&self, index: usize) -> Option<Sprite> {
+        let columns = self.columns()? as usize;
+        if columns == 0 || self.count().is_some_and(|count| index >= count) {
+            return None;
+        }
+        Some(self.at((index % columns) as u32, (index / columns) as u32))
+    }
+}
+
+/// How to draw a sprite, see [Sprite::draw_with]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawParams {
+    /// The point that is put at the draw position and rotated around,
+    /// as a fraction of the sprite size, so the center is `(0.5, 0.5)`
+    pub origin: V2,
+    /// In radians, clockwise
+    pub rotation: f64,
+    pub scale: V2,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub alpha: f64,
+    /// Multiplies the sprite colors, its alpha multiplies [DrawParams::alpha].
+    /// Each distinct color is a cached copy of the sprite, so don't animate
+    /// it through a lot of them, fading the alpha is fine
+    pub tint: Color,
+}
+
+impl Default for DrawParams {
+    fn default() -> Self {
+        Self {
+            origin: v2![0.0, 0.0],
+            rotation: 0.0,
+            scale: v2![1.0, 1.0],
+            flip_x: false,
+            flip_y: false,
+            alpha: 1.0,
+            tint: Color::WHITE,
+        }
+    }
+}
+
+impl DrawParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_origin(mut self, x: f64, y: f64) -> Self {
+        self.origin = v2![x, y];
+        self
+    }
+
+    pub fn centered(self) -> Self {
+        self.with_origin(0.5, 0.5)
+    }
+
+    pub fn with_rotation(mut self, rotation: f64) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = v2![scale, scale];
+        self
+    }
+
+    pub fn with_scale_xy(mut self, x: f64, y: f64) -> Self {
+        self.scale = v2![x, y];
+        self
+    }
+
+    pub fn with_flip(mut self, flip_x: bool, flip_y: bool) -> Self {
+        self.flip_x = flip_x;
+        self.flip_y = flip_y;
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+}
+
+#[derive(Clone)]
 pub struct Sprite {
     parent: Spritesheet,
     u: u32,
@@ -56,32 +289,206 @@ pub struct Sprite {
     w: u32,
     h: u32,
     scale: f64,
+    // where the trimmed region is in the untrimmed sprite
+    offset: V2,
+    source_size: (u32, u32),
 }
 
 impl Sprite {
     pub fn draw(&self, x: f64, y: f64) {
         if let Some(ref image) = *self.parent.image.borrow() {
-            self.parent
-                .surface
-                .borrow()
-                .context()
-                .draw_image_with_html_image_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
-                    image,
-                    self.u as f64,
-                    self.v as f64,
-                    self.w as f64,
-                    self.h as f64,
-                    x,
-                    y,
-                    (self.w as f64) * self.scale,
-                    (self.h as f64) * self.scale,
-                )
-                .expect("Failed to draw sprite image");
+            self.parent.surface.borrow().renderer().draw_image(image, self.rect(), self.dst(x, y));
         }
     }
 
+    pub fn draw_with(&self, x: f64, y: f64, params: &DrawParams) {
+        let image = match self.image(params.tint) {
+            Some(image) => image,
+            None => return,
+        };
+        let (transform, dst) = self.placement(x, y, params);
+        let renderer = self.parent.surface.borrow().renderer();
+        renderer.save();
+        renderer.transform(transform);
+        let alpha = params.alpha * params.tint.a;
+        if alpha != 1.0 {
+            renderer.set_global_alpha(alpha);
+        }
+        renderer.draw_image(&image, self.src(params.tint), dst);
+        renderer.restore();
+    }
+
+    // where the plain draw puts the region
+    pub(crate) fn dst(&self, x: f64, y: f64) -> Rect {
+        Rect::new(
+            x + self.offset.x * self.scale,
+            y + self.offset.y * self.scale,
+            (self.w as f64) * self.scale,
+            (self.h as f64) * self.scale,
+        )
+    }
+
+    // the transform of draw_with and where the region goes under it
+    pub(crate) fn placement(&self, x: f64, y: f64, params: &DrawParams) -> (Transform, Rect) {
+        let scale = params.scale * self.scale;
+        let transform = Transform::translation(x, y)
+            .then(&Transform::rotation(params.rotation))
+            .then(&Transform::scaling(
+                if params.flip_x { -scale.x } else { scale.x },
+                if params.flip_y { -scale.y } else { scale.y },
+            ));
+        let (source_w, source_h) = (self.source_size.0 as f64, self.source_size.1 as f64);
+        let dst = Rect::new(
+            self.offset.x - params.origin.x * source_w,
+            self.offset.y - params.origin.y * source_h,
+            self.w as f64,
+            self.h as f64,
+        );
+        (transform, dst)
+    }
+
+    // the tinted copies are just the region
+    pub(crate) fn src(&self, tint: Color) -> Rect {
+        match tint_rgb(tint) {
+            Some(_) => Rect::new(0.0, 0.0, self.w as f64, self.h as f64),
+            None => self.rect(),
+        }
+    }
+
+    // the spritesheet image, or the tinted copy of just this sprite
+    pub(crate) fn image(&self, tint: Color) -> Option<Image> {
+        let image = self.parent.image()?;
+        let rgb = match tint_rgb(tint) {
+            Some(rgb) => rgb,
+            None => return Some(image),
+        };
+        let key = ([self.u, self.v, self.w, self.h], rgb);
+        if let Some(tinted) = self.parent.tinted.borrow().get(&key) {
+            return Some(tinted.clone());
+        }
+        let src = Rect::new(self.u as f64, self.v as f64, self.w as f64, self.h as f64);
+        let tinted = self.parent.surface.borrow().tint_image(&image, src, tint.with_alpha(1.0));
+        let mut cache = self.parent.tinted.borrow_mut();
+        if cache.len() >= TINT_CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(key, tinted.clone());
+        Some(tinted)
+    }
+
+    /// Draws the sprite into the layer at the end of the frame, see [Surface::submit]
+    pub fn submit(&self, layer: Layer, z: i32, x: f64, y: f64) {
+        let sprite = self.clone();
+        self.parent
+            .surface
+            .borrow()
+            .submit(layer, z, move |_| sprite.draw(x, y));
+    }
+
+    pub fn submit_with(&self, layer: Layer, z: i32, x: f64, y: f64, params: DrawParams) {
+        let sprite = self.clone();
+        self.parent
+            .surface
+            .borrow()
+            .submit(layer, z, move |_| sprite.draw_with(x, y, &params));
+    }
+
+    /// The untrimmed size
+    pub fn width(&self) -> u32 {
+        self.source_size.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.source_size.1
+    }
+
+    pub(crate) fn surface(&self) -> Ref<Surface> {
+        self.parent.surface.borrow()
+    }
+
+    /// The spritesheet image and the part of it this sprite is
+    pub(crate) fn region(&self) -> Option<(Image, Rect)> {
+        let image = self.parent.image.borrow().clone()?;
+        Some((image, Rect::new(self.u as f64, self.v as f64, self.w as f64, self.h as f64)))
+    }
+
     pub fn with_scale(mut self, scale: f64) -> Self {
         self.scale = scale;
         self
     }
+
+    /// A copy of the pixels of the region (without the trimmed borders), see [Spritesheet::pixmap]
+    pub fn pixmap(&self) -> Option<Pixmap> {
+        let pixmap = self.parent.pixmap()?;
+        if self.u + self.w > pixmap.width() || self.v + self.h > pixmap.height() {
+            return None;
+        }
+        Some(pixmap.crop(self.u, self.v, self.w, self.h))
+    }
+
+    pub(crate) fn spritesheet(&self) -> &Spritesheet {
+        &self.parent
+    }
+
+    pub(crate) fn rect(&self) -> Rect {
+        Rect::new(self.u as f64, self.v as f64, self.w as f64, self.h as f64)
+    }
+
+    /// Where the top left corner of the untrimmed sprite would be in the spritesheet
+    pub(crate) fn source_origin(&self) -> V2 {
+        v2![self.u as f64 - self.offset.x, self.v as f64 - self.offset.y]
+    }
+
+    /// For sprites packed with the transparent borders cut off, the region is
+    /// drawn at the offset as if it was still the full source size
+    pub fn with_trim(mut self, offset_x: f64, offset_y: f64, source_w: u32, source_h: u32) -> Self {
+        self.offset = v2![offset_x, offset_y];
+        self.source_size = (source_w, source_h);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::SoftwareRenderer;
+
+    fn sheet() -> (Spritesheet, Rc<SoftwareRenderer>) {
+        let renderer = Rc::new(SoftwareRenderer::new(2, 2));
+        let surface = Mut::new(Surface::with_renderer(renderer.clone()));
+        let mut pixmap = Pixmap::new(2, 2);
+        pixmap.fill([255, 255, 255, 255]);
+        (Spritesheet::from_pixmap(surface, pixmap), renderer)
+    }
+
+    #[test]
+    fn tint_alpha_is_drawn_not_cached() {
+        let (sheet, renderer) = sheet();
+        let sprite = sheet.create_sprite(0, 0, 2, 2);
+        for i in 0..100 {
+            let tint = Color::RED.with_alpha(i as f64 / 100.0);
+            assert!(sprite.image(tint).is_some());
+        }
+        assert_eq!(sheet.tinted.borrow().len(), 1);
+
+        sprite.draw_with(0.0, 0.0, &DrawParams::new().with_tint(Color::RED.with_alpha(0.5)));
+        assert_eq!(renderer.snapshot().pixel(1, 1), [255, 0, 0, 128]);
+
+        // white with some alpha needs no copy at all
+        let (sheet, renderer) = self::sheet();
+        let sprite = sheet.create_sprite(0, 0, 2, 2);
+        sprite.draw_with(0.0, 0.0, &DrawParams::new().with_tint(Color::WHITE.with_alpha(0.5)).with_alpha(0.5));
+        assert!(sheet.tinted.borrow().is_empty());
+        assert_eq!(renderer.snapshot().pixel(0, 0), [255, 255, 255, 64]);
+    }
+
+    #[test]
+    fn tint_cache_is_bounded() {
+        let (sheet, _) = sheet();
+        let sprite = sheet.create_sprite(0, 0, 1, 1);
+        for i in 0..(TINT_CACHE_LIMIT as u32 + 10) {
+            sprite.image(Color::rgb(i as u8, (i >> 8) as u8, 0));
+        }
+        assert!(sheet.tinted.borrow().len() <= TINT_CACHE_LIMIT);
+    }
 }
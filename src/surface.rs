@@ -1,25 +1,198 @@
-use std::f64::consts::TAU;
+use std::{
+    cell::{Ref, RefMut},
+    f64::consts::TAU,
+    rc::Rc,
+};
 
-use js_sys::Array;
 use wasm_bindgen::{JsCast, prelude::*};
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
-
-use crate::{event::Event, util::Mut, V2};
+use web_sys::{CanvasRenderingContext2d, Element, HtmlCanvasElement, HtmlElement};
+
+use crate::{
+    camera::Camera2D,
+    color::Color,
+    event::Event,
+    render::{pixmap_to_canvas, BlendMode, Canvas2D, DrawQueue, FillRule, Image, Layer, Path, Pixmap, Recording, RecordingRenderer, Rect, RenderTarget, Renderer, SoftwareRenderer, Transform},
+    screen::{self, Orientation, OrientationLock, ScreenRequests},
+    util::Mut,
+    V2, v2,
+};
 
 pub type SurfaceContext = CanvasRenderingContext2d;
 
+/// How the logical resolution is mapped onto the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Fill the whole canvas, ignoring the aspect ratio
+    Stretch,
+    /// Keep the aspect ratio, leaving letterbox bars
+    Fit,
+    /// Like `Fit`, but only scale by whole numbers (as long as the canvas is big enough)
+    Integer,
+    /// Keep the aspect ratio and extend the logical size along one axis instead of bars
+    Expand,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Resolution {
+    pub size: V2,
+    pub mode: ScaleMode,
+    pub letterbox_color: Color,
+}
+
+impl Resolution {
+    pub fn new(width: f64, height: f64, mode: ScaleMode) -> Self {
+        Self {
+            size: v2![width, height],
+            mode,
+            letterbox_color: Color::BLACK,
+        }
+    }
+
+    pub fn with_letterbox_color(mut self, letterbox_color: Color) -> Self {
+        self.letterbox_color = letterbox_color;
+        self
+    }
+}
+
+/// Maps logical pixels to canvas pixels, `canvas = logical * scale + offset`
+#[derive(Debug, Clone, Copy)]
+pub struct Viewport {
+    pub size: V2,
+    pub canvas_size: V2,
+    pub scale: V2,
+    pub offset: V2,
+}
+
+impl Viewport {
+    pub fn new(canvas_size: V2, resolution: Option<Resolution>) -> Self {
+        let resolution = match resolution {
+            // a hidden or not yet laid out canvas would make the fit 0 and everything else NaN
+            Some(resolution) if canvas_size.x > 0.0 && canvas_size.y > 0.0 => resolution,
+            _ => {
+                return Self {
+                    size: canvas_size,
+                    canvas_size,
+                    scale: v2![1.0, 1.0],
+                    offset: v2![0.0, 0.0],
+                }
+            }
+        };
+        let ratio = canvas_size.component_div(&resolution.size);
+        let fit = ratio.x.min(ratio.y);
+        let (size, scale) = match resolution.mode {
+            ScaleMode::Stretch => (resolution.size, ratio),
+            ScaleMode::Fit => (resolution.size, v2![fit]),
+            ScaleMode::Integer => {
+                let scale = if fit >= 1.0 { fit.floor() } else { fit };
+                (resolution.size, v2![scale])
+            }
+            ScaleMode::Expand => (canvas_size / fit, v2![fit]),
+        };
+        Self {
+            size,
+            canvas_size,
+            scale,
+            offset: (canvas_size - size.component_mul(&scale)) / 2.0,
+        }
+    }
+
+    pub fn to_logical(&self, canvas: V2) -> V2 {
+        (canvas - self.offset).component_div(&self.scale)
+    }
+
+    pub fn to_canvas(&self, logical: V2) -> V2 {
+        logical.component_mul(&self.scale) + self.offset
+    }
+
+    pub fn has_bars(&self) -> bool {
+        self.offset.x >= 0.5 || self.offset.y >= 0.5
+    }
+
+    /// Sets the renderer transform so that drawing happens in logical pixels
+    pub fn apply(&self, renderer: &dyn Renderer) {
+        renderer.set_transform(Transform::new(self.scale.x, 0.0, 0.0, self.scale.y, self.offset.x, self.offset.y));
+    }
+}
+
+/// Where the game canvas lives on the page
+#[derive(Debug, Clone, Default)]
+pub enum Host {
+    /// A new canvas appended to the body and sized to the whole window
+    #[default]
+    Window,
+    /// A new canvas appended to the element and sized to it, the element must have a size
+    Element(HtmlElement),
+    /// An existing canvas, sized to its own css box at the start, which is then
+    /// kept fixed, use [Host::Element] with a container to follow the page layout
+    Canvas(HtmlCanvasElement),
+}
+
+impl Host {
+    pub fn element_id(id: &str) -> Self {
+        Host::Element(
+            super::document()
+                .get_element_by_id(id)
+                .and_then(|e| e.dyn_into().ok())
+                .unwrap_or_else(|| panic!("No html element with id '{}'", id)),
+        )
+    }
+
+    pub fn canvas_id(id: &str) -> Self {
+        Host::Canvas(
+            super::document()
+                .get_element_by_id(id)
+                .and_then(|e| e.dyn_into().ok())
+                .unwrap_or_else(|| panic!("No canvas with id '{}'", id)),
+        )
+    }
+}
+
+// the parts that only exist when there is an actual page
 #[derive(Clone)]
-pub struct Surface {
-    size: Mut<V2>,
+struct Web {
+    screen_requests: Mut<ScreenRequests>,
+    fullscreen_target: Element,
+    canvas: HtmlCanvasElement,
     context: SurfaceContext,
 }
 
-fn setup_canvas(events: Mut<Vec<Event>>, size: Mut<V2>) -> CanvasRenderingContext2d {
-    let canvas = super::document()
-        .create_element("canvas")
-        .map_err(|_| ())
-        .and_then(|e| e.dyn_into::<HtmlCanvasElement>().map_err(|_| ()))
-        .expect("Failed to create canvas");
+#[derive(Clone)]
+pub struct Surface {
+    viewport: Mut<Viewport>,
+    resolution: Mut<Option<Resolution>>,
+    image_smoothing: Mut<bool>,
+    camera: Mut<Camera2D>,
+    renderer: Rc<dyn Renderer>,
+    recorder: Mut<Option<Rc<RecordingRenderer>>>,
+    last_frame: Mut<Option<Recording>>,
+    draw_queue: Mut<DrawQueue>,
+    web: Option<Web>,
+}
+
+fn observe_resize(target: &Element, callback: &js_sys::Function) {
+    #[wasm_bindgen(inline_js = "export function observe_resize(e, f) { new ResizeObserver(() => f()).observe(e) }")]
+    extern "C" {
+        fn observe_resize(target: &Element, callback: &js_sys::Function);
+    }
+    observe_resize(target, callback);
+}
+
+fn setup_canvas(
+    host: &Host,
+    events: Mut<Vec<Event>>,
+    viewport: Mut<Viewport>,
+    resolution: Mut<Option<Resolution>>,
+    image_smoothing: Mut<bool>,
+    camera: Mut<Camera2D>,
+) -> (HtmlCanvasElement, CanvasRenderingContext2d) {
+    let canvas = match host {
+        Host::Canvas(canvas) => canvas.clone(),
+        _ => super::document()
+            .create_element("canvas")
+            .map_err(|_| ())
+            .and_then(|e| e.dyn_into::<HtmlCanvasElement>().map_err(|_| ()))
+            .expect("Failed to create canvas"),
+    };
 
     let context: CanvasRenderingContext2d = canvas
         .get_context("2d")
@@ -28,80 +201,428 @@ fn setup_canvas(events: Mut<Vec<Event>>, size: Mut<V2>) -> CanvasRenderingContex
         .and_then(|obj| obj.dyn_into::<CanvasRenderingContext2d>().ok())
         .expect("No canvas 2d context?");
 
+    match host {
+        Host::Window => {
+            super::body()
+                .append_child(&canvas)
+                .expect("Failed to add canvas");
+        }
+        Host::Element(element) => {
+            canvas.set_attribute("style", "display: block;").unwrap();
+            element
+                .append_child(&canvas)
+                .expect("Failed to add canvas");
+        }
+        Host::Canvas(_) => {}
+    }
+
     let moved_window = super::window();
     let moved_canvas = canvas.clone();
     let moved_context = context.clone();
-    let moved_size = size; //.clone();
+    let moved_viewport = viewport.clone();
+    let moved_host = host.clone();
+    let is_window = matches!(host, Host::Window);
     let resize = move || {
         let ratio = moved_window.device_pixel_ratio();
 
-        let width = moved_window
-            .inner_width()
-            .ok()
-            .and_then(|js| js.as_f64())
-            .unwrap();
-        let height = moved_window
-            .inner_height()
-            .ok()
-            .and_then(|js| js.as_f64())
-            .unwrap();
+        let (width, height) = if is_window {
+            let width = moved_window
+                .inner_width()
+                .ok()
+                .and_then(|js| js.as_f64())
+                .unwrap();
+            let height = moved_window
+                .inner_height()
+                .ok()
+                .and_then(|js| js.as_f64())
+                .unwrap();
+
+            let style = format!("width: {}px; height: {}px;", width, height);
+            moved_canvas.set_attribute("style", &style).unwrap();
+
+            (width, height)
+        } else {
+            // the backing size changes the client size of an unstyled canvas, or of an element
+            // that takes its height from the canvas, so the css size is fixed first or every
+            // resize would grow it by another device pixel ratio
+            let (width, height) = match &moved_host {
+                Host::Element(element) => (element.client_width() as f64, element.client_height() as f64),
+                _ => (moved_canvas.client_width() as f64, moved_canvas.client_height() as f64),
+            };
+            let style = moved_canvas.style();
+            style.set_property("width", &format!("{}px", width)).unwrap();
+            style.set_property("height", &format!("{}px", height)).unwrap();
+            (width, height)
+        };
 
         let scaled_width = width * ratio;
         let scaled_height = height * ratio;
+        if moved_canvas.width() != scaled_width as u32 || moved_canvas.height() != scaled_height as u32 {
+            moved_canvas.set_width(scaled_width as u32);
+            moved_canvas.set_height(scaled_height as u32);
 
-        moved_canvas.set_width(scaled_width as u32);
-        moved_canvas.set_height(scaled_height as u32);
-
-        let style = format!("width: {}px; height: {}px;", width, height);
-        moved_canvas.set_attribute("style", &style).unwrap();
-
-        moved_context.set_text_align("center");
-        moved_context.set_text_baseline("middle");
+            // resizing the canvas resets the whole context state
+            moved_context.set_image_smoothing_enabled(*image_smoothing.borrow());
+        }
 
-        *moved_size.borrow_mut() = [scaled_width, scaled_height].into();
+        *moved_viewport.borrow_mut() = Viewport::new([scaled_width, scaled_height].into(), *resolution.borrow());
     };
     resize();
 
-    let on_resize = Closure::wrap(Box::new(move |_e| resize()) as Box<dyn FnMut(web_sys::Event)>);
+    let on_resize = Closure::wrap(Box::new(resize) as Box<dyn FnMut()>);
 
-    super::window()
-        .add_event_listener_with_callback("resize", on_resize.as_ref().unchecked_ref())
-        .unwrap();
+    match host {
+        Host::Window => super::window()
+            .add_event_listener_with_callback("resize", on_resize.as_ref().unchecked_ref())
+            .unwrap(),
+        Host::Element(element) => observe_resize(element, on_resize.as_ref().unchecked_ref()),
+        Host::Canvas(canvas) => observe_resize(canvas, on_resize.as_ref().unchecked_ref()),
+    }
 
     on_resize.forget();
 
-    super::body()
-        .append_child(&canvas)
-        .expect("Failed to add canvas");
-
-    super::event::setup_pointer_events(&canvas, &context, events.clone());
-    super::event::setup_keyboard_events(&super::document(), events);
+    super::event::setup_pointer_events(&canvas, camera, viewport, events.clone());
+    if is_window {
+        super::event::setup_keyboard_events(&super::document(), events.clone());
+    } else {
+        // embedded games only get the keyboard when focused, so that
+        // several of them (and the page itself) can live together
+        if !canvas.has_attribute("tabindex") {
+            canvas.set_attribute("tabindex", "0").unwrap();
+        }
+        super::event::setup_keyboard_events(&canvas, events.clone());
+    }
+    super::event::setup_gamepad_events(&super::window(), events);
 
-    context
+    (canvas, context)
 }
 
 impl Surface {
-    pub fn new(events: Mut<Vec<Event>>) -> Self {
-        let size = Mut::new([0.0, 0.0].into());
-        let context = setup_canvas(events, size.clone());
-        Self { size, context }
+    pub fn new(host: &Host, events: Mut<Vec<Event>>) -> Self {
+        let viewport = Mut::new(Viewport::new(v2![0.0, 0.0], None));
+        let resolution = Mut::new(None);
+        let image_smoothing = Mut::new(true);
+        let camera = Mut::default();
+        let screen_requests = Mut::default();
+        let (canvas, context) = setup_canvas(
+            host,
+            events.clone(),
+            viewport.clone(),
+            resolution.clone(),
+            image_smoothing.clone(),
+            camera.clone(),
+        );
+        let fullscreen_target = match host {
+            Host::Window => super::document().document_element().unwrap(),
+            Host::Element(element) => element.clone().into(),
+            Host::Canvas(canvas) => canvas.clone().into(),
+        };
+        screen::setup_screen_events(fullscreen_target.clone(), screen_requests.clone(), events);
+        Self {
+            viewport,
+            resolution,
+            image_smoothing,
+            camera,
+            renderer: Rc::new(Canvas2D::new(context.clone())),
+            recorder: Mut::default(),
+            last_frame: Mut::default(),
+            draw_queue: Mut::default(),
+            web: Some(Web {
+                screen_requests,
+                fullscreen_target,
+                canvas,
+                context,
+            }),
+        }
+    }
+
+    /// A surface without a page that draws with the [SoftwareRenderer],
+    /// for running things natively or in tests
+    pub fn headless(width: u32, height: u32) -> Self {
+        Self::with_renderer(Rc::new(SoftwareRenderer::new(width, height)))
     }
 
+    /// A surface without a page that draws with the given renderer
+    pub fn with_renderer(renderer: Rc<dyn Renderer>) -> Self {
+        Self {
+            viewport: Mut::new(Viewport::new(renderer.size(), None)),
+            resolution: Mut::new(None),
+            image_smoothing: Mut::new(true),
+            camera: Mut::default(),
+            renderer,
+            recorder: Mut::default(),
+            last_frame: Mut::default(),
+            draw_queue: Mut::default(),
+            web: None,
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.web.is_none()
+    }
+
+    fn web(&self) -> &Web {
+        self.web.as_ref().expect("Headless surface has no canvas")
+    }
+
+    pub fn canvas(&self) -> HtmlCanvasElement {
+        self.web().canvas.clone()
+    }
+
+    /// The raw canvas context, prefer [Surface::renderer] which also works headless
     pub fn context(&self) -> CanvasRenderingContext2d {
-        self.context.clone()
+        self.web().context.clone()
+    }
+
+    pub fn renderer(&self) -> Rc<dyn Renderer> {
+        match &*self.recorder.borrow() {
+            Some(recorder) => recorder.clone(),
+            None => self.renderer.clone(),
+        }
+    }
+
+    /// An offscreen target with the same drawing API, for pre-rendering
+    /// things once and drawing them every frame
+    pub fn create_target(&self, width: u32, height: u32) -> RenderTarget {
+        RenderTarget::new(self.clone(), width, height, *self.image_smoothing.borrow())
+    }
+
+    /// A copy of the part of the image multiplied by the color, alpha included
+    pub(crate) fn tint_image(&self, image: &Image, src: Rect, color: Color) -> Image {
+        let target = self.create_target(src.w as u32, src.h as u32);
+        let dst = Rect::new(0.0, 0.0, src.w, src.h);
+        target.draw_image(image, src, dst);
+        target.set_blend_mode(BlendMode::Multiply);
+        target.set_fill_style(&color.with_alpha(1.0).to_css());
+        target.fill_rect(0.0, 0.0, src.w, src.h);
+        target.set_blend_mode(BlendMode::DestinationIn);
+        target.set_global_alpha(color.a);
+        target.draw_image(image, src, dst);
+        target.image()
+    }
+
+    /// Puts the pixels into a canvas once, so they are not uploaded on every draw,
+    /// headless surfaces just keep them
+    pub(crate) fn upload(&self, pixmap: Pixmap) -> Image {
+        if self.is_headless() {
+            pixmap.into()
+        } else {
+            Image::Canvas(pixmap_to_canvas(&pixmap))
+        }
+    }
+
+    /// Starts recording everything drawn through [Surface::renderer],
+    /// renderers that were taken out of the surface before this are not recorded
+    pub fn start_recording(&self) {
+        let mut recorder = self.recorder.borrow_mut();
+        if recorder.is_none() {
+            *recorder = Some(Rc::new(RecordingRenderer::new(self.renderer.clone())));
+        }
+    }
+
+    /// Stops recording, returning whatever was not taken yet
+    pub fn stop_recording(&self) -> Option<Recording> {
+        self.last_frame.borrow_mut().take();
+        self.recorder.borrow_mut().take().map(|recorder| recorder.take())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.borrow().is_some()
+    }
+
+    /// Takes the commands recorded since the start of the current frame (or the last take)
+    pub fn take_recording(&self) -> Option<Recording> {
+        self.recorder.borrow().as_ref().map(|recorder| recorder.take())
+    }
+
+    /// The commands of the last complete frame, while recording
+    pub fn last_frame(&self) -> Option<Recording> {
+        self.last_frame.borrow().clone()
     }
 
+    /// Logical size of the surface, this is what the camera and the
+    /// screen positions of events use
     pub fn size(&self) -> V2 {
-        *self.size.borrow()
+        self.viewport.borrow().size
+    }
+
+    /// Actual size of the canvas in pixels
+    pub fn canvas_size(&self) -> V2 {
+        self.viewport.borrow().canvas_size
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        *self.viewport.borrow()
+    }
+
+    pub fn resolution(&self) -> Option<Resolution> {
+        *self.resolution.borrow()
+    }
+
+    /// Sets the logical resolution, `None` makes it match the canvas
+    pub fn set_resolution(&self, resolution: Option<Resolution>) {
+        *self.resolution.borrow_mut() = resolution;
+        let canvas_size = self.canvas_size();
+        *self.viewport.borrow_mut() = Viewport::new(canvas_size, resolution);
+    }
+
+    /// Turn this off for crisp pixel art
+    pub fn set_image_smoothing(&self, image_smoothing: bool) {
+        *self.image_smoothing.borrow_mut() = image_smoothing;
+        self.renderer().set_image_smoothing(image_smoothing);
+    }
+
+    /// Makes the game (the host element, or the whole page when there is none) fullscreen.
+    ///
+    /// If the browser refuses because this is not called from a user gesture,
+    /// the request is repeated on the next click, touch or key press
+    pub fn request_fullscreen(&self) {
+        if let Some(web) = &self.web {
+            screen::request_fullscreen_on(&web.fullscreen_target, &web.screen_requests);
+        }
+    }
+
+    pub fn exit_fullscreen(&self) {
+        if let Some(web) = &self.web {
+            screen::exit_fullscreen_on(&web.fullscreen_target, &web.screen_requests);
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.web
+            .as_ref()
+            .is_some_and(|web| screen::is_fullscreen(&web.fullscreen_target))
+    }
+
+    pub fn toggle_fullscreen(&self) {
+        if self.is_fullscreen() {
+            self.exit_fullscreen()
+        } else {
+            self.request_fullscreen()
+        }
+    }
+
+    /// Most mobile browsers only allow this in fullscreen, so it is also
+    /// retried after entering fullscreen and on user gestures
+    pub fn lock_orientation(&self, lock: OrientationLock) {
+        if let Some(web) = &self.web {
+            screen::request_orientation_lock(lock, &web.screen_requests);
+        }
+    }
+
+    pub fn unlock_orientation(&self) {
+        if let Some(web) = &self.web {
+            screen::unlock_orientation_lock(&web.screen_requests);
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        match &self.web {
+            Some(_) => Orientation::current(),
+            None if self.canvas_size().x > self.canvas_size().y => Orientation::Landscape,
+            None => Orientation::Portrait,
+        }
+    }
+
+    pub fn camera(&self) -> Ref<Camera2D> {
+        self.camera.borrow()
+    }
+
+    pub fn camera_mut(&self) -> RefMut<Camera2D> {
+        self.camera.borrow_mut()
+    }
+
+    pub fn world_to_screen(&self, world: V2) -> V2 {
+        self.camera().world_to_screen(world, self.size())
+    }
+
+    pub fn screen_to_world(&self, screen: V2) -> V2 {
+        self.camera().screen_to_world(screen, self.size())
+    }
+
+    /// Sets the renderer transform to the logical screen space, ignoring the camera
+    pub fn reset_transform(&self) {
+        self.viewport().apply(&*self.renderer());
+    }
+
+    /// Queues a draw into the given layer, the queue is drawn at the end of the frame
+    /// sorted by layer and then by `z`, draws with equal ones keep their order.
+    ///
+    /// The current transform is captured here and restored for the draw,
+    /// so call [Surface::reset_transform] before submitting screen-space stuff
+    pub fn submit(&self, layer: Layer, z: i32, draw: impl FnOnce(&dyn Renderer) + 'static) {
+        let transform = self.renderer().get_transform();
+        self.draw_queue.borrow_mut().push(layer, z, transform, Box::new(draw));
+    }
+
+    /// Draws everything submitted so far, the engine does this at the end of every frame.
+    ///
+    /// Draws can submit more draws, those go after everything else, but only
+    /// once, whatever those submit in turn is dropped so a draw that keeps
+    /// resubmitting itself can't hang the frame
+    pub fn flush_layers(&self) {
+        const PASSES: usize = 2;
+        let renderer = self.renderer();
+        for _ in 0..PASSES {
+            let draws = self.draw_queue.borrow_mut().take();
+            if draws.is_empty() {
+                return;
+            }
+            for draw in draws {
+                draw.run(&*renderer);
+            }
+        }
+        let dropped = self.draw_queue.borrow_mut().take().len();
+        if dropped > 0 {
+            log::warn!("Dropped {} draws submitted in the last pass, is something resubmitting itself?", dropped);
+        }
+    }
+
+    pub(crate) fn begin_frame(&self, delta_time: f64) {
+        // drop whatever was drawn between the frames, e.g. from event handlers
+        self.take_recording();
+        let size = self.size();
+        let mut camera = self.camera_mut();
+        camera.update(delta_time, size);
+        self.reset_transform();
+        camera.apply(&*self.renderer(), size);
+    }
+
+    pub(crate) fn end_frame(&self) {
+        self.draw_letterbox();
+        if let Some(recording) = self.take_recording() {
+            *self.last_frame.borrow_mut() = Some(recording);
+        }
+    }
+
+    fn draw_letterbox(&self) {
+        let viewport = self.viewport();
+        let resolution = match self.resolution() {
+            Some(resolution) if viewport.has_bars() => resolution,
+            _ => return,
+        };
+        // cover anything that was drawn outside of the logical screen
+        let ctx = self.renderer();
+        let (canvas, offset) = (viewport.canvas_size, viewport.offset);
+        let inner = viewport.size.component_mul(&viewport.scale);
+        ctx.save();
+        ctx.set_transform(Transform::identity());
+        ctx.fill_color(resolution.letterbox_color);
+        ctx.fill_rect(0.0, 0.0, canvas.x, offset.y);
+        ctx.fill_rect(0.0, offset.y + inner.y, canvas.x, canvas.y - offset.y - inner.y);
+        ctx.fill_rect(0.0, 0.0, offset.x, canvas.y);
+        ctx.fill_rect(offset.x + inner.x, 0.0, canvas.x - offset.x - inner.x, canvas.y);
+        ctx.restore();
     }
 }
 
+/// Shorthands over the [Renderer] calls
 pub trait SurfaceContextExt {
     fn line_dash(&self, pattern: &[f64]);
 
-    fn stroke_color(&self, style: &str);
+    fn stroke_color(&self, color: Color);
 
-    fn fill_color(&self, style: &str);
+    fn fill_color(&self, color: Color);
 
     fn line(&self, from: V2, to: V2);
 
@@ -110,23 +631,61 @@ pub trait SurfaceContextExt {
     fn fill_circle(&self, pos: V2, radius: f64);
 
     fn clip_evenodd(&self);
+
+    fn polyline(&self, points: &[V2]);
+
+    fn polygon(&self, points: &[V2]);
+
+    fn fill_polygon(&self, points: &[V2]);
+
+    fn rectangle(&self, pos: V2, size: V2);
+
+    fn fill_rectangle(&self, pos: V2, size: V2);
+
+    fn rounded_rect(&self, pos: V2, size: V2, radius: f64);
+
+    fn fill_rounded_rect(&self, pos: V2, size: V2, radius: f64);
+
+    /// Just the arc line, angles are in radians clockwise from the x axis
+    fn stroke_arc(&self, center: V2, radius: f64, start: f64, end: f64);
+
+    /// Outline of a circle sector
+    fn pie(&self, center: V2, radius: f64, start: f64, end: f64);
+
+    fn fill_pie(&self, center: V2, radius: f64, start: f64, end: f64);
+
+    fn stroke_ellipse(&self, center: V2, radii: V2, rotation: f64);
+
+    fn fill_ellipse(&self, center: V2, radii: V2, rotation: f64);
+
+    fn quadratic(&self, from: V2, control: V2, to: V2);
+
+    fn bezier(&self, from: V2, c1: V2, c2: V2, to: V2);
+
+    /// A line with an open head of the given size at `to`
+    fn arrow(&self, from: V2, to: V2, head_size: f64);
+
+    /// Lines of a grid starting at `pos` with `cells` cells of `cell_size`
+    fn grid(&self, pos: V2, cell_size: V2, cells: (u32, u32));
+
+    fn stroke_path(&self, path: &Path);
+
+    fn fill_path(&self, path: &Path);
+
+    fn clip_path(&self, path: &Path);
 }
 
-impl SurfaceContextExt for SurfaceContext {
+impl<R: Renderer + ?Sized> SurfaceContextExt for R {
     fn line_dash(&self, pattern: &[f64]) {
-        let array = Array::new_with_length(pattern.len() as u32);
-        for (i, x) in pattern.iter().copied().enumerate() {
-            array.set(i as u32, x.into());
-        }
-        self.set_line_dash(&array.into()).unwrap();
+        self.set_line_dash(pattern);
     }
 
-    fn stroke_color(&self, color: &str) {
-        self.set_stroke_style(&color.into());
+    fn stroke_color(&self, color: Color) {
+        self.set_stroke_style(&color.to_css());
     }
 
-    fn fill_color(&self, color: &str) {
-        self.set_fill_style(&color.into());
+    fn fill_color(&self, color: Color) {
+        self.set_fill_style(&color.to_css());
     }
 
     fn line(&self, from: V2, to: V2) {
@@ -138,21 +697,124 @@ impl SurfaceContextExt for SurfaceContext {
 
     fn circle(&self, pos: V2, radius: f64) {
         self.begin_path();
-        self.arc(pos.x, pos.y, radius, 0.0, TAU).unwrap();
+        self.arc(pos.x, pos.y, radius, 0.0, TAU, false);
         self.stroke();
     }
 
     fn fill_circle(&self, pos: V2, radius: f64) {
         self.begin_path();
-        self.arc(pos.x, pos.y, radius, 0.0, TAU).unwrap();
-        self.fill();
+        self.arc(pos.x, pos.y, radius, 0.0, TAU, false);
+        self.fill(FillRule::NonZero);
     }
 
     fn clip_evenodd(&self) {
-        #[wasm_bindgen(inline_js = "export function clip_evenodd(s) { s.clip(\"evenodd\") }")]
-        extern "C" {
-            fn clip_evenodd(this: &SurfaceContext);
+        self.clip(FillRule::EvenOdd);
+    }
+
+    fn polyline(&self, points: &[V2]) {
+        self.stroke_path(&Path::polyline(points, false));
+    }
+
+    fn polygon(&self, points: &[V2]) {
+        self.stroke_path(&Path::polygon(points));
+    }
+
+    fn fill_polygon(&self, points: &[V2]) {
+        self.fill_path(&Path::polygon(points));
+    }
+
+    fn rectangle(&self, pos: V2, size: V2) {
+        self.stroke_rect(pos.x, pos.y, size.x, size.y);
+    }
+
+    fn fill_rectangle(&self, pos: V2, size: V2) {
+        self.fill_rect(pos.x, pos.y, size.x, size.y);
+    }
+
+    fn rounded_rect(&self, pos: V2, size: V2, radius: f64) {
+        self.stroke_path(&Path::rounded_rect(pos, size, radius));
+    }
+
+    fn fill_rounded_rect(&self, pos: V2, size: V2, radius: f64) {
+        self.fill_path(&Path::rounded_rect(pos, size, radius));
+    }
+
+    fn stroke_arc(&self, center: V2, radius: f64, start: f64, end: f64) {
+        self.begin_path();
+        self.arc(center.x, center.y, radius, start, end, false);
+        self.stroke();
+    }
+
+    fn pie(&self, center: V2, radius: f64, start: f64, end: f64) {
+        self.stroke_path(&Path::pie(center, radius, start, end));
+    }
+
+    fn fill_pie(&self, center: V2, radius: f64, start: f64, end: f64) {
+        self.fill_path(&Path::pie(center, radius, start, end));
+    }
+
+    fn stroke_ellipse(&self, center: V2, radii: V2, rotation: f64) {
+        self.stroke_path(&Path::new().ellipse(center, radii, rotation));
+    }
+
+    fn fill_ellipse(&self, center: V2, radii: V2, rotation: f64) {
+        self.fill_path(&Path::new().ellipse(center, radii, rotation));
+    }
+
+    fn quadratic(&self, from: V2, control: V2, to: V2) {
+        self.stroke_path(&Path::new().move_to(from).quadratic_to(control, to));
+    }
+
+    fn bezier(&self, from: V2, c1: V2, c2: V2, to: V2) {
+        self.stroke_path(&Path::new().move_to(from).bezier_to(c1, c2, to));
+    }
+
+    fn arrow(&self, from: V2, to: V2, head_size: f64) {
+        let dir = to - from;
+        let len = dir.norm();
+        if len <= f64::EPSILON {
+            return;
+        }
+        let dir = dir / len;
+        let normal = v2![-dir.y, dir.x] * head_size / 2.0;
+        let base = to - dir * head_size.min(len);
+        self.begin_path();
+        self.move_to(from.x, from.y);
+        self.line_to(to.x, to.y);
+        self.move_to(base.x + normal.x, base.y + normal.y);
+        self.line_to(to.x, to.y);
+        self.line_to(base.x - normal.x, base.y - normal.y);
+        self.stroke();
+    }
+
+    fn grid(&self, pos: V2, cell_size: V2, cells: (u32, u32)) {
+        let size = v2![cell_size.x * cells.0 as f64, cell_size.y * cells.1 as f64];
+        self.begin_path();
+        for i in 0..=cells.0 {
+            let x = pos.x + cell_size.x * i as f64;
+            self.move_to(x, pos.y);
+            self.line_to(x, pos.y + size.y);
         }
-        clip_evenodd(self);
+        for i in 0..=cells.1 {
+            let y = pos.y + cell_size.y * i as f64;
+            self.move_to(pos.x, y);
+            self.line_to(pos.x + size.x, y);
+        }
+        self.stroke();
+    }
+
+    fn stroke_path(&self, path: &Path) {
+        path.trace(self);
+        self.stroke();
+    }
+
+    fn fill_path(&self, path: &Path) {
+        path.trace(self);
+        self.fill(FillRule::NonZero);
+    }
+
+    fn clip_path(&self, path: &Path) {
+        path.trace(self);
+        self.clip(FillRule::NonZero);
     }
 }
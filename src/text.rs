@@ -0,0 +1,492 @@
+use crate::{
+    color::Color,
+    font::Font,
+    render::{Rect, Renderer, TextAlign, TextBaseline},
+    V2, v2,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextStyle {
+    pub font: Font,
+    pub color: Color,
+    pub align: TextAlign,
+    /// Where the position is vertically, for multiple lines `Top` and `Bottom`
+    /// are the edges of the whole block, `Middle` is its center and
+    /// `Alphabetic` is the baseline of the first line
+    pub baseline: TextBaseline,
+    /// Multiplier of the font size
+    pub line_spacing: f64,
+    pub max_width: Option<f64>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            font: Font::default(),
+            color: Color::BLACK,
+            align: TextAlign::Left,
+            baseline: TextBaseline::Top,
+            line_spacing: 1.2,
+            max_width: None,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn new(font: impl Into<Font>) -> Self {
+        Self {
+            font: font.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn with_baseline(mut self, baseline: TextBaseline) -> Self {
+        self.baseline = baseline;
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f64) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    /// Words are wrapped to fit, words longer than that are broken
+    pub fn with_max_width(mut self, max_width: f64) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn line_height(&self) -> f64 {
+        let height = match &self.font {
+            Font::Css(spec) => spec.size,
+            Font::Bitmap { font, size } => font.line_height() * font.scale_for(*size),
+        };
+        height * self.line_spacing
+    }
+}
+
+/// A piece of text with its own style, `None`s use the [TextStyle] ones.
+/// Bitmap fonts have no bold or italic variants, so those only affect css fonts
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl Span {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    fn font(&self, base: &Font) -> Font {
+        match base {
+            Font::Css(spec) => {
+                let mut spec = spec.clone();
+                if self.bold {
+                    spec = spec.bold();
+                }
+                if self.italic {
+                    spec = spec.italic();
+                }
+                Font::Css(spec)
+            }
+            bitmap => bitmap.clone(),
+        }
+    }
+}
+
+/// Parses `[b]bold[/b]`, `[i]italic[/i]` and `[color=red]colored[/color]`
+/// (any [Color::parse] color) tags, which can be nested.
+/// `[[` is a literal `[` and unknown tags are left as they are.
+pub fn parse_markup(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut colors: Vec<Color> = Vec::new();
+    let (mut bold, mut italic) = (0, 0);
+    let mut current = String::new();
+
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        current.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("[[") {
+            current.push('[');
+            rest = after;
+            continue;
+        }
+        let end = match rest.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[1..end];
+        let known = match tag {
+            "b" | "/b" | "i" | "/i" | "/color" => true,
+            _ => tag.strip_prefix("color=").and_then(Color::parse).is_some(),
+        };
+        if !known {
+            current.push('[');
+            rest = &rest[1..];
+            continue;
+        }
+        if !current.is_empty() {
+            spans.push(Span {
+                text: std::mem::take(&mut current),
+                color: colors.last().copied(),
+                bold: bold > 0,
+                italic: italic > 0,
+            });
+        }
+        match tag {
+            "b" => bold += 1,
+            "/b" => bold = 0.max(bold - 1),
+            "i" => italic += 1,
+            "/i" => italic = 0.max(italic - 1),
+            "/color" => {
+                colors.pop();
+            }
+            _ => colors.extend(tag.strip_prefix("color=").and_then(Color::parse)),
+        }
+        rest = &rest[end + 1..];
+    }
+    current.push_str(rest);
+    if !current.is_empty() {
+        spans.push(Span {
+            text: current,
+            color: colors.last().copied(),
+            bold: bold > 0,
+            italic: italic > 0,
+        });
+    }
+    spans
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    pub text: String,
+    pub font: Font,
+    pub color: Color,
+    /// Offset from the start of the line
+    pub x: f64,
+    pub width: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextLine {
+    pub runs: Vec<TextRun>,
+    pub width: f64,
+}
+
+impl TextLine {
+    fn push(&mut self, text: &str, font: &Font, color: Color, width: f64) {
+        match self.runs.last_mut() {
+            Some(last) if last.font == *font && last.color == color => {
+                last.text.push_str(text);
+                last.width += width;
+            }
+            _ => self.runs.push(TextRun {
+                text: text.to_owned(),
+                font: font.clone(),
+                color,
+                x: self.width,
+                width,
+            }),
+        }
+        self.width += width;
+    }
+
+    fn trim_end(&mut self) {
+        while let Some(last) = self.runs.last_mut() {
+            let trimmed = last.text.trim_end().len();
+            if trimmed == last.text.len() {
+                break;
+            }
+            // close enough, trailing spaces are not drawn anyway
+            let removed = last.width * (1.0 - trimmed as f64 / last.text.len() as f64);
+            last.text.truncate(trimmed);
+            last.width -= removed;
+            self.width -= removed;
+            if last.text.is_empty() {
+                self.runs.pop();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Text broken into lines and styled runs, ready to be measured and drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLayout {
+    pub lines: Vec<TextLine>,
+    pub style: TextStyle,
+}
+
+impl TextLayout {
+    pub fn new(renderer: &dyn Renderer, spans: &[Span], style: &TextStyle) -> Self {
+        let mut lines = vec![TextLine::default()];
+        let max_width = style.max_width.unwrap_or(f64::INFINITY);
+
+        // measuring sets the font, which is not for the caller to see
+        renderer.save();
+        for span in spans {
+            let font = span.font(&style.font);
+            let color = span.color.unwrap_or(style.color);
+            let measure = |text: &str| match &font {
+                Font::Css(_) => renderer.measure_text(text),
+                Font::Bitmap { font, size } => font.measure(text, font.scale_for(*size)),
+            };
+            if let Font::Css(spec) = &font {
+                renderer.set_font(&spec.to_css());
+            }
+
+            for (i, paragraph) in span.text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.last_mut().unwrap().trim_end();
+                    lines.push(TextLine::default());
+                }
+                for piece in split_words(paragraph) {
+                    let width = measure(piece);
+                    let line = lines.last_mut().unwrap();
+                    let is_space = piece.trim().is_empty();
+                    if line.width + width <= max_width || is_space {
+                        line.push(piece, &font, color, width);
+                        continue;
+                    }
+                    if !line.runs.is_empty() {
+                        line.trim_end();
+                        lines.push(TextLine::default());
+                    }
+                    if width <= max_width {
+                        lines.last_mut().unwrap().push(piece, &font, color, width);
+                        continue;
+                    }
+                    // too long for a line of its own, break it at chars
+                    for c in piece.chars() {
+                        let mut buf = [0; 4];
+                        let c = c.encode_utf8(&mut buf);
+                        let width = measure(c);
+                        let line = lines.last_mut().unwrap();
+                        if line.width + width > max_width && !line.runs.is_empty() {
+                            lines.push(TextLine::default());
+                        }
+                        lines.last_mut().unwrap().push(c, &font, color, width);
+                    }
+                }
+            }
+        }
+        renderer.restore();
+        lines.last_mut().unwrap().trim_end();
+        Self {
+            lines,
+            style: style.clone(),
+        }
+    }
+
+    pub fn plain(renderer: &dyn Renderer, text: &str, style: &TextStyle) -> Self {
+        Self::new(renderer, &[Span::new(text)], style)
+    }
+
+    /// See [parse_markup]
+    pub fn markup(renderer: &dyn Renderer, text: &str, style: &TextStyle) -> Self {
+        Self::new(renderer, &parse_markup(text), style)
+    }
+
+    pub fn width(&self) -> f64 {
+        self.lines.iter().map(|l| l.width).fold(0.0, f64::max)
+    }
+
+    pub fn height(&self) -> f64 {
+        self.lines.len() as f64 * self.style.line_height()
+    }
+
+    pub fn size(&self) -> V2 {
+        v2![self.width(), self.height()]
+    }
+
+    fn top(&self, y: f64) -> f64 {
+        let line_height = self.style.line_height();
+        match self.style.baseline {
+            TextBaseline::Top => y,
+            TextBaseline::Middle => y - self.height() / 2.0,
+            TextBaseline::Bottom => y - self.height(),
+            TextBaseline::Alphabetic => match &self.style.font {
+                // roughly where the alphabetic baseline is below the middle of the line
+                Font::Css(spec) => y - line_height / 2.0 - spec.size * 0.35,
+                Font::Bitmap { font, size } => {
+                    let scale = font.scale_for(*size);
+                    let base = font.data().map_or(0.0, |d| d.base);
+                    y - (line_height - font.line_height() * scale) / 2.0 - base * scale
+                }
+            },
+        }
+    }
+
+    fn line_x(&self, x: f64, line: &TextLine) -> f64 {
+        match self.style.align {
+            TextAlign::Left => x,
+            TextAlign::Center => x - line.width / 2.0,
+            TextAlign::Right => x - line.width,
+        }
+    }
+
+    /// The rectangle the text takes when drawn at the position
+    pub fn bounds(&self, pos: V2) -> Rect {
+        let width = self.width();
+        let x = match self.style.align {
+            TextAlign::Left => pos.x,
+            TextAlign::Center => pos.x - width / 2.0,
+            TextAlign::Right => pos.x - width,
+        };
+        Rect::new(x, self.top(pos.y), width, self.height())
+    }
+
+    pub fn draw(&self, renderer: &dyn Renderer, pos: V2) {
+        let line_height = self.style.line_height();
+        let top = self.top(pos.y);
+        renderer.save();
+        renderer.set_text_align(TextAlign::Left);
+        renderer.set_text_baseline(TextBaseline::Middle);
+        for (i, line) in self.lines.iter().enumerate() {
+            let x = self.line_x(pos.x, line);
+            let y = top + line_height * (i as f64 + 0.5);
+            // no fallback fonts flashing while the real ones load
+            for run in line.runs.iter().filter(|r| r.font.is_ready()) {
+                match &run.font {
+                    Font::Css(spec) => {
+                        renderer.set_font(&spec.to_css());
+                        renderer.set_fill_style(&run.color.to_css());
+                        renderer.fill_text(&run.text, x + run.x, y);
+                    }
+                    Font::Bitmap { font, size } => {
+                        let scale = font.scale_for(*size);
+                        let top = y - font.line_height() * scale / 2.0;
+                        font.draw(renderer, &run.text, x + run.x, top, scale, run.color);
+                    }
+                }
+            }
+        }
+        renderer.restore();
+    }
+}
+
+// words and the whitespace between them as separate pieces
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let space = first.is_whitespace();
+        let end = rest
+            .char_indices()
+            .find(|(_, c)| c.is_whitespace() != space)
+            .map_or(rest.len(), |(i, _)| i);
+        let (piece, tail) = rest.split_at(end);
+        rest = tail;
+        Some(piece)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{font::FontSpec, render::SoftwareRenderer};
+
+    // the software renderer measures every char as 0.6 of the font size, so 6px here
+    fn style() -> TextStyle {
+        TextStyle::new(FontSpec::new("monospace", 10.0))
+    }
+
+    fn texts(layout: &TextLayout) -> Vec<String> {
+        layout.lines.iter().map(|l| l.runs.iter().map(|r| r.text.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn markup() {
+        let spans = parse_markup("a [b]bold [i]both[/i][/b][color=#f00]red[/color] [[x] [nope]");
+        let red = Some(Color::RED);
+        assert_eq!(
+            spans,
+            [
+                Span::new("a "),
+                Span { bold: true, ..Span::new("bold ") },
+                Span { bold: true, italic: true, ..Span::new("both") },
+                Span { color: red, ..Span::new("red") },
+                Span::new(" [x] [nope]"),
+            ]
+        );
+        // unbalanced closing tags don't go negative
+        let spans = parse_markup("[/b][/color]plain[b]bold");
+        assert_eq!(spans, [Span::new("plain"), Span { bold: true, ..Span::new("bold") }]);
+        assert_eq!(parse_markup("[b"), [Span::new("[b")]);
+        assert!(parse_markup("").is_empty());
+    }
+
+    #[test]
+    fn measuring_keeps_the_font() {
+        let renderer = SoftwareRenderer::new(1, 1);
+        renderer.set_font("20px serif");
+        let layout = TextLayout::plain(&renderer, "abc", &style());
+        assert_eq!(layout.width(), 18.0);
+        assert_eq!(renderer.measure_text("ab"), 24.0);
+    }
+
+    #[test]
+    fn word_wrapping() {
+        let renderer = SoftwareRenderer::new(1, 1);
+        let style = style().with_max_width(30.0);
+        let layout = TextLayout::plain(&renderer, "hello world foo", &style);
+        assert_eq!(texts(&layout), ["hello", "world", "foo"]);
+        assert_eq!(layout.lines[2].width, 18.0);
+
+        let layout = TextLayout::plain(&renderer, "ab abcdefghijkl", &style);
+        assert_eq!(texts(&layout), ["ab", "abcde", "fghij", "kl"]);
+
+        let layout = TextLayout::plain(&renderer, "one\ntwo  \n", &style);
+        assert_eq!(texts(&layout), ["one", "two", ""]);
+        assert_eq!(layout.height(), 3.0 * style.line_height());
+    }
+
+    #[test]
+    fn styled_runs() {
+        let renderer = SoftwareRenderer::new(1, 1);
+        let layout = TextLayout::markup(&renderer, "ab[color=red]cd[/color]", &style());
+        let runs = &layout.lines[0].runs;
+        assert_eq!(runs.len(), 2);
+        assert_eq!((runs[1].text.as_str(), runs[1].x, runs[1].width), ("cd", 12.0, 12.0));
+        assert_eq!(runs[1].color, Color::RED);
+    }
+
+    #[test]
+    fn alignment() {
+        let renderer = SoftwareRenderer::new(1, 1);
+        let layout = |align| TextLayout::plain(&renderer, "ab\nabcd", &style().with_align(align));
+
+        let left = layout(TextAlign::Left);
+        assert_eq!(left.bounds(v2![50.0, 0.0]), Rect::new(50.0, 0.0, 24.0, 24.0));
+
+        let center = layout(TextAlign::Center);
+        assert_eq!(center.line_x(50.0, &center.lines[0]), 44.0);
+        assert_eq!(center.line_x(50.0, &center.lines[1]), 38.0);
+        assert_eq!(center.bounds(v2![50.0, 0.0]).x, 38.0);
+
+        let right = layout(TextAlign::Right);
+        assert_eq!(right.line_x(50.0, &right.lines[0]), 38.0);
+        assert_eq!(right.bounds(v2![50.0, 0.0]).x, 26.0);
+
+        let bottom = TextLayout::plain(&renderer, "ab", &style().with_baseline(TextBaseline::Bottom));
+        assert_eq!(bottom.bounds(v2![0.0, 12.0]).y, 0.0);
+    }
+}
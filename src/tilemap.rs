@@ -0,0 +1,1180 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    rc::Rc,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::{
+    color::Color,
+    render::{Image, Layer, Rect, Renderer, Transform},
+    sprite::{Spritesheet, Tileset},
+    surface::Surface,
+    util::{base_url, fetch_text, Mut},
+    xml, Context, Game, V2, v2,
+};
+
+pub const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+pub const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+pub const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// In tiles, along both axes
+const CHUNK_SIZE: i32 = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Color(Color),
+    /// Path relative to the map
+    File(String),
+    /// Id of an object on the map
+    Object(u32),
+}
+
+impl Property {
+    fn parse(kind: &str, value: &str) -> Self {
+        match kind {
+            "bool" => Property::Bool(value == "true"),
+            "int" => Property::Int(value.parse().unwrap_or(0)),
+            "float" => Property::Float(value.parse().unwrap_or(0.0)),
+            "color" => Property::Color(tiled_color(value).unwrap_or(Color::TRANSPARENT)),
+            "file" => Property::File(value.to_owned()),
+            "object" => Property::Object(value.parse().unwrap_or(0)),
+            _ => Property::String(value.to_owned()),
+        }
+    }
+
+    fn from_json(kind: &str, value: &Value) -> Self {
+        match value {
+            Value::String(s) => Self::parse(kind, s),
+            Value::Bool(b) => Property::Bool(*b),
+            Value::Number(n) if kind == "int" || kind == "object" => Self::parse(kind, &n.as_i64().unwrap_or(0).to_string()),
+            Value::Number(n) => Property::Float(n.as_f64().unwrap_or(0.0)),
+            _ => Property::String(value.to_string()),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Property::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Property::Int(i) => Some(*i),
+            Property::Object(id) => Some(*id as i64),
+            _ => None,
+        }
+    }
+
+    /// Ints are converted
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Property::Float(f) => Some(*f),
+            Property::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Property::String(s) | Property::File(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_color(&self) -> Option<Color> {
+        match self {
+            Property::Color(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Property::Bool(b) => Value::from(*b),
+            Property::Int(i) => Value::from(*i),
+            Property::Float(f) => Value::from(*f),
+            Property::String(s) | Property::File(s) => Value::from(s.as_str()),
+            Property::Color(c) => Value::from(c.to_hex()),
+            Property::Object(id) => Value::from(*id),
+        }
+    }
+}
+
+pub type Properties = HashMap<String, Property>;
+
+// Tiled writes #AARRGGBB
+fn tiled_color(color: &str) -> Option<Color> {
+    let hex = color.trim().trim_start_matches('#');
+    if hex.len() == 8 {
+        Color::parse(&format!("#{}{}", &hex[2..], &hex[..2]))
+    } else {
+        Color::parse(&format!("#{}", hex))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TileInfo {
+    pub kind: String,
+    pub properties: Properties,
+    /// Local tile ids and their durations in seconds
+    pub animation: Vec<(u32, f64)>,
+}
+
+impl TileInfo {
+    /// The frame shown at the time, for animated tiles
+    pub fn frame_at(&self, time: f64) -> Option<u32> {
+        let total: f64 = self.animation.iter().map(|(_, d)| d).sum();
+        if total <= 0.0 {
+            return self.animation.first().map(|(id, _)| *id);
+        }
+        let mut time = time.rem_euclid(total);
+        for (id, duration) in &self.animation {
+            if time < *duration {
+                return Some(*id);
+            }
+            time -= duration;
+        }
+        self.animation.last().map(|(id, _)| *id)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MapTileset {
+    pub first_gid: u32,
+    pub name: String,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: u32,
+    pub tile_count: u32,
+    pub margin: u32,
+    pub spacing: u32,
+    /// Relative to the map, empty for image collection tilesets which are not supported
+    pub image: String,
+    /// By the local tile id, only the tiles that have something set in Tiled
+    pub tiles: HashMap<u32, TileInfo>,
+    /// The external `.tsx` or `.tsj`, it's loaded and merged in by [Tilemap]
+    pub source: Option<String>,
+}
+
+impl MapTileset {
+    /// An external tileset file, JSON or XML
+    pub fn parse(text: &str) -> Option<Self> {
+        if text.trim_start().starts_with('{') {
+            let json = serde_json::from_str(text)
+                .map_err(|e| log::error!("Failed to parse the tileset: {}", e))
+                .ok()?;
+            Some(Self::from_json(&json))
+        } else {
+            Some(Self::from_tmx(&xml::parse(text)?))
+        }
+    }
+
+    fn from_json(json: &Value) -> Self {
+        let tiles = json
+            .get("tiles")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|tile| {
+                let animation = tile
+                    .get("animation")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .map(|frame| (json_u32(frame, "tileid"), json_f64(frame, "duration") / 1e3))
+                    .collect();
+                let info = TileInfo {
+                    kind: json_kind(tile).to_owned(),
+                    properties: json_properties(tile),
+                    animation,
+                };
+                (json_u32(tile, "id"), info)
+            })
+            .collect();
+        Self {
+            first_gid: json_u32(json, "firstgid"),
+            name: json_str(json, "name").to_owned(),
+            tile_width: json_u32(json, "tilewidth"),
+            tile_height: json_u32(json, "tileheight"),
+            columns: json_u32(json, "columns"),
+            tile_count: json_u32(json, "tilecount"),
+            margin: json_u32(json, "margin"),
+            spacing: json_u32(json, "spacing"),
+            image: json_str(json, "image").to_owned(),
+            tiles,
+            source: json.get("source").and_then(Value::as_str).map(str::to_owned),
+        }
+    }
+
+    fn from_tmx(element: &xml::Element) -> Self {
+        let tiles = element
+            .children_named("tile")
+            .map(|tile| {
+                let animation = tile
+                    .child("animation")
+                    .into_iter()
+                    .flat_map(|a| a.children_named("frame"))
+                    .map(|frame| {
+                        let duration: f64 = frame.attr_parse("duration").unwrap_or(0.0);
+                        (frame.attr_parse("tileid").unwrap_or(0), duration / 1e3)
+                    })
+                    .collect();
+                let info = TileInfo {
+                    kind: tmx_kind(tile).to_owned(),
+                    properties: tmx_properties(tile),
+                    animation,
+                };
+                (tile.attr_parse("id").unwrap_or(0), info)
+            })
+            .collect();
+        Self {
+            first_gid: element.attr_parse("firstgid").unwrap_or(0),
+            name: element.attr("name").unwrap_or("").to_owned(),
+            tile_width: element.attr_parse("tilewidth").unwrap_or(0),
+            tile_height: element.attr_parse("tileheight").unwrap_or(0),
+            columns: element.attr_parse("columns").unwrap_or(0),
+            tile_count: element.attr_parse("tilecount").unwrap_or(0),
+            margin: element.attr_parse("margin").unwrap_or(0),
+            spacing: element.attr_parse("spacing").unwrap_or(0),
+            image: element.child("image").and_then(|i| i.attr("source")).unwrap_or("").to_owned(),
+            tiles,
+            source: element.attr("source").map(str::to_owned),
+        }
+    }
+
+    /// Replaces this reference to an external tileset with the loaded [MapTileset::source],
+    /// keeping the first gid and making the image path relative to the map
+    pub fn merge_external(&mut self, external: MapTileset) {
+        let source = self.source.take().unwrap_or_default();
+        let image = match external.image.as_str() {
+            "" => String::new(),
+            image => format!("{}{}", base_url(&source), image),
+        };
+        *self = MapTileset {
+            first_gid: self.first_gid,
+            image,
+            source: None,
+            ..external
+        };
+    }
+
+    pub fn contains(&self, gid: u32) -> bool {
+        gid >= self.first_gid && gid < self.first_gid + self.tile_count
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TileLayer {
+    pub name: String,
+    /// In tiles, infinite maps can have the chunks start anywhere
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Row by row, with the flip flags still in, 0 is empty
+    pub data: Vec<u32>,
+    pub opacity: f64,
+    pub visible: bool,
+    /// In pixels
+    pub offset: V2,
+    pub properties: Properties,
+}
+
+impl TileLayer {
+    /// The global tile id with the flip flags, 0 if it's empty or out of bounds
+    pub fn raw_tile(&self, x: i32, y: i32) -> u32 {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return 0;
+        }
+        self.data.get((y * self.width as i32 + x) as usize).copied().unwrap_or(0)
+    }
+
+    /// The global tile id, `None` for empty tiles
+    pub fn tile(&self, x: i32, y: i32) -> Option<u32> {
+        Some(self.raw_tile(x, y) & GID_MASK).filter(|&gid| gid != 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectShape {
+    Rectangle,
+    Ellipse,
+    Point,
+    /// Relative to the object position
+    Polygon(Vec<V2>),
+    Polyline(Vec<V2>),
+    /// A tile object, positioned by its bottom left corner like in Tiled
+    Tile(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    /// The type, or the class since Tiled 1.9
+    pub kind: String,
+    pub pos: V2,
+    pub size: V2,
+    /// In radians, clockwise
+    pub rotation: f64,
+    pub visible: bool,
+    pub shape: ObjectShape,
+    pub properties: Properties,
+}
+
+impl MapObject {
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.get(name)
+    }
+
+    /// The custom properties deserialized into a struct, for spawning entities
+    pub fn properties_as<T: DeserializeOwned>(&self) -> Option<T> {
+        let map = self.properties.iter().map(|(k, v)| (k.clone(), v.to_json())).collect();
+        serde_json::from_value(Value::Object(map))
+            .map_err(|e| log::warn!("Object {} has unexpected properties: {}", self.id, e))
+            .ok()
+    }
+
+    pub fn bounds(&self) -> Rect {
+        match self.shape {
+            ObjectShape::Tile(_) => Rect::new(self.pos.x, self.pos.y - self.size.y, self.size.x, self.size.y),
+            _ => Rect::new(self.pos.x, self.pos.y, self.size.x, self.size.y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+    pub opacity: f64,
+    pub visible: bool,
+    pub offset: V2,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Clone)]
+pub enum MapLayer {
+    Tiles(TileLayer),
+    Objects(ObjectLayer),
+}
+
+impl MapLayer {
+    pub fn name(&self) -> &str {
+        match self {
+            MapLayer::Tiles(layer) => &layer.name,
+            MapLayer::Objects(layer) => &layer.name,
+        }
+    }
+}
+
+/// A Tiled map, either the JSON (`.tmj`) or the XML (`.tmx`) one.
+///
+/// Layer groups are flattened with their offsets, opacity and visibility
+/// applied to the layers in them. Image layers are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct MapData {
+    /// In tiles
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub infinite: bool,
+    pub tilesets: Vec<MapTileset>,
+    pub layers: Vec<MapLayer>,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Copy)]
+struct Group {
+    offset: V2,
+    opacity: f64,
+    visible: bool,
+}
+
+const ROOT: Group = Group {
+    offset: V2::new(0.0, 0.0),
+    opacity: 1.0,
+    visible: true,
+};
+
+impl MapData {
+    pub fn parse(text: &str) -> Option<Self> {
+        if text.trim_start().starts_with('{') {
+            Self::parse_json(text)
+        } else {
+            Self::parse_tmx(text)
+        }
+    }
+
+    pub fn parse_json(text: &str) -> Option<Self> {
+        let json: Value = serde_json::from_str(text)
+            .map_err(|e| log::error!("Failed to parse the map: {}", e))
+            .ok()?;
+        if json_str(&json, "orientation") != "orthogonal" {
+            log::warn!("Only orthogonal maps are supported");
+        }
+        let mut map = Self {
+            width: json_u32(&json, "width"),
+            height: json_u32(&json, "height"),
+            tile_width: json_u32(&json, "tilewidth"),
+            tile_height: json_u32(&json, "tileheight"),
+            infinite: json.get("infinite").and_then(Value::as_bool).unwrap_or(false),
+            tilesets: json
+                .get("tilesets")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .map(MapTileset::from_json)
+                .collect(),
+            layers: Vec::new(),
+            properties: json_properties(&json),
+        };
+        map.add_json_layers(json.get("layers")?, ROOT)?;
+        Some(map)
+    }
+
+    fn add_json_layers(&mut self, layers: &Value, group: Group) -> Option<()> {
+        for layer in layers.as_array()? {
+            let name = json_str(layer, "name").to_owned();
+            let offset = group.offset + v2![json_f64(layer, "offsetx"), json_f64(layer, "offsety")];
+            let opacity = group.opacity * layer.get("opacity").and_then(Value::as_f64).unwrap_or(1.0);
+            let visible = group.visible && layer.get("visible").and_then(Value::as_bool).unwrap_or(true);
+            let properties = json_properties(layer);
+            match json_str(layer, "type") {
+                "tilelayer" => {
+                    let mut tiles = TileLayer {
+                        name,
+                        width: json_u32(layer, "width"),
+                        height: json_u32(layer, "height"),
+                        opacity,
+                        visible,
+                        offset,
+                        properties,
+                        ..TileLayer::default()
+                    };
+                    let encoding = json_str(layer, "encoding");
+                    let compression = json_str(layer, "compression");
+                    match layer.get("chunks").and_then(Value::as_array) {
+                        Some(chunks) => {
+                            let chunks = chunks
+                                .iter()
+                                .map(|chunk| {
+                                    let data = json_data(chunk.get("data")?, encoding, compression)?;
+                                    Some(Chunk {
+                                        x: json_f64(chunk, "x") as i32,
+                                        y: json_f64(chunk, "y") as i32,
+                                        width: json_u32(chunk, "width"),
+                                        height: json_u32(chunk, "height"),
+                                        data,
+                                    })
+                                })
+                                .collect::<Option<Vec<_>>>()?;
+                            merge_chunks(&mut tiles, chunks);
+                        }
+                        None => tiles.data = json_data(layer.get("data")?, encoding, compression)?,
+                    }
+                    self.layers.push(MapLayer::Tiles(tiles));
+                }
+                "objectgroup" => {
+                    let objects = layer
+                        .get("objects")
+                        .and_then(Value::as_array)
+                        .into_iter()
+                        .flatten()
+                        .map(json_object)
+                        .collect();
+                    self.layers.push(MapLayer::Objects(ObjectLayer {
+                        name,
+                        objects,
+                        opacity,
+                        visible,
+                        offset,
+                        properties,
+                    }));
+                }
+                "group" => self.add_json_layers(layer.get("layers")?, Group { offset, opacity, visible })?,
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    pub fn parse_tmx(text: &str) -> Option<Self> {
+        let root = xml::parse(text)?;
+        if root.name != "map" {
+            return None;
+        }
+        if root.attr("orientation") != Some("orthogonal") {
+            log::warn!("Only orthogonal maps are supported");
+        }
+        let mut map = Self {
+            width: root.attr_parse("width").unwrap_or(0),
+            height: root.attr_parse("height").unwrap_or(0),
+            tile_width: root.attr_parse("tilewidth").unwrap_or(0),
+            tile_height: root.attr_parse("tileheight").unwrap_or(0),
+            infinite: root.attr("infinite") == Some("1"),
+            tilesets: root.children_named("tileset").map(MapTileset::from_tmx).collect(),
+            layers: Vec::new(),
+            properties: tmx_properties(&root),
+        };
+        map.add_tmx_layers(&root, ROOT)?;
+        Some(map)
+    }
+
+    fn add_tmx_layers(&mut self, parent: &xml::Element, group: Group) -> Option<()> {
+        for layer in &parent.children {
+            let name = layer.attr("name").unwrap_or("").to_owned();
+            let offset = group.offset
+                + v2![
+                    layer.attr_parse("offsetx").unwrap_or(0.0),
+                    layer.attr_parse("offsety").unwrap_or(0.0)
+                ];
+            let opacity = group.opacity * layer.attr_parse("opacity").unwrap_or(1.0);
+            let visible = group.visible && layer.attr("visible") != Some("0");
+            let properties = tmx_properties(layer);
+            match layer.name.as_str() {
+                "layer" => {
+                    let mut tiles = TileLayer {
+                        name,
+                        width: layer.attr_parse("width").unwrap_or(0),
+                        height: layer.attr_parse("height").unwrap_or(0),
+                        opacity,
+                        visible,
+                        offset,
+                        properties,
+                        ..TileLayer::default()
+                    };
+                    let data = layer.child("data")?;
+                    let encoding = data.attr("encoding").unwrap_or("");
+                    let compression = data.attr("compression").unwrap_or("");
+                    if data.child("chunk").is_some() {
+                        let chunks = data
+                            .children_named("chunk")
+                            .map(|chunk| {
+                                Some(Chunk {
+                                    x: chunk.attr_parse("x")?,
+                                    y: chunk.attr_parse("y")?,
+                                    width: chunk.attr_parse("width")?,
+                                    height: chunk.attr_parse("height")?,
+                                    data: tmx_data(chunk, encoding, compression)?,
+                                })
+                            })
+                            .collect::<Option<Vec<_>>>()?;
+                        merge_chunks(&mut tiles, chunks);
+                    } else {
+                        tiles.data = tmx_data(data, encoding, compression)?;
+                    }
+                    self.layers.push(MapLayer::Tiles(tiles));
+                }
+                "objectgroup" => self.layers.push(MapLayer::Objects(ObjectLayer {
+                    name,
+                    objects: layer.children_named("object").map(tmx_object).collect(),
+                    opacity,
+                    visible,
+                    offset,
+                    properties,
+                })),
+                "group" => self.add_tmx_layers(layer, Group { offset, opacity, visible })?,
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&MapLayer> {
+        self.layers.iter().find(|l| l.name() == name)
+    }
+
+    pub fn tile_layer(&self, name: &str) -> Option<&TileLayer> {
+        self.tile_layers().find(|l| l.name == name)
+    }
+
+    pub fn object_layer(&self, name: &str) -> Option<&ObjectLayer> {
+        self.object_layers().find(|l| l.name == name)
+    }
+
+    pub fn tile_layers(&self) -> impl Iterator<Item = &TileLayer> {
+        self.layers.iter().filter_map(|l| match l {
+            MapLayer::Tiles(layer) => Some(layer),
+            _ => None,
+        })
+    }
+
+    pub fn object_layers(&self) -> impl Iterator<Item = &ObjectLayer> {
+        self.layers.iter().filter_map(|l| match l {
+            MapLayer::Objects(layer) => Some(layer),
+            _ => None,
+        })
+    }
+
+    /// From all of the object layers
+    pub fn objects(&self) -> impl Iterator<Item = &MapObject> {
+        self.object_layers().flat_map(|l| &l.objects)
+    }
+
+    pub fn objects_of_kind<'a>(&'a self, kind: &'a str) -> impl Iterator<Item = &'a MapObject> + 'a {
+        self.objects().filter(move |o| o.kind == kind)
+    }
+
+    pub fn object(&self, id: u32) -> Option<&MapObject> {
+        self.objects().find(|o| o.id == id)
+    }
+
+    /// The index of the tileset and the local id in it, flip flags are ignored
+    pub fn tileset_for(&self, gid: u32) -> Option<(usize, u32)> {
+        let gid = gid & GID_MASK;
+        let index = self.tilesets.iter().rposition(|t| t.first_gid <= gid && gid != 0)?;
+        Some((index, gid - self.tilesets[index].first_gid))
+    }
+
+    pub fn tile_info(&self, gid: u32) -> Option<&TileInfo> {
+        let (index, local) = self.tileset_for(gid)?;
+        self.tilesets[index].tiles.get(&local)
+    }
+
+    pub fn tile_properties(&self, gid: u32) -> Option<&Properties> {
+        Some(&self.tile_info(gid)?.properties)
+    }
+}
+
+struct Chunk {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    data: Vec<u32>,
+}
+
+fn merge_chunks(layer: &mut TileLayer, chunks: Vec<Chunk>) {
+    if chunks.is_empty() {
+        return;
+    }
+    let x0 = chunks.iter().map(|c| c.x).min().unwrap_or(0);
+    let y0 = chunks.iter().map(|c| c.y).min().unwrap_or(0);
+    let x1 = chunks.iter().map(|c| c.x + c.width as i32).max().unwrap_or(0);
+    let y1 = chunks.iter().map(|c| c.y + c.height as i32).max().unwrap_or(0);
+    let width = (x1 - x0) as u32;
+    let mut data = vec![0; width as usize * (y1 - y0) as usize];
+    for chunk in chunks {
+        // anything past the declared size would land in the other chunks or out of bounds
+        let size = (chunk.width * chunk.height) as usize;
+        for (i, gid) in chunk.data.into_iter().take(size).enumerate() {
+            let x = chunk.x - x0 + (i as u32 % chunk.width.max(1)) as i32;
+            let y = chunk.y - y0 + (i as u32 / chunk.width.max(1)) as i32;
+            data[(y * width as i32 + x) as usize] = gid;
+        }
+    }
+    layer.x = x0;
+    layer.y = y0;
+    layer.width = width;
+    layer.height = (y1 - y0) as u32;
+    layer.data = data;
+}
+
+fn json_str<'a>(json: &'a Value, key: &str) -> &'a str {
+    json.get(key).and_then(Value::as_str).unwrap_or("")
+}
+
+fn json_f64(json: &Value, key: &str) -> f64 {
+    json.get(key).and_then(Value::as_f64).unwrap_or(0.0)
+}
+
+fn json_u32(json: &Value, key: &str) -> u32 {
+    json.get(key).and_then(Value::as_u64).unwrap_or(0) as u32
+}
+
+// "type" was renamed to "class" in Tiled 1.9
+fn json_kind(json: &Value) -> &str {
+    match json_str(json, "type") {
+        "" => json_str(json, "class"),
+        kind => kind,
+    }
+}
+
+fn tmx_kind(element: &xml::Element) -> &str {
+    element.attr("type").or_else(|| element.attr("class")).unwrap_or("")
+}
+
+fn json_properties(json: &Value) -> Properties {
+    json.get("properties")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|p| {
+            let value = p.get("value").unwrap_or(&Value::Null);
+            (json_str(p, "name").to_owned(), Property::from_json(json_str(p, "type"), value))
+        })
+        .collect()
+}
+
+fn tmx_properties(element: &xml::Element) -> Properties {
+    element
+        .child("properties")
+        .into_iter()
+        .flat_map(|p| p.children_named("property"))
+        .map(|p| {
+            // multiline strings are in the text instead
+            let value = p.attr("value").unwrap_or(&p.text);
+            (p.attr("name").unwrap_or("").to_owned(), Property::parse(p.attr("type").unwrap_or("string"), value))
+        })
+        .collect()
+}
+
+fn json_data(data: &Value, encoding: &str, compression: &str) -> Option<Vec<u32>> {
+    match data {
+        Value::Array(gids) => Some(gids.iter().map(|g| g.as_u64().unwrap_or(0) as u32).collect()),
+        Value::String(text) if encoding == "base64" => decode_base64_data(text, compression),
+        _ => None,
+    }
+}
+
+fn tmx_data(data: &xml::Element, encoding: &str, compression: &str) -> Option<Vec<u32>> {
+    match encoding {
+        "csv" => data.text.split(',').map(|gid| gid.trim().parse().ok()).collect(),
+        "base64" => decode_base64_data(&data.text, compression),
+        _ => Some(data.children_named("tile").map(|t| t.attr_parse("gid").unwrap_or(0)).collect()),
+    }
+}
+
+fn decode_base64_data(text: &str, compression: &str) -> Option<Vec<u32>> {
+    if !compression.is_empty() {
+        log::error!("Compressed ({}) tile layers are not supported, save them as CSV or uncompressed", compression);
+        return None;
+    }
+    let bytes = decode_base64(text)?;
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    )
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn parse_points(points: &str) -> Vec<V2> {
+    points
+        .split_whitespace()
+        .filter_map(|p| {
+            let (x, y) = p.split_at(p.find(',')?);
+            Some(v2![x.parse().ok()?, y[1..].parse().ok()?])
+        })
+        .collect()
+}
+
+fn json_points(points: &Value) -> Vec<V2> {
+    points
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|p| v2![json_f64(p, "x"), json_f64(p, "y")])
+        .collect()
+}
+
+fn json_object(object: &Value) -> MapObject {
+    let shape = if let Some(gid) = object.get("gid").and_then(Value::as_u64) {
+        ObjectShape::Tile(gid as u32)
+    } else if let Some(points) = object.get("polygon") {
+        ObjectShape::Polygon(json_points(points))
+    } else if let Some(points) = object.get("polyline") {
+        ObjectShape::Polyline(json_points(points))
+    } else if object.get("ellipse").and_then(Value::as_bool) == Some(true) {
+        ObjectShape::Ellipse
+    } else if object.get("point").and_then(Value::as_bool) == Some(true) {
+        ObjectShape::Point
+    } else {
+        ObjectShape::Rectangle
+    };
+    MapObject {
+        id: json_u32(object, "id"),
+        name: json_str(object, "name").to_owned(),
+        kind: json_kind(object).to_owned(),
+        pos: v2![json_f64(object, "x"), json_f64(object, "y")],
+        size: v2![json_f64(object, "width"), json_f64(object, "height")],
+        rotation: json_f64(object, "rotation").to_radians(),
+        visible: object.get("visible").and_then(Value::as_bool).unwrap_or(true),
+        shape,
+        properties: json_properties(object),
+    }
+}
+
+fn tmx_object(object: &xml::Element) -> MapObject {
+    let shape = if let Some(gid) = object.attr_parse("gid") {
+        ObjectShape::Tile(gid)
+    } else if let Some(polygon) = object.child("polygon") {
+        ObjectShape::Polygon(parse_points(polygon.attr("points").unwrap_or("")))
+    } else if let Some(polyline) = object.child("polyline") {
+        ObjectShape::Polyline(parse_points(polyline.attr("points").unwrap_or("")))
+    } else if object.child("ellipse").is_some() {
+        ObjectShape::Ellipse
+    } else if object.child("point").is_some() {
+        ObjectShape::Point
+    } else {
+        ObjectShape::Rectangle
+    };
+    let attr = |name| object.attr_parse(name).unwrap_or(0.0);
+    MapObject {
+        id: object.attr_parse("id").unwrap_or(0),
+        name: object.attr("name").unwrap_or("").to_owned(),
+        kind: tmx_kind(object).to_owned(),
+        pos: v2![attr("x"), attr("y")],
+        size: v2![attr("width"), attr("height")],
+        rotation: attr("rotation").to_radians(),
+        visible: object.attr("visible") != Some("0"),
+        shape,
+        properties: tmx_properties(object),
+    }
+}
+
+struct CachedChunk {
+    image: Image,
+    // drawn every frame instead, with the raw gids
+    animated: Vec<(i32, i32, u32)>,
+}
+
+struct Loaded {
+    map: Rc<MapData>,
+    // None for the image collection tilesets
+    tilesets: Vec<Option<Tileset>>,
+    chunks: RefCell<HashMap<(usize, i32, i32), Rc<CachedChunk>>>,
+}
+
+/// A Tiled map ready to be drawn.
+///
+/// Tile layers are prerendered in chunks of 16x16 tiles into offscreen targets
+/// as they come into view, so only the visible ones are drawn every frame.
+/// Animated tiles are drawn on top of the chunks, advanced by [Tilemap::update].
+///
+/// Tile objects are not drawn, the object layers are there for spawning
+/// things, see [MapData::objects_of_kind] and [MapObject::properties_as].
+#[derive(Clone)]
+pub struct Tilemap {
+    surface: Mut<Surface>,
+    inner: Mut<Option<Rc<Loaded>>>,
+    time: Rc<Cell<f64>>,
+}
+
+impl Debug for Tilemap {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &*self.inner.borrow() {
+            Some(loaded) => f
+                .debug_struct("Tilemap")
+                .field("size", &(loaded.map.width, loaded.map.height))
+                .field("layers", &loaded.map.layers.iter().map(MapLayer::name).collect::<Vec<_>>())
+                .finish(),
+            None => write!(f, "Tilemap(not loaded)"),
+        }
+    }
+}
+
+impl Tilemap {
+    /// With a spritesheet for every tileset of the map, in the same order,
+    /// external tilesets have to be already merged in with [MapTileset::merge_external]
+    pub fn new(surface: Mut<Surface>, map: MapData, spritesheets: Vec<Spritesheet>) -> Self {
+        let tilemap = Self {
+            surface,
+            inner: Mut::new(None),
+            time: Rc::new(Cell::new(0.0)),
+        };
+        tilemap.set(map, spritesheets.into_iter().map(Some).collect());
+        tilemap
+    }
+
+    /// Loads the map, its external tilesets and then the tileset images
+    pub(crate) fn load(surface: Mut<Surface>, url: &str) -> Self {
+        let tilemap = Self {
+            surface: surface.clone(),
+            inner: Mut::new(None),
+            time: Rc::new(Cell::new(0.0)),
+        };
+        let moved_tilemap = tilemap.clone();
+        let url = url.to_owned();
+        spawn_local(async move {
+            let map = match fetch_text(&url).await {
+                Some(text) => MapData::parse(&text),
+                None => return log::error!("Failed to load the map at {}", url),
+            };
+            let mut map = match map {
+                Some(map) => map,
+                None => return log::error!("Failed to parse the map at {}", url),
+            };
+            let base = base_url(&url);
+            for tileset in &mut map.tilesets {
+                let source = match &tileset.source {
+                    Some(source) => source,
+                    None => continue,
+                };
+                let tileset_url = format!("{}{}", base, source);
+                let external = match fetch_text(&tileset_url).await {
+                    Some(text) => MapTileset::parse(&text),
+                    None => return log::error!("Failed to load the tileset at {}", tileset_url),
+                };
+                let external = match external {
+                    Some(external) => external,
+                    None => return log::error!("Failed to parse the tileset at {}", tileset_url),
+                };
+                tileset.merge_external(external);
+            }
+            let spritesheets = map
+                .tilesets
+                .iter()
+                .map(|tileset| {
+                    if tileset.image.is_empty() {
+                        log::warn!("Tileset {} is an image collection, those are not supported", tileset.name);
+                        return None;
+                    }
+                    Some(Spritesheet::load(surface.clone(), &format!("{}{}", base, tileset.image)))
+                })
+                .collect();
+            moved_tilemap.set(map, spritesheets);
+        });
+        tilemap
+    }
+
+    fn set(&self, map: MapData, spritesheets: Vec<Option<Spritesheet>>) {
+        let tilesets = map
+            .tilesets
+            .iter()
+            .zip(spritesheets)
+            .map(|(tileset, spritesheet)| {
+                let grid = spritesheet?.grid(tileset.tile_width, tileset.tile_height, tileset.margin, tileset.spacing);
+                Some(grid.with_columns(tileset.columns))
+            })
+            .collect();
+        *self.inner.borrow_mut() = Some(Rc::new(Loaded {
+            map: Rc::new(map),
+            tilesets,
+            chunks: RefCell::new(HashMap::new()),
+        }));
+    }
+
+    fn loaded(&self) -> Option<Rc<Loaded>> {
+        self.inner.borrow().clone()
+    }
+
+    /// The map and all of the tileset images are loaded
+    pub fn is_loaded(&self) -> bool {
+        self.loaded()
+            .is_some_and(|l| l.tilesets.iter().flatten().all(|t| t.spritesheet().is_loaded()))
+    }
+
+    pub fn map(&self) -> Option<Rc<MapData>> {
+        Some(self.loaded()?.map.clone())
+    }
+
+    /// Size of the map in pixels
+    pub fn size(&self) -> Option<V2> {
+        let map = self.map()?;
+        Some(v2![(map.width * map.tile_width) as f64, (map.height * map.tile_height) as f64])
+    }
+
+    pub fn on_update<G: Game>(&self, context: &Context<G>) {
+        self.update(context.delta_time());
+    }
+
+    /// Advances the animated tiles by the time in seconds
+    pub fn update(&self, delta_time: f64) {
+        self.time.set(self.time.get() + delta_time);
+    }
+
+    /// Drops the prerendered chunks, they are rendered again when needed
+    pub fn invalidate(&self) {
+        if let Some(loaded) = self.loaded() {
+            loaded.chunks.borrow_mut().clear();
+        }
+    }
+
+    /// All of the visible tile layers in order, with the map at the origin of the current transform
+    pub fn draw(&self) {
+        let renderer = self.surface.borrow().renderer();
+        self.draw_into(&*renderer, None);
+    }
+
+    pub fn draw_layer(&self, name: &str) {
+        let renderer = self.surface.borrow().renderer();
+        self.draw_into(&*renderer, Some(name));
+    }
+
+    /// Queues [Tilemap::draw] into the layer
+    pub fn submit(&self, layer: Layer, z: i32) {
+        let tilemap = self.clone();
+        self.surface.borrow().submit(layer, z, move |renderer| tilemap.draw_into(renderer, None));
+    }
+
+    /// Draws through the given renderer, all of the visible tile layers or the one named
+    pub fn draw_into(&self, renderer: &dyn Renderer, name: Option<&str>) {
+        let loaded = match self.loaded() {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        let view = match renderer.visible_rect() {
+            Some(view) => view,
+            None => return,
+        };
+        for (index, layer) in loaded.map.layers.iter().enumerate() {
+            let layer = match layer {
+                MapLayer::Tiles(layer) if name.map_or(layer.visible, |n| n == layer.name) => layer,
+                _ => continue,
+            };
+            renderer.save();
+            renderer.set_global_alpha(layer.opacity);
+            self.draw_tile_layer(&loaded, renderer, index, layer, view);
+            renderer.restore();
+        }
+    }
+
+    fn draw_tile_layer(&self, loaded: &Loaded, renderer: &dyn Renderer, index: usize, layer: &TileLayer, view: Rect) {
+        let map = &loaded.map;
+        let (tw, th) = (map.tile_width as f64, map.tile_height as f64);
+        let (pad_right, pad_top) = overhang(map);
+        let (chunk_w, chunk_h) = (CHUNK_SIZE as f64 * tw, CHUNK_SIZE as f64 * th);
+        let origin = layer.offset + v2![layer.x as f64 * tw, layer.y as f64 * th];
+
+        // bigger tiles stick out to the right and up from their cells
+        let max_cx = (layer.width as i32 + CHUNK_SIZE - 1) / CHUNK_SIZE - 1;
+        let max_cy = (layer.height as i32 + CHUNK_SIZE - 1) / CHUNK_SIZE - 1;
+        let cx0 = (((view.x - origin.x - pad_right) / chunk_w).floor() as i32).max(0);
+        let cy0 = (((view.y - origin.y) / chunk_h).floor() as i32).max(0);
+        let cx1 = (((view.x + view.w - origin.x) / chunk_w).floor() as i32).min(max_cx);
+        let cy1 = (((view.y + view.h - origin.y + pad_top) / chunk_h).floor() as i32).min(max_cy);
+
+        for cy in cy0..=cy1 {
+            for cx in cx0..=cx1 {
+                let chunk = match self.chunk(loaded, index, layer, cx, cy) {
+                    Some(chunk) => chunk,
+                    None => continue,
+                };
+                let (x, y) = (origin.x + cx as f64 * chunk_w, origin.y + cy as f64 * chunk_h);
+                let (w, h) = (chunk.image.width() as f64, chunk.image.height() as f64);
+                renderer.draw_image(&chunk.image, Rect::new(0.0, 0.0, w, h), Rect::new(x, y - pad_top, w, h));
+                for &(tx, ty, gid) in &chunk.animated {
+                    let x = origin.x + (tx - layer.x) as f64 * tw;
+                    let y = origin.y + (ty - layer.y + 1) as f64 * th;
+                    draw_tile(loaded, renderer, gid, x, y, self.time.get());
+                }
+            }
+        }
+    }
+
+    fn chunk(&self, loaded: &Loaded, index: usize, layer: &TileLayer, cx: i32, cy: i32) -> Option<Rc<CachedChunk>> {
+        if let Some(chunk) = loaded.chunks.borrow().get(&(index, cx, cy)) {
+            return Some(chunk.clone());
+        }
+        // not cached until everything is there, or it would stay half empty
+        if !loaded.tilesets.iter().flatten().all(|t| t.spritesheet().is_loaded()) {
+            return None;
+        }
+        let map = &loaded.map;
+        let (pad_right, pad_top) = overhang(map);
+        let (tw, th) = (map.tile_width as f64, map.tile_height as f64);
+        let target = self.surface.borrow().create_target(
+            (CHUNK_SIZE as f64 * tw + pad_right) as u32,
+            (CHUNK_SIZE as f64 * th + pad_top) as u32,
+        );
+        let mut animated = Vec::new();
+        for j in 0..CHUNK_SIZE {
+            for i in 0..CHUNK_SIZE {
+                let (x, y) = (layer.x + cx * CHUNK_SIZE + i, layer.y + cy * CHUNK_SIZE + j);
+                let gid = layer.raw_tile(x, y);
+                if gid & GID_MASK == 0 {
+                    continue;
+                }
+                if map.tile_info(gid).is_some_and(|info| !info.animation.is_empty()) {
+                    animated.push((x, y, gid));
+                    continue;
+                }
+                draw_tile(loaded, &*target, gid, i as f64 * tw, pad_top + (j + 1) as f64 * th, 0.0);
+            }
+        }
+        let chunk = Rc::new(CachedChunk {
+            image: target.image(),
+            animated,
+        });
+        loaded.chunks.borrow_mut().insert((index, cx, cy), chunk.clone());
+        Some(chunk)
+    }
+}
+
+// how much bigger than the map cells the biggest tiles are
+fn overhang(map: &MapData) -> (f64, f64) {
+    map.tilesets.iter().fold((0.0, 0.0), |(w, h), t| {
+        let dw = t.tile_width.saturating_sub(map.tile_width) as f64;
+        let dh = t.tile_height.saturating_sub(map.tile_height) as f64;
+        (f64::max(w, dw), f64::max(h, dh))
+    })
+}
+
+// tiles are aligned to the bottom left corner of their cell, like in Tiled
+fn draw_tile(loaded: &Loaded, renderer: &dyn Renderer, gid: u32, x: f64, bottom: f64, time: f64) {
+    let (index, mut local) = match loaded.map.tileset_for(gid) {
+        Some(found) => found,
+        None => return,
+    };
+    let info = &loaded.map.tilesets[index];
+    if let Some(frame) = info.tiles.get(&local).and_then(|t| t.frame_at(time)) {
+        local = frame;
+    }
+    let (image, src) = match loaded.tilesets[index].as_ref().and_then(|t| t.get(local as usize)?.region()) {
+        Some(region) => region,
+        None => return,
+    };
+    let (w, h) = (info.tile_width as f64, info.tile_height as f64);
+    let dst = Rect::new(-w / 2.0, -h / 2.0, w, h);
+    if gid & (FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY) == 0 {
+        renderer.draw_image(&image, src, Rect::new(x, bottom - h, w, h));
+        return;
+    }
+    // the diagonal flip goes first, it swaps x and y
+    let (mut a, mut b, mut c, mut d) = if gid & FLIPPED_DIAGONALLY != 0 {
+        (0.0, 1.0, 1.0, 0.0)
+    } else {
+        (1.0, 0.0, 0.0, 1.0)
+    };
+    if gid & FLIPPED_HORIZONTALLY != 0 {
+        a = -a;
+        c = -c;
+    }
+    if gid & FLIPPED_VERTICALLY != 0 {
+        b = -b;
+        d = -d;
+    }
+    renderer.save();
+    renderer.transform(Transform::new(a, b, c, d, x + w / 2.0, bottom - h / 2.0));
+    renderer.draw_image(&image, src, dst);
+    renderer.restore();
+}
@@ -1,32 +1,29 @@
 use std::{
     rc::Rc,
     borrow::Cow,
-    fmt::{Debug, Formatter},
 };
 
 use crate::{
+    color::Color,
     event::{Event, MouseButton},
+    font::{Font, FontSpec},
+    nine_slice::NineSlice,
+    render::{Rect, TextAlign, TextBaseline},
     sound::Sound,
-    surface::SurfaceContextExt,
+    text::{TextLayout, TextStyle},
     Context, Game,
     V2, v2,
 };
 
+#[derive(Debug)]
 pub struct Text {
     pub pos: V2,
     pub text: Cow<'static, str>,
+    /// Parse the text as [markup](crate::text::parse_markup)
+    pub markup: bool,
     size: f64,
-    font: String,
-}
-
-impl Debug for Text {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        f.debug_struct("Text")
-            .field("pos", &self.pos)
-            .field("text", &self.text)
-            .field("size", &self.size)
-            .finish()
-    }
+    max_width: Option<f64>,
+    style: TextStyle,
 }
 
 impl Text {
@@ -38,11 +35,16 @@ impl Text {
         Self {
             pos: v2![0.0, 0.0],
             text,
+            markup: false,
             size: 2.5,
-            font: "2.5rem monospace".into(),
+            max_width: None,
+            style: TextStyle::new(FontSpec::new("monospace", 0.0))
+                .with_align(TextAlign::Center)
+                .with_baseline(TextBaseline::Middle),
         }
     }
 
+    /// In rems
     pub fn with_size(mut self, size: f64) -> Self {
         self.set_size(size);
         self
@@ -50,32 +52,92 @@ impl Text {
 
     pub fn set_size(&mut self, size: f64) {
         self.size = size;
-        self.font = format!("{}rem monospace", size);
+    }
+
+    /// A css or a bitmap font, its size is replaced by the text one
+    pub fn with_font(mut self, font: impl Into<Font>) -> Self {
+        self.set_font(font);
+        self
+    }
+
+    pub fn set_font(&mut self, font: impl Into<Font>) {
+        self.style.font = font.into();
+    }
+
+    /// The following three do nothing for bitmap fonts
+    pub fn with_family(mut self, family: impl Into<String>) -> Self {
+        if let Font::Css(spec) = &mut self.style.font {
+            spec.family = family.into();
+        }
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u16) -> Self {
+        if let Font::Css(spec) = &mut self.style.font {
+            spec.weight = weight;
+        }
+        self
+    }
+
+    pub fn with_italic(mut self, italic: bool) -> Self {
+        if let Font::Css(spec) = &mut self.style.font {
+            spec.italic = italic;
+        }
+        self
+    }
+
+    pub fn with_align(mut self, align: TextAlign) -> Self {
+        self.style.align = align;
+        self
+    }
+
+    pub fn with_baseline(mut self, baseline: TextBaseline) -> Self {
+        self.style.baseline = baseline;
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f64) -> Self {
+        self.style.line_spacing = line_spacing;
+        self
+    }
+
+    /// In rems, the text is wrapped to fit
+    pub fn with_max_width(mut self, max_width: f64) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_markup(mut self, markup: bool) -> Self {
+        self.markup = markup;
+        self
+    }
+
+    pub fn layout<G: Game>(&self, context: &Context<G>, color: Color) -> TextLayout {
+        let mut style = self.style.clone().with_color(color);
+        style.font.set_size(context.rem_to_px(self.size));
+        style.max_width = self.max_width.map(|w| context.rem_to_px(w));
+
+        let surface = context.surface().renderer();
+        if self.markup {
+            TextLayout::markup(&*surface, &self.text, &style)
+        } else {
+            TextLayout::plain(&*surface, &self.text, &style)
+        }
     }
 
     pub fn compute_size<G: Game>(&self, context: &mut Context<G>) -> (f64, f64) {
-        let surface = context.surface().context();
-        surface.set_font(&self.font);
-        let dim = surface.measure_text(&self.text).unwrap();
-        (dim.width(), context.rem_to_px(self.size))
+        let size = self.layout(context, Color::BLACK).size();
+        (size.x, size.y)
     }
 
     pub fn is_over<G: Game>(&self, pos: V2, context: &mut Context<G>) -> bool {
-        let (w, h) = self.compute_size(context);
-        pos.x >= self.pos.x - w / 2.0
-            && pos.x <= self.pos.x + w / 2.0
-            && pos.y >= self.pos.y - h / 2.0
-            && pos.y <= self.pos.y + h / 2.0
+        self.layout(context, Color::BLACK).bounds(self.pos).contains(pos)
     }
 
-    pub fn on_update<G: Game>(&mut self, context: &mut Context<G>, pos: V2, color: &str) {
-        let surface = context.surface().context();
-
+    pub fn on_update<G: Game>(&mut self, context: &mut Context<G>, pos: V2, color: Color) {
         self.pos = pos;
-
-        surface.fill_color(color);
-        surface.set_font(&self.font);
-        surface.fill_text(&self.text, pos.x, pos.y).unwrap();
+        let layout = self.layout(context, color);
+        layout.draw(&*context.surface().renderer(), pos);
     }
 }
 
@@ -83,21 +145,22 @@ impl Text {
 pub struct Button {
     pub text: Text,
     pub enabled: bool,
-    color: &'static str,
-    hover_color: &'static str,
-    disabled_color: &'static str,
+    color: Color,
+    hover_color: Color,
+    disabled_color: Color,
     click_sound: Option<Rc<Sound>>,
     hover_sound: Option<Rc<Sound>>,
+    background: Option<(NineSlice, f64)>,
     hovered: bool,
     last_touch: Option<V2>,
 }
 
 impl Button {
-    pub fn empty(color: &'static str) -> Self {
+    pub fn empty(color: Color) -> Self {
         Self::new("".into(), color)
     }
 
-    pub fn new(text: Cow<'static, str>, color: &'static str) -> Self {
+    pub fn new(text: Cow<'static, str>, color: Color) -> Self {
         Self {
             text: Text::new(text),
             color,
@@ -105,6 +168,7 @@ impl Button {
             disabled_color: color,
             click_sound: None,
             hover_sound: None,
+            background: None,
             hovered: false,
             enabled: true,
             last_touch: None,
@@ -116,6 +180,11 @@ impl Button {
         self
     }
 
+    pub fn with_font(mut self, font: impl Into<Font>) -> Self {
+        self.text.set_font(font);
+        self
+    }
+
     pub fn with_click_sound(mut self, click_sound: Rc<Sound>) -> Self {
         self.click_sound = Some(click_sound);
         self
@@ -126,22 +195,49 @@ impl Button {
         self
     }
 
-    pub fn with_hover_color(mut self, hover_color: &'static str) -> Self {
+    pub fn with_hover_color(mut self, hover_color: Color) -> Self {
         self.hover_color = hover_color;
         self
     }
 
-    pub fn with_disabled_color(mut self, disabled_color: &'static str) -> Self {
+    pub fn with_disabled_color(mut self, disabled_color: Color) -> Self {
         self.disabled_color = disabled_color;
         self
     }
 
+    /// Drawn behind the text with the padding (in rems) around it,
+    /// the whole panel is clickable
+    pub fn with_background(mut self, background: NineSlice, padding: f64) -> Self {
+        self.background = Some((background, padding));
+        self
+    }
+
     pub fn set_text(&mut self, text: impl Into<Cow<'static, str>>) {
         self.text.text = text.into();
     }
 
+    fn bounds<G: Game>(&self, context: &Context<G>) -> Rect {
+        let bounds = self.text.layout(context, self.color).bounds(self.text.pos);
+        match &self.background {
+            Some((_, padding)) => {
+                let padding = context.rem_to_px(*padding);
+                Rect::new(
+                    bounds.x - padding,
+                    bounds.y - padding,
+                    bounds.w + padding * 2.0,
+                    bounds.h + padding * 2.0,
+                )
+            }
+            None => bounds,
+        }
+    }
+
+    pub fn is_over<G: Game>(&self, pos: V2, context: &Context<G>) -> bool {
+        self.bounds(context).contains(pos)
+    }
+
     fn handle_press<G: Game>(&mut self, pos: V2, context: &mut Context<G>) -> bool {
-        if self.text.is_over(pos, context) {
+        if self.is_over(pos, context) {
             if let Some(click_sound) = self.click_sound.as_ref() {
                 click_sound.play();
             }
@@ -157,7 +253,7 @@ impl Button {
         }
         match event {
             Event::MouseMove { pos, .. } => {
-                let over = self.text.is_over(*pos, context);
+                let over = self.is_over(*pos, context);
                 if !self.hovered && over {
                     if let Some(hover_sound) = self.hover_sound.as_ref() {
                         hover_sound.play();
@@ -169,18 +265,19 @@ impl Button {
             Event::MouseUp {
                 pos,
                 button: MouseButton::Left,
+                ..
             } => self.handle_press(*pos, context),
             Event::TouchStart { touches } => {
-                self.last_touch = touches.get(0).copied();
+                self.last_touch = touches.get(0).map(|t| t.pos);
                 false
             }
             Event::TouchMove { touches } => {
-                self.last_touch = touches.get(0).copied();
+                self.last_touch = touches.get(0).map(|t| t.pos);
                 false
             }
             Event::TouchEnd { touches } if touches.len() <= 1 => {
                 self.hovered = false;
-                if let Some(pos) = touches.get(0).copied().or(self.last_touch) {
+                if let Some(pos) = touches.get(0).map(|t| t.pos).or(self.last_touch) {
                     self.handle_press(pos, context)
                 } else {
                     false
@@ -191,6 +288,10 @@ impl Button {
     }
 
     pub fn on_update<G: Game>(&mut self, context: &mut Context<G>, pos: V2) {
+        if let Some((background, _)) = &self.background {
+            self.text.pos = pos;
+            background.draw(self.bounds(context));
+        }
         self.text.on_update(
             context,
             pos,
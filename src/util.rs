@@ -6,8 +6,11 @@ use std::{
     rc::Rc,
 };
 
-use wasm_bindgen::prelude::*;
-use web_sys::console;
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{console, Response};
+
+use crate::window;
 
 #[wasm_bindgen]
 extern "C" {
@@ -24,6 +27,23 @@ pub fn setup_panic_hook() {
     }));
 }
 
+/// The body of the response, `None` if the request failed or the status was not 2xx
+pub(crate) async fn fetch_text(url: &str) -> Option<String> {
+    let response = async { JsFuture::from(window().fetch_with_str(url)).await?.dyn_into::<Response>() };
+    let response = response.await.ok()?;
+    if !response.ok() {
+        log::warn!("{} responded with {} {}", url, response.status(), response.status_text());
+        return None;
+    }
+    let text: Result<JsValue, JsValue> = async { JsFuture::from(response.text()?).await }.await;
+    text.ok()?.as_string()
+}
+
+/// The url up to and including the last `/`, for the files relative to it
+pub(crate) fn base_url(url: &str) -> &str {
+    url.rfind('/').map_or("", |i| &url[..=i])
+}
+
 pub struct Mut<T> {
     inner: Rc<RefCell<T>>,
 }
@@ -0,0 +1,227 @@
+//! Just enough XML for the asset formats, elements with attributes and text.
+//! Comments, processing instructions, doctypes and CDATA are skipped.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    /// All of the text directly inside, pieces between the children are concatenated
+    pub text: String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn attr_parse<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.attr(name)?.trim().parse().ok()
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// This element and everything below it, depth first
+    pub fn descendants(&self) -> Vec<&Element> {
+        let mut result = vec![self];
+        for child in &self.children {
+            result.extend(child.descendants());
+        }
+        result
+    }
+}
+
+/// The root element, `None` if the document is malformed
+pub fn parse(text: &str) -> Option<Element> {
+    let mut stack: Vec<Element> = vec![Element::default()];
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        if start > 0 {
+            stack.last_mut()?.text.push_str(&unescape(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->")? + 3..];
+            continue;
+        }
+        if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[rest.find('>')? + 1..];
+            continue;
+        }
+        let end = find_tag_end(rest)?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let element = stack.pop()?;
+            if element.name != name.trim() || stack.is_empty() {
+                return None;
+            }
+            stack.last_mut()?.children.push(element);
+            continue;
+        }
+        let (tag, closed) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let element = Element {
+            name: tag[..name_end].to_owned(),
+            attributes: parse_attributes(&tag[name_end..])?,
+            ..Element::default()
+        };
+        if closed {
+            stack.last_mut()?.children.push(element);
+        } else {
+            stack.push(element);
+        }
+    }
+    if stack.len() != 1 {
+        return None;
+    }
+    stack.pop()?.children.into_iter().next()
+}
+
+// the closing '>' that is not in a quoted attribute value
+fn find_tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('>', None) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `key="value" other='value'`, also used for the BMFont text format
+/// where the values can be unquoted
+pub(crate) fn parse_attributes(text: &str) -> Option<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let name = rest[..eq].trim().to_owned();
+        rest = rest[eq + 1..].trim_start();
+        let value = match rest.chars().next()? {
+            q @ '"' | q @ '\'' => {
+                let end = rest[1..].find(q)? + 1;
+                let value = &rest[1..end];
+                rest = &rest[end + 1..];
+                value
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                value
+            }
+        };
+        attributes.push((name, unescape(value)));
+        rest = rest.trim_start();
+    }
+    Some(attributes)
+}
+
+pub(crate) fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_owned();
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let decoded = entity.and_then(|(entity, _)| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(std::char::from_u32),
+                None => entity.strip_prefix('#')?.parse().ok().and_then(std::char::from_u32),
+            },
+        });
+        match (decoded, entity) {
+            (Some(c), Some((_, end))) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        let root = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE map>
+            <!-- a comment with <tags/> in it -->
+            <map width="2" name='a "quoted" &gt; name'>
+                <layer name="ground"/>
+                <!-- <layer name="hidden"/> -->
+                <layer name="top">text &amp; <data/> more</layer>
+            </map>"#,
+        )
+        .expect("Failed to parse");
+        assert_eq!(root.name, "map");
+        assert_eq!(root.attr_parse::<u32>("width"), Some(2));
+        assert_eq!(root.attr("name"), Some("a \"quoted\" > name"));
+        assert_eq!(root.attr("height"), None);
+        let names: Vec<_> = root.children_named("layer").filter_map(|l| l.attr("name")).collect();
+        assert_eq!(names, ["ground", "top"]);
+        let top = &root.children[1];
+        assert_eq!(top.text, "text &  more");
+        assert!(top.child("data").is_some());
+        assert_eq!(root.descendants().len(), 4);
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(unescape("plain"), "plain");
+        assert_eq!(unescape("&lt;a&gt; &amp;&amp; &quot;b&quot; &apos;c&apos;"), "<a> && \"b\" 'c'");
+        assert_eq!(unescape("&#65;&#x42;&#X43;"), "ABC");
+        // unknown and broken ones are kept as they are
+        assert_eq!(unescape("&nbsp; & &#xD800; &#zz; &amp"), "&nbsp; & &#xD800; &#zz; &amp");
+    }
+
+    #[test]
+    fn malformed() {
+        let malformed = [
+            "",
+            "just text",
+            "<a>",
+            "<a></b>",
+            "<a><b></a></b>",
+            "</a>",
+            "<a x=\"1></a>",
+            "<a x></a>",
+            "<!-- unterminated <a/>",
+            "<a/",
+        ];
+        for text in &malformed {
+            assert_eq!(parse(text), None, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,13 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" name="tiles" tilewidth="8" tileheight="8" tilecount="4" columns="2">
+ <image source="../images/tiles.png" width="16" height="16"/>
+ <tile id="0" type="water">
+  <properties>
+   <property name="solid" type="bool" value="true"/>
+  </properties>
+  <animation>
+   <frame tileid="0" duration="100"/>
+   <frame tileid="1" duration="300"/>
+  </animation>
+ </tile>
+</tileset>
@@ -0,0 +1,43 @@
+use ld_game_engine::font::{BitmapFontData, Glyph};
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
+#[test]
+fn formats_agree() {
+    let json = BitmapFontData::parse(&fixture("fonts/tiny.json")).expect("Failed to parse the json font");
+    assert_eq!((json.size, json.line_height, json.base), (8.0, 10.0, 8.0));
+    assert_eq!(json.pages, ["tiny_0.png", "tiny_1.png"]);
+    assert_eq!(json.glyphs.len(), 3);
+    assert_eq!(
+        json.glyphs[&'V'],
+        Glyph { x: 6, y: 0, width: 5, height: 7, xoffset: 0.0, yoffset: 1.0, xadvance: 6.0, page: 0 }
+    );
+    assert_eq!(json.glyphs[&' '].page, 1);
+    assert_eq!(json.kerning, [('A', 'V', -1.0), ('V', 'A', -2.0)]);
+
+    // the pages are sorted by id, and the negative size of the info tag is in pixels
+    for name in &["fonts/tiny.fnt", "fonts/tiny.xml"] {
+        let data = BitmapFontData::parse(&fixture(name)).unwrap_or_else(|| panic!("Failed to parse {}", name));
+        assert_eq!(data, json, "{}", name);
+    }
+}
+
+#[test]
+fn malformed_fonts() {
+    let malformed = [
+        "",
+        "{ \"size\": 8 ",
+        "{ \"size\": 8, \"line_height\": 10, \"base\": 8, \"glyphs\": { \"AB\": {} } }",
+        "common lineHeight=10 base=8",
+        "char id=\"65 x=0 y=0 width=5 height=7 xadvance=6",
+        "char id=55296 x=0 y=0 width=5 height=7 xadvance=6",
+        "<font><chars><char id=\"65\" xadvance=\"6\"/></font>",
+        "<font><common lineHeight=\"10\"/></font>",
+    ];
+    for text in &malformed {
+        assert_eq!(BitmapFontData::parse(text), None, "{:?}", text);
+    }
+}
@@ -0,0 +1,55 @@
+use std::rc::Rc;
+
+use ld_game_engine::{
+    render::{FillRule, Golden, Image, Pixmap, Rect, SoftwareRenderer},
+    sprite::Spritesheet,
+    surface::Surface,
+    util::Mut,
+};
+
+fn golden(name: &str) -> Golden {
+    Golden::new(format!("{}/tests/golden/{}", env!("CARGO_MANIFEST_DIR"), name))
+}
+
+#[test]
+fn headless_frame_replays() {
+    let software = Rc::new(SoftwareRenderer::new(32, 24));
+    let surface = Mut::new(Surface::with_renderer(software.clone()));
+
+    let mut pixmap = Pixmap::new(4, 4);
+    for y in 0..4 {
+        for x in 0..4 {
+            pixmap.set_pixel(x, y, [x as u8 * 60, y as u8 * 60, 200, 255]);
+        }
+    }
+    let image: Image = pixmap.clone().into();
+    let sheet = Spritesheet::from_pixmap(surface.clone(), pixmap);
+
+    surface.borrow().start_recording();
+    {
+        let renderer = surface.borrow().renderer();
+        renderer.set_fill_style("#203040");
+        renderer.fill_rect(0.0, 0.0, 32.0, 24.0);
+        renderer.save();
+        renderer.translate(16.0, 12.0);
+        renderer.begin_path();
+        renderer.move_to(-8.0, -8.0);
+        renderer.line_to(8.0, -4.0);
+        renderer.line_to(0.0, 8.0);
+        renderer.close_path();
+        renderer.set_fill_style("#ff8000");
+        renderer.fill(FillRule::NonZero);
+        renderer.restore();
+        renderer.set_global_alpha(0.5);
+        renderer.draw_image(&image, Rect::new(0.0, 0.0, 4.0, 4.0), Rect::new(2.0, 2.0, 8.0, 8.0));
+    }
+    sheet.create_sprite(1, 1, 2, 2).draw(24.0, 16.0);
+    let recording = surface.borrow().stop_recording().expect("Nothing was recorded");
+
+    let replayed = SoftwareRenderer::new(32, 24);
+    recording.replay(&replayed);
+    assert_eq!(replayed.snapshot(), software.snapshot(), "Replay differs from the original frame");
+
+    golden("headless_frame").assert(&recording, &replayed.snapshot());
+}
+
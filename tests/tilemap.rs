@@ -0,0 +1,97 @@
+use ld_game_engine::tilemap::{
+    MapData, MapLayer, MapTileset, ObjectShape, Property, FLIPPED_DIAGONALLY, FLIPPED_HORIZONTALLY, FLIPPED_VERTICALLY,
+};
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
+#[test]
+fn flip_flags() {
+    let map = MapData::parse(&fixture("flips.tmj")).expect("Failed to parse the map");
+    for name in &["csv", "base64"] {
+        let layer = map.tile_layer(name).unwrap();
+        assert_eq!(layer.raw_tile(1, 0), 2 | FLIPPED_HORIZONTALLY, "{}", name);
+        assert_eq!(layer.raw_tile(2, 0), 3 | FLIPPED_VERTICALLY, "{}", name);
+        assert_eq!(layer.raw_tile(0, 1), 4 | FLIPPED_DIAGONALLY, "{}", name);
+        assert_eq!(layer.raw_tile(1, 1), 1 | FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY, "{}", name);
+
+        let tiles: Vec<_> = (0..2).flat_map(|y| (0..3).map(move |x| layer.tile(x, y))).collect();
+        assert_eq!(tiles, [Some(1), Some(2), Some(3), Some(4), Some(1), None], "{}", name);
+    }
+    assert_eq!(map.tileset_for(3 | FLIPPED_VERTICALLY), Some((0, 2)));
+}
+
+#[test]
+fn infinite_chunks() {
+    let map = MapData::parse(&fixture("infinite.tmx")).expect("Failed to parse the map");
+    assert!(map.infinite);
+    let layer = map.tile_layer("ground").unwrap();
+    assert_eq!((layer.x, layer.y, layer.width, layer.height), (-2, -2, 4, 4));
+    assert_eq!(layer.data.len(), 16);
+
+    let tiles: Vec<_> = (-2..2).flat_map(|y| (-2..2).map(move |x| layer.tile(x, y))).collect();
+    #[rustfmt::skip]
+    assert_eq!(tiles, [
+        Some(1), Some(2), None, None,
+        Some(3), Some(4), None, None,
+        None, None, Some(4), Some(3),
+        None, None, Some(2), Some(1),
+    ]);
+    // the extra row of the second chunk is past its declared size and is dropped
+    assert_eq!(layer.tile(0, 2), None);
+    assert_eq!(layer.tile(-3, 0), None);
+}
+
+#[test]
+fn groups_are_flattened() {
+    let map = MapData::parse(&fixture("groups.tmj")).expect("Failed to parse the map");
+    let names: Vec<_> = map.layers.iter().map(MapLayer::name).collect();
+    assert_eq!(names, ["top", "spawns", "deco"]);
+
+    let spawns = map.object_layer("spawns").unwrap();
+    assert_eq!((spawns.offset.x, spawns.offset.y), (10.0, 6.0));
+    assert_eq!(spawns.opacity, 0.25);
+    assert!(!spawns.visible);
+
+    let deco = map.tile_layer("deco").unwrap();
+    assert_eq!((deco.offset.x, deco.offset.y), (8.0, 4.0));
+    assert_eq!(deco.opacity, 0.5);
+    assert!(deco.visible);
+
+    let slime = map.objects_of_kind("enemy").next().expect("No enemies");
+    assert_eq!(slime.id, 7);
+    assert_eq!(slime.shape, ObjectShape::Point);
+    assert_eq!(slime.property("health"), Some(&Property::Int(3)));
+    assert_eq!(map.object(7).map(|o| o.name.as_str()), Some("slime"));
+}
+
+#[test]
+fn external_tilesets() {
+    let mut map = MapData::parse(&fixture("external.tmx")).expect("Failed to parse the map");
+    assert_eq!(map.tilesets[1].source.as_deref(), Some("tilesets/tiles.tsx"));
+    assert_eq!(map.tilesets[1].first_gid, 3);
+
+    let external = MapTileset::parse(&fixture("tilesets/tiles.tsx")).expect("Failed to parse the tileset");
+    map.tilesets[1].merge_external(external);
+
+    let tileset = &map.tilesets[1];
+    assert_eq!(tileset.source, None);
+    assert_eq!(tileset.first_gid, 3);
+    assert_eq!(tileset.name, "tiles");
+    assert_eq!((tileset.tile_count, tileset.columns), (4, 2));
+    assert_eq!(tileset.image, "tilesets/../images/tiles.png");
+
+    let ground = map.tile_layer("ground").unwrap();
+    assert_eq!(map.tileset_for(ground.tile(0, 0).unwrap()), Some((1, 0)));
+    assert_eq!(map.tileset_for(ground.tile(1, 0).unwrap()), Some((1, 3)));
+    assert_eq!(map.tileset_for(2), Some((0, 1)));
+
+    let water = map.tile_info(3).expect("No tile info");
+    assert_eq!(water.kind, "water");
+    assert_eq!(water.properties.get("solid"), Some(&Property::Bool(true)));
+    assert_eq!(water.frame_at(0.05), Some(0));
+    assert_eq!(water.frame_at(0.2), Some(1));
+    assert_eq!(water.frame_at(0.45), Some(0));
+}